@@ -2,12 +2,22 @@
 //! Module containing the application itself.
 //!
 
+use crate::assembly::*;
+use crate::cache::*;
 use crate::camera::*;
+use crate::crystal::*;
 use crate::grid::*;
-use crate::pipelines::{raymarch::*, render::*, ssao::*};
-use lib3dmol::structures::GetAtom;
+use crate::loaders::LoadError;
+use crate::loading::*;
+use crate::molecule::*;
+use crate::patches::*;
+use crate::pipelines::{grid::*, raymarch::*, render::*, ssao::*};
+use crate::radii::*;
+use crate::scene::*;
+use crate::selection::*;
 use nalgebra_glm as glm;
 use std::convert::TryInto;
+use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 use wgpu;
 
@@ -30,8 +40,42 @@ pub struct Application {
     /// Holds information whether camera was changed between frames. The information is used for accumulation of result.
     pub camera_changed: bool,
 
+    /// Molecules shown together in the scene. Empty while the initial test scene is shown.
+    molecules: Vec<SceneMolecule>,
+    /// Index of the molecule the options of frames, assemblies and crystals apply to.
+    active: usize,
+    /// Whether dropped structure files are added to the scene instead of replacing the active molecule.
+    add_dropped_files: bool,
+    /// Policy deciding which alternate conformers of atoms are loaded.
+    alt_loc_policy: AltLocPolicy,
+    /// Structure files being loaded in the background. The current molecules are shown until they finish.
+    loading: Vec<LoadingTask>,
+    /// Last error shown to the user, e.g. a file that could not be loaded.
+    error: Option<String>,
+    /// Warnings shown to the user until they are dismissed, e.g. atoms not matched by the radii table.
+    warnings: Vec<String>,
+    /// Whether the frames of the active molecule are advanced automatically.
+    playing: bool,
+    /// Value of `RaymarchGlobals::time` when the current frame was shown.
+    frame_start_time: f32,
+    /// Playback speed of trajectories.
+    frames_per_second: f32,
+    /// Whether symmetry copies of the asymmetric unit of crystal structures are shown.
+    show_symmetry_mates: bool,
+    /// Distance from the asymmetric unit within which symmetry mates are shown.
+    symmetry_radius: f32,
+    /// User-supplied radii overriding the element radii.
+    radii_table: Option<RadiiTable>,
+    /// Selection of atoms the surfaces are computed for. `None` selects all atoms.
+    selection: Option<Selection>,
+
     /// Voxel grid containing atoms of the molecule.
     voxel_grid: VoxelGrid,
+    /// Way `voxel_grid` is built.
+    grid_builder: GridBuilder,
+    /// Cache of voxel grids of large scenes. Grids are read from and stored in It only when structures are loaded. `None` if
+    /// grids are always built.
+    grid_cache: Option<GridCache>,
 
     /// Global variables for ray marching passed to GPU.
     raymarch_globals: RaymarchGlobals,
@@ -50,6 +94,9 @@ pub struct Application {
     /// Pipeline that adds SSAO to the sphere marched result.
     ssao_pipeline: SsaoPipeline,
 
+    /// Pipelines that build the voxel grid on the GPU.
+    grid_pipeline: GridPipeline,
+
     gbuffer_positions: wgpu::TextureView,
     gbuffer_normals: wgpu::TextureView,
     output_texture: wgpu::TextureView,
@@ -96,19 +143,20 @@ impl Application {
         let raymarch_pipeline = RaymarchPipeline::new(&device);
         let render_pipeline = RenderPipeline::new(&device);
         let ssao_pipeline = SsaoPipeline::new(&device);
+        let grid_pipeline = GridPipeline::new(&device);
 
         //
         // Globals
         //
         let start_time = SystemTime::now();
 
+        let solvent_radius = 0.71590906;
         let voxel_grid = {
-            let mut atoms = Vec::new();
-            atoms.push(glm::vec4(1.5, 0.0, 0.0, 1.0));
-            atoms.push(glm::vec4(-1.5, 0.0, 0.0, 1.0));
-            atoms.push(glm::vec4(0.0, 2.5, 0.0, 1.0));
-
-            VoxelGrid::new(&device, 2.0, atoms)
+            let scene = SceneAtoms::test_scene();
+            let mut voxel_grid = VoxelGrid::new(&device, solvent_radius, scene.atoms.clone(), scene.colors, scene.surfaces);
+            let patches = PatchLists::new(&voxel_grid.layout(), &scene.atoms, solvent_radius).expect("Three atoms have few patches");
+            voxel_grid.set_patches(&device, &patches);
+            voxel_grid
         };
 
         let camera = RotationCamera::new(0.5 * glm::distance(&glm::vec3(0.0, 0.0, 0.0), &voxel_grid.bb_diff));
@@ -123,11 +171,11 @@ impl Application {
             bb_diff: voxel_grid.bb_diff.into(),
             bb_size: voxel_grid.bb_size.into(),
             voxel_length: voxel_grid.voxel_length,
-            solvent_radius: 0.71590906,
-            max_neighbours: 15,
+            solvent_radius,
             time: 0.0,
             save: 0,
             max_steps: 8,
+            patch_table_size: voxel_grid.patches.keys_len as i32,
             ..Default::default()
         };
         let raymarch_globals_buffer = device.create_buffer_with_data(
@@ -238,7 +286,24 @@ impl Application {
             camera,
             camera_changed: true,
 
+            molecules: Vec::new(),
+            active: 0,
+            add_dropped_files: false,
+            alt_loc_policy: AltLocPolicy::HighestOccupancy,
+            loading: Vec::new(),
+            error: None,
+            warnings: Vec::new(),
+            playing: false,
+            frame_start_time: 0.0,
+            frames_per_second: 10.0,
+            show_symmetry_mates: false,
+            symmetry_radius: 5.0,
+            radii_table: None,
+            selection: None,
+
             voxel_grid,
+            grid_builder: GridBuilder::Gpu,
+            grid_cache: Some(GridCache::new(GridCache::default_directory())),
 
             raymarch_globals,
             raymarch_globals_buffer,
@@ -248,6 +313,8 @@ impl Application {
             render_pipeline,
             ssao_pipeline,
 
+            grid_pipeline,
+
             gbuffer_positions,
             gbuffer_normals,
             output_texture,
@@ -357,6 +424,13 @@ impl Application {
         {
             let now = SystemTime::now();
             self.raymarch_globals.time = now.duration_since(self.start_time).expect("Time went backwards").as_secs_f32();
+            self.poll_loading();
+            self.advance_playback();
+            // Grids are rebuilt whenever the solvent radius or the atoms need longer voxels
+            debug_assert!(
+                self.scene_is_empty() || self.voxel_grid.supports(self.raymarch_globals.solvent_radius),
+                "Voxels are too short for the solvent radius, patches of the surface would be lost"
+            );
 
             if self.camera_changed {
                 let eye = self.camera.distance * self.camera.direction_vector();
@@ -440,6 +514,55 @@ impl Application {
                     binding: 6,
                     resource: wgpu::BindingResource::TextureView(&self.gbuffer_normals),
                 },
+                wgpu::Binding {
+                    binding: 7,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &self.voxel_grid.colors,
+                        range: 0..(self.voxel_grid.colors_len * std::mem::size_of::<f32>()) as u64,
+                    },
+                },
+                wgpu::Binding {
+                    binding: 8,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &self.voxel_grid.surfaces,
+                        range: 0..(self.voxel_grid.surfaces_len * std::mem::size_of::<u32>()) as u64,
+                    },
+                },
+                wgpu::Binding {
+                    binding: 9,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &self.voxel_grid.hash_keys,
+                        range: 0..(self.voxel_grid.hash_keys_len.max(1) * std::mem::size_of::<u32>()) as u64,
+                    },
+                },
+                wgpu::Binding {
+                    binding: 10,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &self.voxel_grid.atom_slots,
+                        range: 0..(self.voxel_grid.atom_slots_len.max(1) * std::mem::size_of::<u32>()) as u64,
+                    },
+                },
+                wgpu::Binding {
+                    binding: 11,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &self.voxel_grid.patches.keys,
+                        range: 0..(self.voxel_grid.patches.keys_len * std::mem::size_of::<u32>()) as u64,
+                    },
+                },
+                wgpu::Binding {
+                    binding: 12,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &self.voxel_grid.patches.pointers,
+                        range: 0..(self.voxel_grid.patches.keys_len * std::mem::size_of::<PatchPointer>()) as u64,
+                    },
+                },
+                wgpu::Binding {
+                    binding: 13,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &self.voxel_grid.patches.atoms,
+                        range: 0..(self.voxel_grid.patches.atoms_len * std::mem::size_of::<u32>()) as u64,
+                    },
+                },
             ],
         });
 
@@ -550,20 +673,16 @@ impl Application {
                 self.mouse_position = *position;
             }
             winit::event::WindowEvent::DroppedFile(file_path) => {
-                let molecule_structure = lib3dmol::parser::read_pdb(file_path.to_str().unwrap(), "");
-                let mut atoms = Vec::new();
-                for atom in molecule_structure.get_atom() {
-                    atoms.push(glm::vec4(atom.coord[0], atom.coord[1], atom.coord[2], 0.0));
+                if file_path.extension() == Some(std::ffi::OsStr::new("radii")) {
+                    match RadiiTable::from_file(file_path) {
+                        Ok(radii_table) => self.set_radii_table(Some(radii_table)),
+                        Err(e) => self.set_error(Some(format!("Could not load radii table {}", e))),
+                    }
+                } else if self.add_dropped_files {
+                    self.add_molecule(file_path.clone());
+                } else {
+                    self.load_molecule(file_path.clone());
                 }
-
-                self.voxel_grid = VoxelGrid::new(&self.device, 1.0, atoms);
-                self.raymarch_globals.bb_min = self.voxel_grid.bb_min.into();
-                self.raymarch_globals.bb_max = self.voxel_grid.bb_max.into();
-                self.raymarch_globals.bb_diff = self.voxel_grid.bb_diff.into();
-                self.raymarch_globals.bb_size = self.voxel_grid.bb_size.into();
-                self.raymarch_globals.voxel_length = self.voxel_grid.voxel_length;
-
-                self.camera_changed = true;
             }
             _ => {}
         };
@@ -596,6 +715,424 @@ impl Application {
         &self.queue
     }
 
+    ///
+    /// Assigns radii to the atoms of all molecules and reports atoms not matched by the radii table.
+    ///
+    fn update_radii(&mut self) {
+        for index in 0..self.molecules.len() {
+            let unmatched = assign_radii(&mut self.molecules[index].molecule.atoms, self.radii_table.as_ref());
+            self.report_unmatched_atoms(index, &unmatched);
+        }
+    }
+
+    ///
+    /// Warns about atoms of a molecule that were not matched by the radii table. The first few of them are named.
+    ///
+    fn report_unmatched_atoms(&mut self, index: usize, unmatched: &[usize]) {
+        const NAMED_ATOMS: usize = 5;
+
+        self.molecules[index].unmatched_atoms = unmatched.len();
+        let radii_table = match self.radii_table.as_ref() {
+            Some(radii_table) if !unmatched.is_empty() => radii_table,
+            _ => return,
+        };
+
+        let molecule = &self.molecules[index];
+        let mut names: Vec<String> = unmatched
+            .iter()
+            .take(NAMED_ATOMS)
+            .map(|&index| {
+                let atom = &molecule.molecule.atoms[index];
+                format!("{} {} {} (chain {})", atom.residue_name, atom.residue_id, atom.name, atom.chain)
+            })
+            .collect();
+        if unmatched.len() > NAMED_ATOMS {
+            names.push("...".to_string());
+        }
+
+        let warning = format!(
+            "{} atoms of {} are not matched by radii table {}, element radii are used for them: {}",
+            unmatched.len(),
+            molecule.name(),
+            radii_table.name,
+            names.join(", ")
+        );
+        self.add_warning(warning);
+    }
+
+    ///
+    /// Starts loading a structure file in the background that replaces the active molecule, or that is added to an empty
+    /// scene. The current molecule is shown until the file is loaded. Errors are shown to the user and the current molecule
+    /// is kept.
+    ///
+    pub fn load_molecule(&mut self, path: PathBuf) {
+        if self.molecules.is_empty() {
+            self.loading.clear();
+            self.spawn_loading(path, None);
+        } else {
+            self.spawn_loading(path, Some(self.active));
+        }
+    }
+
+    ///
+    /// Starts loading a structure file in the background that is added to the scene.
+    ///
+    pub fn add_molecule(&mut self, path: PathBuf) {
+        self.spawn_loading(path, None);
+    }
+
+    ///
+    /// Starts loading a structure file that replaces the `target` molecule or that is added if `target` is `None`.
+    /// Loading of another file replacing the same molecule is abandoned.
+    ///
+    fn spawn_loading(&mut self, path: PathBuf, target: Option<usize>) {
+        if target.is_some() {
+            self.loading.retain(|loading| loading.target != target);
+        }
+
+        let request = LoadRequest {
+            path,
+            alt_loc_policy: self.alt_loc_policy,
+            radii_table: self.radii_table.clone(),
+            selection: self.selection.clone(),
+            symmetry_radius: Some(self.symmetry_radius).filter(|_| self.show_symmetry_mates),
+            // Validated grids are built on the GPU, so they are left to the main thread
+            scene: Some(self.scene_request(target)).filter(|_| self.grid_builder != GridBuilder::Validate),
+        };
+        self.loading.push(LoadingTask::spawn(request, target));
+    }
+
+    ///
+    /// Describes the scene a molecule loaded for `target` is put into if the scene does not change during the loading.
+    ///
+    fn scene_request(&self, target: Option<usize>) -> SceneRequest {
+        let index = target
+            .filter(|&target| target < self.molecules.len())
+            .unwrap_or(self.molecules.len());
+        let replaced = self.molecules.get(index);
+
+        let mut before = SceneAtoms::default();
+        let mut after = SceneAtoms::default();
+        for (other, molecule) in self.molecules.iter().enumerate() {
+            if other == index || !molecule.visible {
+                continue;
+            }
+
+            let atoms = molecule.placed_atoms(other as u32);
+            if other < index {
+                before.extend(atoms);
+            } else {
+                after.extend(atoms);
+            }
+        }
+
+        SceneRequest {
+            before,
+            after,
+            index,
+            visible: replaced.map_or(true, |replaced| replaced.visible),
+            color: replaced.map_or_else(
+                || glm::Vec4::from(MOLECULE_COLORS[self.molecules.len() % MOLECULE_COLORS.len()].1),
+                |replaced| replaced.color,
+            ),
+            translation: replaced.map_or_else(|| glm::vec3(0.0, 0.0, 0.0), |replaced| replaced.translation),
+            rotation: replaced.map_or_else(|| glm::vec3(0.0, 0.0, 0.0), |replaced| replaced.rotation),
+            solvent_radius: self.raymarch_globals.solvent_radius,
+            cache: self.grid_cache.clone(),
+        }
+    }
+
+    ///
+    /// Swaps in the molecules and the voxel grid once the background loading finished.
+    ///
+    fn poll_loading(&mut self) {
+        let mut index = 0;
+        while index < self.loading.len() {
+            match self.loading[index].poll() {
+                Some(result) => {
+                    let loading = self.loading.remove(index);
+                    self.finish_loading(loading, result);
+                }
+                None => index += 1,
+            }
+        }
+    }
+
+    ///
+    /// Puts a loaded molecule into the scene and makes It active. A replaced molecule passes Its placement and color to It.
+    /// The voxel grid built by the worker thread is uploaded if the scene turned out as expected, otherwise It is rebuilt.
+    ///
+    fn finish_loading(&mut self, loading: LoadingTask, result: Result<LoadedMolecule, LoadError>) {
+        let mut loaded = match result {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                self.set_error(Some(format!("Could not load {}: {}", loading.path().display(), e)));
+                return;
+            }
+        };
+
+        let color = glm::Vec4::from(MOLECULE_COLORS[self.molecules.len() % MOLECULE_COLORS.len()].1);
+        let mut molecule = SceneMolecule::new(loading.path().to_path_buf(), loaded.molecule, color, loaded.scene);
+        let index = match loading.target.filter(|&target| target < self.molecules.len()) {
+            Some(target) => {
+                let replaced = &self.molecules[target];
+                molecule.visible = replaced.visible;
+                molecule.color = replaced.color;
+                molecule.translation = replaced.translation;
+                molecule.rotation = replaced.rotation;
+
+                self.molecules[target] = molecule;
+                target
+            }
+            None => {
+                self.molecules.push(molecule);
+                self.molecules.len() - 1
+            }
+        };
+        self.active = index;
+        self.error = None;
+        self.playing = false;
+
+        let name = self.molecules[index].name();
+        for warning in std::mem::take(&mut self.molecules[index].molecule.warnings) {
+            self.add_warning(format!("{}: {}", name, warning));
+        }
+
+        if loading.outdated {
+            let unmatched = assign_radii(&mut self.molecules[index].molecule.atoms, self.radii_table.as_ref());
+            self.report_unmatched_atoms(index, &unmatched);
+            self.collect_atoms(index);
+        } else {
+            self.report_unmatched_atoms(index, &loaded.unmatched_atoms);
+        }
+
+        if let Some(cache_error) = loaded.grid.as_mut().and_then(|prepared| prepared.cache_error.take()) {
+            self.add_warning(cache_error);
+        }
+
+        let scene = self.scene();
+        let solvent_radius = self.raymarch_globals.solvent_radius;
+        match loaded
+            .grid
+            .filter(|prepared| prepared.solvent_radius == solvent_radius && prepared.scene == scene)
+        {
+            Some(prepared) => {
+                let voxel_grid = prepared
+                    .grid
+                    .map(|(data, patches)| VoxelGrid::upload(&self.device, &data, &patches));
+                self.set_voxel_grid(voxel_grid);
+            }
+            None => self.set_scene_atoms(scene),
+        }
+    }
+
+    ///
+    /// Marks the atoms prepared by the background loading as outdated because an option they depend on changed.
+    ///
+    fn outdate_loading(&mut self) {
+        for loading in self.loading.iter_mut() {
+            loading.outdated = true;
+        }
+    }
+
+    ///
+    /// Collects atoms of a molecule again from the selected atoms in Its current frame or from Its overlaid models.
+    ///
+    fn collect_atoms(&mut self, index: usize) {
+        let symmetry_radius = Some(self.symmetry_radius).filter(|_| self.show_symmetry_mates);
+        self.molecules[index].collect_atoms(self.selection.as_ref(), symmetry_radius);
+    }
+
+    ///
+    /// Collects atoms of all molecules again and rebuilds the voxel grid. The initial test scene is kept if there are
+    /// no molecules.
+    ///
+    fn update_voxel_grid(&mut self) {
+        if self.molecules.is_empty() {
+            return;
+        }
+
+        for index in 0..self.molecules.len() {
+            self.collect_atoms(index);
+        }
+        self.update_scene();
+    }
+
+    ///
+    /// Collects atoms of the active molecule again and rebuilds the voxel grid.
+    ///
+    fn update_active_molecule(&mut self) {
+        if self.molecules.is_empty() {
+            return;
+        }
+
+        self.collect_atoms(self.active);
+        self.update_scene();
+    }
+
+    ///
+    /// Rebuilds the voxel grid from the collected atoms of the visible molecules, or from the initial test scene if
+    /// there are no molecules.
+    ///
+    fn update_scene(&mut self) {
+        let scene = self.scene();
+        self.set_scene_atoms(scene);
+    }
+
+    ///
+    /// Returns the collected atoms of the visible molecules, or the initial test scene if there are no molecules.
+    ///
+    fn scene(&self) -> SceneAtoms {
+        if self.molecules.is_empty() {
+            SceneAtoms::test_scene()
+        } else {
+            SceneAtoms::combine(&self.molecules)
+        }
+    }
+
+    ///
+    /// Moves the atoms of the voxel grid to the collected atoms of the visible molecules. The voxel grid is rebuilt
+    /// instead if the atoms leave Its bounding box or differ in number, colors or surfaces.
+    ///
+    fn move_scene(&mut self) {
+        let scene = self.scene();
+        if scene.atoms.is_empty() || self.scene_is_empty() {
+            self.set_scene_atoms(scene);
+            return;
+        }
+
+        let solvent_radius = self.raymarch_globals.solvent_radius;
+        let moved = self.voxel_grid.update_atoms(
+            &self.device,
+            &self.queue,
+            solvent_radius,
+            &scene.atoms,
+            &scene.colors,
+            &scene.surfaces,
+        );
+        if moved {
+            self.camera_changed = true;
+            self.raymarch_globals.hash_table_size = self.voxel_grid.hash_keys_len as i32;
+            self.raymarch_globals.patch_table_size = self.voxel_grid.patches.keys_len as i32;
+        } else {
+            self.set_scene_atoms(scene);
+        }
+    }
+
+    ///
+    /// Replaces the voxel grid and the bounding box of the scene by the given atoms. The voxels are sized for the current
+    /// solvent radius. Empty scenes keep the current grid, but collapse the bounding box so that nothing is hit by rays.
+    /// Scenes of which no grid can be built are treated as empty after reporting the error.
+    ///
+    fn set_scene_atoms(&mut self, scene: SceneAtoms) {
+        self.camera_changed = true;
+        if scene.atoms.is_empty() {
+            self.collapse_scene();
+            return;
+        }
+
+        let solvent_radius = self.raymarch_globals.solvent_radius;
+        let voxel_grid = VoxelGridData::validate(solvent_radius, &scene.atoms).and_then(|_| self.build_grid(scene));
+        self.set_voxel_grid(voxel_grid);
+    }
+
+    ///
+    /// Replaces the voxel grid and the bounding box of the scene by the built grid. If the grid could not be built, the
+    /// error is reported and the scene is treated as empty.
+    ///
+    fn set_voxel_grid(&mut self, voxel_grid: Result<VoxelGrid, String>) {
+        self.camera_changed = true;
+        self.voxel_grid = match voxel_grid {
+            Ok(voxel_grid) => voxel_grid,
+            Err(e) => {
+                self.set_error(Some(format!("Could not build the voxel grid: {}", e)));
+                self.collapse_scene();
+                return;
+            }
+        };
+        self.raymarch_globals.bb_min = self.voxel_grid.bb_min.into();
+        self.raymarch_globals.bb_max = self.voxel_grid.bb_max.into();
+        self.raymarch_globals.bb_diff = self.voxel_grid.bb_diff.into();
+        self.raymarch_globals.bb_size = self.voxel_grid.bb_size.into();
+        self.raymarch_globals.voxel_length = self.voxel_grid.voxel_length;
+        self.raymarch_globals.hash_table_size = self.voxel_grid.hash_keys_len as i32;
+        self.raymarch_globals.patch_table_size = self.voxel_grid.patches.keys_len as i32;
+    }
+
+    ///
+    /// Builds the voxel grid of atoms of the scene in the way chosen by `grid_builder`. Patches of the surface are found
+    /// once on the CPU in every way. Returns an error if they take too much memory.
+    ///
+    fn build_grid(&self, scene: SceneAtoms) -> Result<VoxelGrid, String> {
+        let solvent_radius = self.raymarch_globals.solvent_radius;
+        let patches = PatchLists::new(&VoxelGridData::layout(solvent_radius, &scene.atoms), &scene.atoms, solvent_radius)?;
+        let mut voxel_grid = match self.grid_builder {
+            GridBuilder::Cpu => VoxelGrid::new(&self.device, solvent_radius, scene.atoms, scene.colors, scene.surfaces),
+            GridBuilder::Gpu => VoxelGrid::build(
+                &self.device,
+                &self.queue,
+                &self.grid_pipeline,
+                solvent_radius,
+                &scene.atoms,
+                &scene.colors,
+                &scene.surfaces,
+            ),
+            GridBuilder::Validate => {
+                let voxel_grid = VoxelGrid::build(
+                    &self.device,
+                    &self.queue,
+                    &self.grid_pipeline,
+                    solvent_radius,
+                    &scene.atoms,
+                    &scene.colors,
+                    &scene.surfaces,
+                );
+                let built = futures::executor::block_on(voxel_grid.download(&self.device, &self.queue));
+                let expected = VoxelGridData::new(solvent_radius, scene.atoms, scene.colors, scene.surfaces);
+                match built.difference(&expected) {
+                    Some(difference) => eprintln!("Voxel grid built on the GPU differs from the CPU one: {}", difference),
+                    None => eprintln!("Voxel grid built on the GPU matches the CPU one"),
+                }
+
+                voxel_grid
+            }
+        };
+        voxel_grid.set_patches(&self.device, &patches);
+
+        Ok(voxel_grid)
+    }
+
+    ///
+    /// Collapses the bounding box of the scene, so that nothing is hit by rays.
+    ///
+    fn collapse_scene(&mut self) {
+        self.raymarch_globals.bb_min = [0.0; 3];
+        self.raymarch_globals.bb_max = [0.0; 3];
+    }
+
+    ///
+    /// Returns `true` if the bounding box of the scene is collapsed because there are no atoms to show.
+    ///
+    fn scene_is_empty(&self) -> bool {
+        self.raymarch_globals.bb_min == self.raymarch_globals.bb_max
+    }
+
+    ///
+    /// Advances the trajectory playback of the active molecule according to `RaymarchGlobals::time`.
+    /// Every frame is shown, so the playback slows down if building the voxel grid takes longer than one frame.
+    ///
+    fn advance_playback(&mut self) {
+        let frame_count = self.frame_count();
+        if !self.playing || frame_count < 2 {
+            return;
+        }
+
+        if self.raymarch_globals.time - self.frame_start_time >= 1.0 / self.frames_per_second {
+            self.frame_start_time = self.raymarch_globals.time;
+            self.set_frame((self.frame() + 1) % frame_count);
+        }
+    }
+
     fn update_raymarch_globals(&mut self) {
         self.raymarch_globals_buffer = self.device.create_buffer_with_data(
             bytemuck::cast_slice(&[self.raymarch_globals]),
@@ -607,29 +1144,426 @@ impl Application {
         self.raymarch_globals.solvent_radius
     }
 
+    ///
+    /// Sets the solvent radius. The voxel grid is rebuilt with longer voxels if the current ones are too short for It,
+    /// otherwise only Its patches are found again.
+    ///
     pub fn set_solvent_radius(&mut self, solvent_radius: f32) {
         self.raymarch_globals.solvent_radius = solvent_radius;
+        if !self.voxel_grid.supports(solvent_radius) || self.scene_is_empty() {
+            self.update_scene();
+        } else {
+            let scene = self.scene();
+            match PatchLists::new(&self.voxel_grid.layout(), &scene.atoms, solvent_radius) {
+                Ok(patches) => {
+                    self.voxel_grid.set_patches(&self.device, &patches);
+                    self.raymarch_globals.patch_table_size = self.voxel_grid.patches.keys_len as i32;
+                }
+                Err(e) => {
+                    self.set_error(Some(format!("Could not find the patches of the surface: {}", e)));
+                    self.collapse_scene();
+                }
+            }
+        }
         self.update_raymarch_globals();
         self.camera_changed = true;
     }
 
-    pub fn max_neighbours(&self) -> i32 {
-        self.raymarch_globals.max_neighbours
+    pub fn max_steps(&self) -> i32 {
+        self.raymarch_globals.max_steps
     }
 
-    pub fn set_max_neighbours(&mut self, max_neighbours: i32) {
-        self.raymarch_globals.max_neighbours = max_neighbours;
+    pub fn set_max_steps(&mut self, max_steps: i32) {
+        self.raymarch_globals.max_steps = max_steps;
         self.update_raymarch_globals();
         self.camera_changed = true;
     }
 
-    pub fn max_steps(&self) -> i32 {
-        self.raymarch_globals.max_steps
+    pub fn separate_surfaces(&self) -> bool {
+        self.raymarch_globals.separate_surfaces != 0
     }
 
-    pub fn set_max_steps(&mut self, max_steps: i32) {
-        self.raymarch_globals.max_steps = max_steps;
+    ///
+    /// Computes a separate surface for every molecule instead of blending surfaces of neighbouring molecules together.
+    ///
+    pub fn set_separate_surfaces(&mut self, separate_surfaces: bool) {
+        self.raymarch_globals.separate_surfaces = separate_surfaces as i32;
         self.update_raymarch_globals();
         self.camera_changed = true;
     }
+
+    ///
+    /// Chooses how the voxel grid is built and builds It again.
+    ///
+    pub fn set_grid_builder(&mut self, grid_builder: GridBuilder) {
+        self.grid_builder = grid_builder;
+        self.update_scene();
+    }
+
+    ///
+    /// Sets the cache voxel grids of large scenes are reused from. `None` disables the cache.
+    ///
+    pub fn set_grid_cache(&mut self, grid_cache: Option<GridCache>) {
+        self.grid_cache = grid_cache;
+    }
+
+    pub fn radii_table(&self) -> Option<&RadiiTable> {
+        self.radii_table.as_ref()
+    }
+
+    ///
+    /// Sets user-supplied radii. `None` switches back to the element radii.
+    ///
+    pub fn set_radii_table(&mut self, radii_table: Option<RadiiTable>) {
+        self.radii_table = radii_table;
+        self.outdate_loading();
+        self.update_radii();
+        self.update_voxel_grid();
+    }
+
+    ///
+    /// Returns `true` if the structure file of the active molecule provided Its own radii, e.g. PQR.
+    ///
+    pub fn has_file_radii(&self) -> bool {
+        self.active().map_or(false, |molecule| {
+            molecule.molecule.atoms.iter().any(|atom| atom.file_radius.is_some())
+        })
+    }
+
+    ///
+    /// Returns the number of atoms of all molecules that were not matched by the radii table.
+    ///
+    pub fn unmatched_atoms(&self) -> usize {
+        self.molecules.iter().map(|molecule| molecule.unmatched_atoms).sum()
+    }
+
+    pub fn molecules(&self) -> &[SceneMolecule] {
+        &self.molecules
+    }
+
+    fn active(&self) -> Option<&SceneMolecule> {
+        self.molecules.get(self.active)
+    }
+
+    ///
+    /// Returns the centre of the view, around which the camera rotates, in the coordinates of the structure file of the
+    /// active molecule. Returns `None` if no molecule is shown.
+    ///
+    pub fn view_centre(&self) -> Option<glm::Vec3> {
+        if self.scene_is_empty() {
+            return None;
+        }
+
+        let molecule = self.active()?;
+        Some(molecule.to_structure(&self.voxel_grid.to_scene(&glm::vec3(0.0, 0.0, 0.0))))
+    }
+
+    ///
+    /// Returns the index of the molecule the options of frames, assemblies and crystals apply to.
+    ///
+    pub fn active_molecule(&self) -> usize {
+        self.active
+    }
+
+    pub fn set_active_molecule(&mut self, active: usize) {
+        if active < self.molecules.len() {
+            self.active = active;
+            self.playing = false;
+        }
+    }
+
+    ///
+    /// Removes a molecule from the scene. Loading of a file replacing It is abandoned.
+    ///
+    pub fn remove_molecule(&mut self, index: usize) {
+        if index >= self.molecules.len() {
+            return;
+        }
+
+        self.molecules.remove(index);
+        self.loading.retain(|loading| loading.target != Some(index));
+        for loading in self.loading.iter_mut() {
+            loading.target = loading.target.map(|target| if target > index { target - 1 } else { target });
+        }
+        if self.active > index || self.active == self.molecules.len() {
+            self.active = self.active.saturating_sub(1);
+        }
+        self.playing = false;
+
+        self.update_scene();
+    }
+
+    pub fn set_molecule_visible(&mut self, index: usize, visible: bool) {
+        if let Some(molecule) = self.molecules.get_mut(index) {
+            molecule.visible = visible;
+            self.update_scene();
+        }
+    }
+
+    pub fn set_molecule_color(&mut self, index: usize, color: glm::Vec4) {
+        if let Some(molecule) = self.molecules.get_mut(index) {
+            molecule.color = color;
+            self.update_scene();
+        }
+    }
+
+    ///
+    /// Places a molecule in the scene. The rotation in degrees is applied around the centre of the molecule.
+    ///
+    pub fn set_molecule_transform(&mut self, index: usize, translation: glm::Vec3, rotation: glm::Vec3) {
+        if let Some(molecule) = self.molecules.get_mut(index) {
+            molecule.translation = translation;
+            molecule.rotation = rotation;
+            self.move_scene();
+        }
+    }
+
+    pub fn add_dropped_files(&self) -> bool {
+        self.add_dropped_files
+    }
+
+    ///
+    /// Chooses whether dropped structure files are added to the scene or replace the active molecule.
+    ///
+    pub fn set_add_dropped_files(&mut self, add_dropped_files: bool) {
+        self.add_dropped_files = add_dropped_files;
+    }
+
+    pub fn frame(&self) -> usize {
+        self.active().map_or(0, |molecule| molecule.frame)
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.active().map_or(0, |molecule| molecule.molecule.frame_count())
+    }
+
+    ///
+    /// Shows the given frame of the trajectory of the active molecule. Out of range frames are clamped.
+    ///
+    pub fn set_frame(&mut self, frame: usize) {
+        let frame = frame.min(self.frame_count().saturating_sub(1));
+        if frame == self.frame() {
+            return;
+        }
+
+        let molecule = &mut self.molecules[self.active];
+        molecule.frame = frame;
+        molecule.molecule.set_frame(frame);
+        self.collect_atoms(self.active);
+        self.move_scene();
+    }
+
+    pub fn playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn set_playing(&mut self, playing: bool) {
+        self.playing = playing;
+        self.frame_start_time = self.raymarch_globals.time;
+    }
+
+    ///
+    /// Returns `true` if the frames of the active molecule are models of an ensemble.
+    ///
+    pub fn is_ensemble(&self) -> bool {
+        self.active().map_or(false, |molecule| molecule.molecule.ensemble)
+    }
+
+    pub fn assemblies(&self) -> &[Assembly] {
+        self.active()
+            .map(|molecule| molecule.molecule.assemblies.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn assembly(&self) -> Option<usize> {
+        self.active().and_then(|molecule| molecule.assembly)
+    }
+
+    ///
+    /// Shows the given biological assembly of the active molecule instead of the asymmetric unit.
+    /// `None` shows the asymmetric unit.
+    ///
+    pub fn set_assembly(&mut self, assembly: Option<usize>) {
+        let assembly = assembly.filter(|&assembly| assembly < self.assemblies().len());
+        if assembly == self.assembly() {
+            return;
+        }
+
+        self.molecules[self.active].assembly = assembly;
+        self.update_active_molecule();
+    }
+
+    ///
+    /// Returns the unit cell of the active molecule if It is a crystal structure.
+    ///
+    pub fn crystal(&self) -> Option<&Crystal> {
+        self.active().and_then(|molecule| molecule.molecule.crystal.as_ref())
+    }
+
+    pub fn show_symmetry_mates(&self) -> bool {
+        self.show_symmetry_mates
+    }
+
+    ///
+    /// Shows or hides symmetry mates. They are shown only together with the asymmetric unit, not with assemblies.
+    ///
+    pub fn set_show_symmetry_mates(&mut self, show_symmetry_mates: bool) {
+        self.show_symmetry_mates = show_symmetry_mates;
+        self.outdate_loading();
+        self.update_voxel_grid();
+    }
+
+    pub fn symmetry_radius(&self) -> f32 {
+        self.symmetry_radius
+    }
+
+    ///
+    /// Sets the distance from the asymmetric unit within which symmetry mates are shown.
+    ///
+    pub fn set_symmetry_radius(&mut self, symmetry_radius: f32) {
+        self.symmetry_radius = symmetry_radius;
+        if self.show_symmetry_mates {
+            self.outdate_loading();
+            self.update_voxel_grid();
+        }
+    }
+
+    ///
+    /// Returns the number of symmetry mates shown for the active molecule.
+    ///
+    pub fn symmetry_mates(&self) -> usize {
+        self.active().map_or(0, |molecule| molecule.atoms.symmetry_mates)
+    }
+
+    pub fn is_overlaid(&self, model: usize) -> bool {
+        self.active().map_or(false, |molecule| molecule.overlay.contains(&model))
+    }
+
+    ///
+    /// Adds or removes a model of the ensemble of the active molecule from the overlaid models.
+    ///
+    pub fn set_overlaid(&mut self, model: usize, overlaid: bool) {
+        if model >= self.frame_count() || overlaid == self.is_overlaid(model) {
+            return;
+        }
+
+        let overlay = &mut self.molecules[self.active].overlay;
+        if overlaid {
+            overlay.push(model);
+            overlay.sort();
+        } else {
+            overlay.retain(|&overlaid_model| overlaid_model != model);
+        }
+        self.update_active_molecule();
+    }
+
+    ///
+    /// Returns the structure file being loaded in the background and the loaded fraction of It.
+    /// If several files are being loaded, the one started first is returned.
+    ///
+    pub fn loading(&self) -> Option<(&Path, f32)> {
+        self.loading.first().map(|loading| (loading.path(), loading.progress()))
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    ///
+    /// Shows a warning to the user next to the ones that were not dismissed yet.
+    ///
+    fn add_warning(&mut self, warning: String) {
+        self.warnings.push(warning);
+    }
+
+    pub fn dismiss_warnings(&mut self) {
+        self.warnings.clear();
+    }
+
+    ///
+    /// Shows an error to the user, or hides It if `None` is given. Errors are printed to the console as well.
+    ///
+    pub fn set_error(&mut self, error: Option<String>) {
+        if let Some(error) = error.as_ref() {
+            eprintln!("{}", error);
+        }
+        self.error = error;
+    }
+
+    pub fn alt_loc_policy(&self) -> AltLocPolicy {
+        self.alt_loc_policy
+    }
+
+    ///
+    /// Changes which alternate conformers are loaded. All molecules are reloaded from their structure files and loading
+    /// of files that is in progress is restarted.
+    ///
+    pub fn set_alt_loc_policy(&mut self, alt_loc_policy: AltLocPolicy) {
+        if alt_loc_policy == self.alt_loc_policy {
+            return;
+        }
+
+        self.alt_loc_policy = alt_loc_policy;
+        let mut reloads: Vec<(PathBuf, Option<usize>)> = self
+            .molecules
+            .iter()
+            .enumerate()
+            .map(|(index, molecule)| (molecule.path.clone(), Some(index)))
+            .collect();
+        reloads.extend(self.loading.drain(..).map(|loading| (loading.path().to_path_buf(), loading.target)));
+        for (path, target) in reloads {
+            self.spawn_loading(path, target);
+        }
+    }
+
+    ///
+    /// Returns alternate location indicators present in the structure file of the active molecule.
+    ///
+    pub fn alt_locs(&self) -> &[char] {
+        self.active()
+            .map(|molecule| molecule.molecule.alt_locs.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn discarded_atoms(&self) -> usize {
+        self.active().map_or(0, |molecule| molecule.molecule.discarded_atoms)
+    }
+
+    pub fn selection(&self) -> Option<&Selection> {
+        self.selection.as_ref()
+    }
+
+    ///
+    /// Restricts the surfaces of all molecules to atoms matching the selection. Empty text selects all atoms.
+    /// Returns a description of the problem if the selection is not valid, in which case the current selection is kept.
+    ///
+    pub fn set_selection(&mut self, text: &str) -> Result<(), String> {
+        self.selection = if text.trim().is_empty() {
+            None
+        } else {
+            Some(Selection::parse(text)?)
+        };
+        self.outdate_loading();
+        self.update_voxel_grid();
+
+        Ok(())
+    }
+
+    ///
+    /// Returns the number of atoms of all molecules matching the selection.
+    ///
+    pub fn selected_atoms(&self) -> usize {
+        self.molecules.iter().map(|molecule| molecule.atoms.selected_atoms).sum()
+    }
+
+    ///
+    /// Returns the number of atoms of all molecules.
+    ///
+    pub fn atom_count(&self) -> usize {
+        self.molecules.iter().map(|molecule| molecule.molecule.atoms.len()).sum()
+    }
 }
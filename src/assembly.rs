@@ -0,0 +1,52 @@
+//!
+//! Module containing biological assemblies, i.e. the functional molecules built from copies of the asymmetric unit.
+//!
+
+use crate::molecule::Atom;
+use nalgebra_glm as glm;
+
+///
+/// Transforms applied to a group of chains of the asymmetric unit.
+///
+#[derive(Clone, Debug)]
+pub struct AssemblyPart {
+    /// Chains the transforms are applied to.
+    pub chains: Vec<String>,
+    /// Transforms in homogeneous coordinates. Every transform creates one copy of the chains.
+    pub transforms: Vec<glm::Mat4>,
+}
+
+///
+/// Biological assembly given by `REMARK 350 BIOMT` records of PDB files or `_pdbx_struct_assembly_gen` of mmCIF files.
+///
+#[derive(Clone, Debug)]
+pub struct Assembly {
+    /// Name of the assembly shown to the user, e.g. `1 (dimeric)`.
+    pub name: String,
+    /// Groups of chains with their transforms.
+    pub parts: Vec<AssemblyPart>,
+}
+
+impl Assembly {
+    ///
+    /// Builds the assembly from atoms of the asymmetric unit.
+    /// Atoms of chains that are not part of the assembly are left out.
+    ///
+    pub fn build(&self, atoms: &[Atom]) -> Vec<Atom> {
+        let mut assembly = Vec::new();
+        for part in &self.parts {
+            for transform in &part.transforms {
+                for atom in atoms.iter().filter(|atom| part.chains.contains(&atom.chain)) {
+                    let position = transform * glm::vec4(atom.position.x, atom.position.y, atom.position.z, 1.0);
+
+                    assembly.push(Atom {
+                        position: position.xyz(),
+                        ..atom.clone()
+                    });
+                }
+            }
+        }
+
+        assembly
+    }
+}
@@ -0,0 +1,460 @@
+//!
+//! Module containing the cache of voxel grids built for large structures. Every grid is stored with Its patch lists
+//! in a binary file named by a hash of the scene, so loading the same structure again skips building the grid.
+//!
+
+use crate::grid::*;
+use crate::patches::*;
+use crate::scene::SceneAtoms;
+use nalgebra_glm as glm;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Magic bytes at the start of every cache file.
+const CACHE_MAGIC: &[u8; 8] = b"MOLGRID\0";
+
+/// Version of the format of cache files. Must be increased whenever the format or the way grids or patch lists are
+/// built changes, so that stale files are not reused.
+pub const CACHE_VERSION: u32 = 1;
+
+/// Smallest number of atoms of a scene whose grid is cached. Grids of smaller scenes are built faster than read.
+pub const CACHE_MIN_ATOMS: usize = 10_000;
+
+/// Largest total size of files kept in the cache directory in bytes. The least recently written files are removed
+/// first, but the latest file is always kept.
+pub const MAX_CACHE_SIZE: u64 = 4 << 30;
+
+///
+/// Hash identifying the grid of a scene. Covers everything the grid and the patch lists are built from.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CacheKey(pub u64);
+
+impl CacheKey {
+    ///
+    /// Hashes atoms of the scene with their radii, colors and surfaces and the solvent radius. The radii are hashed
+    /// directly, so grids of the same scene are shared by radii tables assigning the same radii.
+    ///
+    pub fn new(scene: &SceneAtoms, solvent_radius: f32) -> Self {
+        let mut hash = Fnv::new();
+        hash.write(&CACHE_VERSION.to_le_bytes());
+        hash.write(&(scene.atoms.len() as u64).to_le_bytes());
+        for (atom, color) in scene.atoms.iter().zip(scene.colors.iter()) {
+            for value in atom.iter().chain(color.iter()) {
+                hash.write(&value.to_bits().to_le_bytes());
+            }
+        }
+        for surface in scene.surfaces.iter() {
+            hash.write(&surface.to_le_bytes());
+        }
+        hash.write(&solvent_radius.to_bits().to_le_bytes());
+
+        Self(hash.0)
+    }
+}
+
+///
+/// 64-bit FNV-1a hash. Unlike the hashers of the standard library It is stable across runs and Rust versions.
+///
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+///
+/// Directory of cached voxel grids.
+///
+#[derive(Clone, Debug)]
+pub struct GridCache {
+    pub directory: PathBuf,
+}
+
+impl GridCache {
+    pub fn new(directory: PathBuf) -> Self {
+        Self { directory }
+    }
+
+    ///
+    /// Returns the cache directory of the user, or the temporary directory if the user has none.
+    ///
+    pub fn default_directory() -> PathBuf {
+        std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+            .unwrap_or_else(std::env::temp_dir)
+            .join("molecules")
+            .join("grids")
+    }
+
+    fn path(&self, key: CacheKey) -> PathBuf {
+        self.directory.join(format!("{:016x}.grid", key.0))
+    }
+
+    ///
+    /// Reads the grid and the patch lists stored under the key. Files that are missing return `None`. Files of other
+    /// versions, other scenes or that cannot be read are removed and return `None` too.
+    ///
+    pub fn load(&self, key: CacheKey, atom_count: usize, solvent_radius: f32) -> Option<(VoxelGridData, PatchLists)> {
+        let path = self.path(key);
+        let bytes = fs::read(&path).ok()?;
+        let result = read_grid(&bytes, key, atom_count, solvent_radius);
+        if result.is_none() {
+            let _ = fs::remove_file(&path);
+        }
+
+        result
+    }
+
+    ///
+    /// Writes the grid and the patch lists under the key and removes the oldest files above `MAX_CACHE_SIZE`.
+    /// The file is written under a temporary name first, so an interrupted write never leaves a partial file behind.
+    ///
+    pub fn store(&self, key: CacheKey, solvent_radius: f32, data: &VoxelGridData, patches: &PatchLists) -> io::Result<()> {
+        fs::create_dir_all(&self.directory)?;
+
+        // Grids are stored by several threads at once, so every one writes Its own temporary file
+        static TEMPORARY_FILES: AtomicUsize = AtomicUsize::new(0);
+        let path = self.path(key);
+        let temporary = path.with_extension(format!(
+            "{}-{}.tmp",
+            std::process::id(),
+            TEMPORARY_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut file = io::BufWriter::new(fs::File::create(&temporary)?);
+        write_grid(&mut file, key, solvent_radius, data, patches)?;
+        file.flush()?;
+        file.get_ref().sync_all()?;
+        fs::rename(&temporary, &path)?;
+
+        self.evict(MAX_CACHE_SIZE)
+    }
+
+    fn evict(&self, max_size: u64) -> io::Result<()> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            if path.extension() == Some(OsStr::new("grid")) {
+                // Files removed by another thread in the meantime are skipped
+                let metadata = match fs::metadata(&path) {
+                    Ok(metadata) => metadata,
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e),
+                };
+                files.push((metadata.modified()?, metadata.len(), path));
+            }
+        }
+
+        files.sort();
+        let mut size = 0;
+        for (index, (_, length, path)) in files.iter().rev().enumerate() {
+            size += length;
+            if index > 0 && size > max_size {
+                if let Err(e) = fs::remove_file(path) {
+                    if e.kind() != io::ErrorKind::NotFound {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+///
+/// Writes the header identifying the scene followed by the grid and the patch lists. Arrays are prefixed by their
+/// lengths and written in the byte order of the machine, which is fine for a cache that never leaves It.
+///
+fn write_grid(writer: &mut impl Write, key: CacheKey, solvent_radius: f32, data: &VoxelGridData, patches: &PatchLists) -> io::Result<()> {
+    writer.write_all(CACHE_MAGIC)?;
+    writer.write_all(&CACHE_VERSION.to_le_bytes())?;
+    writer.write_all(&key.0.to_le_bytes())?;
+    writer.write_all(&(data.atom_slots.len() as u64).to_le_bytes())?;
+    writer.write_all(&solvent_radius.to_le_bytes())?;
+
+    for vector in [data.origin, data.bb_min, data.bb_max, data.bb_diff, data.bb_size].iter() {
+        for value in vector.iter() {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    for value in [data.voxel_length, data.radius_max, data.solvent_radius_max].iter() {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+
+    let mut write_array = |bytes: &[u8]| {
+        writer.write_all(&(bytes.len() as u64).to_le_bytes())?;
+        writer.write_all(bytes)
+    };
+    write_array(bytemuck::cast_slice(&data.voxels))?;
+    write_array(bytemuck::cast_slice(&data.colors))?;
+    write_array(bytemuck::cast_slice(&data.surfaces))?;
+    write_array(bytemuck::cast_slice(&data.voxel_pointers))?;
+    write_array(bytemuck::cast_slice(&data.hash_keys))?;
+    write_array(bytemuck::cast_slice(&data.atom_slots))?;
+    write_array(bytemuck::cast_slice(&patches.keys))?;
+    write_array(bytemuck::cast_slice(&patches.pointers))?;
+    write_array(bytemuck::cast_slice(&patches.atoms))
+}
+
+///
+/// Reads bytes written by `write_grid`. Returns `None` if the header does not match the expected scene or the file
+/// is truncated.
+///
+fn read_grid(bytes: &[u8], key: CacheKey, atom_count: usize, solvent_radius: f32) -> Option<(VoxelGridData, PatchLists)> {
+    let mut reader = Reader(bytes);
+    if reader.take(CACHE_MAGIC.len())? != CACHE_MAGIC
+        || reader.u32()? != CACHE_VERSION
+        || reader.u64()? != key.0
+        || reader.u64()? != atom_count as u64
+        || reader.f32()?.to_bits() != solvent_radius.to_bits()
+    {
+        return None;
+    }
+
+    let mut vectors = [glm::vec3(0.0, 0.0, 0.0); 5];
+    for vector in vectors.iter_mut() {
+        *vector = glm::vec3(reader.f32()?, reader.f32()?, reader.f32()?);
+    }
+    let [origin, bb_min, bb_max, bb_diff, bb_size] = vectors;
+
+    let data = VoxelGridData {
+        origin,
+        bb_min,
+        bb_max,
+        bb_diff,
+        bb_size,
+        voxel_length: reader.f32()?,
+        radius_max: reader.f32()?,
+        solvent_radius_max: reader.f32()?,
+        voxels: reader.array()?,
+        colors: reader.array()?,
+        surfaces: reader.array()?,
+        voxel_pointers: reader.array()?,
+        hash_keys: reader.array()?,
+        atom_slots: reader.array()?,
+    };
+    let patches = PatchLists {
+        keys: reader.array()?,
+        pointers: reader.array()?,
+        atoms: reader.array()?,
+    };
+
+    if !reader.0.is_empty() || data.atom_slots.len() != atom_count {
+        return None;
+    }
+
+    Some((data, patches))
+}
+
+///
+/// Reads values from the front of a byte slice.
+///
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        if self.0.len() < length {
+            return None;
+        }
+
+        let (bytes, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Some(bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Some(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Some(u64::from_le_bytes(bytes))
+    }
+
+    fn f32(&mut self) -> Option<f32> {
+        self.u32().map(f32::from_bits)
+    }
+
+    ///
+    /// Reads an array prefixed by Its length in bytes. The bytes are copied, because the slice may not be aligned.
+    ///
+    fn array<T: bytemuck::Pod>(&mut self) -> Option<Vec<T>> {
+        let length = self.u64()? as usize;
+        let count = length / std::mem::size_of::<T>();
+        if count * std::mem::size_of::<T>() != length {
+            return None;
+        }
+
+        let bytes = self.take(length)?;
+        let mut array = vec![T::zeroed(); count];
+        bytemuck::cast_slice_mut(&mut array).copy_from_slice(bytes);
+        Some(array)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scene() -> SceneAtoms {
+        let atoms = vec![
+            glm::vec4(0.0, 0.0, 0.0, 1.5),
+            glm::vec4(4.0, 0.0, 0.0, 1.5),
+            glm::vec4(2.0, 3.4, 0.0, 1.7),
+            glm::vec4(20.0, 0.0, 0.0, 1.2),
+        ];
+        SceneAtoms {
+            colors: vec![glm::vec4(1.0, 0.5, 0.25, 1.0); atoms.len()],
+            surfaces: vec![0, 0, 1, 1],
+            selected_atoms: atoms.len(),
+            symmetry_mates: 0,
+            atoms,
+        }
+    }
+
+    fn build(scene: &SceneAtoms, solvent_radius: f32) -> (VoxelGridData, PatchLists) {
+        let data = VoxelGridData::new(solvent_radius, scene.atoms.clone(), scene.colors.clone(), scene.surfaces.clone());
+        let patches = PatchLists::new(&data, &scene.atoms, solvent_radius).unwrap();
+        (data, patches)
+    }
+
+    fn cache(name: &str) -> GridCache {
+        let directory = std::env::temp_dir().join(format!("molecules-cache-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        GridCache::new(directory)
+    }
+
+    #[test]
+    fn keys_cover_scene_radii_and_solvent() {
+        let scene = scene();
+        let key = CacheKey::new(&scene, 1.4);
+        assert_eq!(key, CacheKey::new(&scene, 1.4));
+        assert_ne!(key, CacheKey::new(&scene, 1.2));
+
+        let mut moved = scene.clone();
+        moved.atoms[1].x += 0.01;
+        assert_ne!(key, CacheKey::new(&moved, 1.4));
+
+        let mut resized = scene.clone();
+        resized.atoms[2].w = 1.8;
+        assert_ne!(key, CacheKey::new(&resized, 1.4));
+
+        let mut recolored = scene.clone();
+        recolored.surfaces[0] = 1;
+        assert_ne!(key, CacheKey::new(&recolored, 1.4));
+    }
+
+    #[test]
+    fn grids_are_reused() {
+        let cache = cache("reuse");
+        let scene = scene();
+        let key = CacheKey::new(&scene, 1.4);
+        assert!(cache.load(key, scene.atoms.len(), 1.4).is_none());
+
+        let (data, patches) = build(&scene, 1.4);
+        cache.store(key, 1.4, &data, &patches).unwrap();
+
+        let (loaded_data, loaded_patches) = cache.load(key, scene.atoms.len(), 1.4).unwrap();
+        assert_eq!(loaded_data.difference(&data), None);
+        assert_eq!(loaded_data.origin, data.origin);
+        assert_eq!(loaded_data.solvent_radius_max, data.solvent_radius_max);
+        assert_eq!(loaded_patches, patches);
+
+        fs::remove_dir_all(&cache.directory).unwrap();
+    }
+
+    #[test]
+    fn mismatched_files_are_invalidated() {
+        let cache = cache("mismatch");
+        let scene = scene();
+        let key = CacheKey::new(&scene, 1.4);
+        let (data, patches) = build(&scene, 1.4);
+
+        // File of another version
+        cache.store(key, 1.4, &data, &patches).unwrap();
+        let mut bytes = fs::read(cache.path(key)).unwrap();
+        bytes[CACHE_MAGIC.len()..CACHE_MAGIC.len() + 4].copy_from_slice(&(CACHE_VERSION + 1).to_le_bytes());
+        fs::write(cache.path(key), &bytes).unwrap();
+        assert!(cache.load(key, scene.atoms.len(), 1.4).is_none());
+        assert!(!cache.path(key).exists());
+
+        // Truncated file
+        cache.store(key, 1.4, &data, &patches).unwrap();
+        let bytes = fs::read(cache.path(key)).unwrap();
+        fs::write(cache.path(key), &bytes[..bytes.len() - 1]).unwrap();
+        assert!(cache.load(key, scene.atoms.len(), 1.4).is_none());
+
+        // File of another scene stored under the same name
+        cache.store(key, 1.4, &data, &patches).unwrap();
+        assert!(cache.load(key, scene.atoms.len() + 1, 1.4).is_none());
+        cache.store(key, 1.4, &data, &patches).unwrap();
+        assert!(cache.load(key, scene.atoms.len(), 1.2).is_none());
+
+        fs::remove_dir_all(&cache.directory).unwrap();
+    }
+
+    #[test]
+    fn concurrent_stores_leave_valid_file() {
+        let cache = cache("concurrent");
+        let scene = scene();
+        let key = CacheKey::new(&scene, 1.4);
+        let (data, patches) = build(&scene, 1.4);
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let (cache, data, patches) = (cache.clone(), data.clone(), patches.clone());
+                std::thread::spawn(move || cache.store(key, 1.4, &data, &patches))
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap().unwrap();
+        }
+
+        let (loaded_data, loaded_patches) = cache.load(key, scene.atoms.len(), 1.4).unwrap();
+        assert_eq!(loaded_data.difference(&data), None);
+        assert_eq!(loaded_patches, patches);
+
+        fs::remove_dir_all(&cache.directory).unwrap();
+    }
+
+    #[test]
+    fn oldest_files_are_evicted() {
+        let cache = cache("evict");
+        let scene = scene();
+        let (data, patches) = build(&scene, 1.4);
+        for key in 0..4 {
+            cache.store(CacheKey(key), 1.4, &data, &patches).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+
+        let size = fs::metadata(cache.path(CacheKey(0))).unwrap().len();
+        cache.evict(2 * size).unwrap();
+        assert!(!cache.path(CacheKey(0)).exists());
+        assert!(!cache.path(CacheKey(1)).exists());
+        assert!(cache.path(CacheKey(2)).exists());
+        assert!(cache.path(CacheKey(3)).exists());
+
+        // The latest file is kept even if It is too large
+        cache.evict(0).unwrap();
+        assert!(!cache.path(CacheKey(2)).exists());
+        assert!(cache.path(CacheKey(3)).exists());
+
+        fs::remove_dir_all(&cache.directory).unwrap();
+    }
+}
@@ -0,0 +1,228 @@
+//!
+//! Module containing the command line interface of the application.
+//!
+
+use crate::grid::GridBuilder;
+use std::path::PathBuf;
+
+/// Usage printed by `--help` and on invalid arguments.
+pub const USAGE: &str = "\
+Usage: molecules [OPTIONS] [FILE]...
+
+Arguments:
+  [FILE]...                  Structure files shown together on start instead of the test scene
+
+Options:
+  --solvent-radius <RADIUS>  Initial solvent radius in Å
+  --max-steps <COUNT>        Initial maximum number of ray marching steps per frame
+  --size <WIDTH>x<HEIGHT>    Size of the window, e.g. 1920x1080
+  --present-mode <MODE>      Presentation mode of the swap chain: mailbox, fifo or immediate
+  --camera-distance <DIST>   Initial distance of the camera from the centre of the structure
+  --radii <FILE>             Radii table replacing the built-in element radii
+  --select <SELECTION>       Selection of atoms the surface is computed for, e.g. \"chain A\"
+  --grid-builder <BUILDER>   Where the voxel grid is built: gpu, cpu or validate, which compares both
+  --no-grid-cache            Always builds voxel grids instead of reusing cached grids of large structures
+  -h, --help                 Prints this help
+
+The maximum number of neighbouring atoms, formerly set by --max-neighbours, is no longer limited. Pairs and triples of
+atoms forming the surface are found for every cell of the voxel grid in advance instead of searching neighbours while
+ray marching.
+";
+
+///
+/// Options given on the command line. Options that are not given keep the defaults of the application.
+///
+#[derive(Clone, Debug)]
+pub struct Arguments {
+    pub files: Vec<PathBuf>,
+    pub solvent_radius: Option<f32>,
+    pub max_steps: Option<i32>,
+    pub width: u32,
+    pub height: u32,
+    pub present_mode: wgpu::PresentMode,
+    pub camera_distance: Option<f32>,
+    pub radii: Option<PathBuf>,
+    pub selection: Option<String>,
+    pub grid_builder: Option<GridBuilder>,
+    /// Whether voxel grids of large structures are never cached.
+    pub no_grid_cache: bool,
+    /// Whether only the usage should be printed.
+    pub help: bool,
+}
+
+impl Default for Arguments {
+    fn default() -> Self {
+        Self {
+            files: Vec::new(),
+            solvent_radius: None,
+            max_steps: None,
+            width: 1280,
+            height: 720,
+            present_mode: wgpu::PresentMode::Mailbox,
+            camera_distance: None,
+            radii: None,
+            selection: None,
+            grid_builder: None,
+            no_grid_cache: false,
+            help: false,
+        }
+    }
+}
+
+impl Arguments {
+    ///
+    /// Parses arguments without the name of the executable. Returns a description of the problem on invalid arguments.
+    ///
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut arguments = Self::default();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("missing value of `{}`", arg));
+
+            match arg.as_str() {
+                "-h" | "--help" => arguments.help = true,
+                "--solvent-radius" => arguments.solvent_radius = Some(parse_number(&arg, &value()?, 0.0, 2.0)?),
+                "--max-steps" => arguments.max_steps = Some(parse_number(&arg, &value()?, 1, 64)?),
+                "--size" => {
+                    let size = value()?;
+                    let invalid = || format!("invalid window size `{}`, expected e.g. 1280x720", size);
+                    let index = size.find('x').ok_or_else(invalid)?;
+
+                    arguments.width = size[..index].parse().map_err(|_| invalid())?;
+                    arguments.height = size[index + 1..].parse().map_err(|_| invalid())?;
+                    if arguments.width == 0 || arguments.height == 0 {
+                        return Err(invalid());
+                    }
+                }
+                "--present-mode" => {
+                    arguments.present_mode = match value()?.to_ascii_lowercase().as_str() {
+                        "mailbox" => wgpu::PresentMode::Mailbox,
+                        "fifo" => wgpu::PresentMode::Fifo,
+                        "immediate" => wgpu::PresentMode::Immediate,
+                        mode => return Err(format!("unknown present mode `{}`", mode)),
+                    }
+                }
+                "--camera-distance" => arguments.camera_distance = Some(parse_number(&arg, &value()?, 0.0, f32::MAX)?),
+                "--radii" => arguments.radii = Some(PathBuf::from(value()?)),
+                "--select" => arguments.selection = Some(value()?),
+                "--grid-builder" => {
+                    arguments.grid_builder = Some(match value()?.to_ascii_lowercase().as_str() {
+                        "gpu" => GridBuilder::Gpu,
+                        "cpu" => GridBuilder::Cpu,
+                        "validate" => GridBuilder::Validate,
+                        builder => return Err(format!("unknown grid builder `{}`", builder)),
+                    })
+                }
+                "--no-grid-cache" => arguments.no_grid_cache = true,
+                "--max-neighbours" => return Err(format!("`{}` is no longer supported, neighbours are not limited", arg)),
+                _ if arg.starts_with('-') => return Err(format!("unknown option `{}`", arg)),
+                _ => arguments.files.push(PathBuf::from(arg)),
+            }
+        }
+
+        Ok(arguments)
+    }
+}
+
+///
+/// Parses a number of an option and checks that It lies in the range `min..=max`.
+///
+fn parse_number<T: std::str::FromStr + PartialOrd + std::fmt::Display>(option: &str, value: &str, min: T, max: T) -> Result<T, String> {
+    let number = value
+        .parse::<T>()
+        .map_err(|_| format!("invalid value `{}` of `{}`", value, option))?;
+    // Written this way, so that NaN is out of range as well
+    if !(number >= min && number <= max) {
+        return Err(format!("value of `{}` must be between {} and {}", option, min, max));
+    }
+
+    Ok(number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Arguments, String> {
+        Arguments::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn options_and_files_are_parsed() {
+        let arguments = parse(&["a.pdb", "--solvent-radius", "1.4", "--max-steps", "32", "b.cif", "--no-grid-cache"]).unwrap();
+        assert_eq!(arguments.files, vec![PathBuf::from("a.pdb"), PathBuf::from("b.cif")]);
+        assert_eq!((arguments.solvent_radius, arguments.max_steps), (Some(1.4), Some(32)));
+        assert!(arguments.no_grid_cache);
+        assert!(!arguments.help);
+
+        let arguments = parse(&[]).unwrap();
+        assert!(arguments.files.is_empty());
+        assert_eq!((arguments.width, arguments.height), (1280, 720));
+        assert!(parse(&["-h"]).unwrap().help);
+    }
+
+    #[test]
+    fn window_size_is_parsed() {
+        let arguments = parse(&["--size", "1920x1080"]).unwrap();
+        assert_eq!((arguments.width, arguments.height), (1920, 1080));
+
+        for size in ["1920", "1920x", "x1080", "0x1080", "1920x0", "-1x1080", "1920X1080", "1920x1080x2"].iter() {
+            assert_eq!(
+                parse(&["--size", size]).unwrap_err(),
+                format!("invalid window size `{}`, expected e.g. 1280x720", size)
+            );
+        }
+        assert_eq!(parse(&["--size"]).unwrap_err(), "missing value of `--size`");
+    }
+
+    #[test]
+    fn unknown_options_are_rejected() {
+        assert_eq!(parse(&["--solvent"]).unwrap_err(), "unknown option `--solvent`");
+        assert_eq!(parse(&["a.pdb", "-x"]).unwrap_err(), "unknown option `-x`");
+        assert_eq!(
+            parse(&["--max-neighbours", "15"]).unwrap_err(),
+            "`--max-neighbours` is no longer supported, neighbours are not limited"
+        );
+    }
+
+    #[test]
+    fn numbers_must_be_in_range() {
+        assert_eq!(parse(&["--solvent-radius", "0"]).unwrap().solvent_radius, Some(0.0));
+        assert_eq!(parse(&["--solvent-radius", "2"]).unwrap().solvent_radius, Some(2.0));
+
+        for value in ["-0.1", "2.1", "inf", "NaN"].iter() {
+            assert_eq!(
+                parse(&["--solvent-radius", value]).unwrap_err(),
+                "value of `--solvent-radius` must be between 0 and 2"
+            );
+        }
+        assert!(parse(&["--camera-distance", "nan"]).is_err());
+        assert_eq!(
+            parse(&["--max-steps", "0"]).unwrap_err(),
+            "value of `--max-steps` must be between 1 and 64"
+        );
+        assert_eq!(parse(&["--max-steps", "8.5"]).unwrap_err(), "invalid value `8.5` of `--max-steps`");
+    }
+
+    #[test]
+    fn named_values_are_parsed() {
+        let builders = [
+            ("gpu", GridBuilder::Gpu),
+            ("CPU", GridBuilder::Cpu),
+            ("validate", GridBuilder::Validate),
+        ];
+        for (name, builder) in builders.iter() {
+            assert_eq!(parse(&["--grid-builder", name]).unwrap().grid_builder, Some(*builder));
+        }
+        assert_eq!(parse(&["--grid-builder", "gl"]).unwrap_err(), "unknown grid builder `gl`");
+
+        let modes = [
+            ("mailbox", wgpu::PresentMode::Mailbox),
+            ("Fifo", wgpu::PresentMode::Fifo),
+            ("immediate", wgpu::PresentMode::Immediate),
+        ];
+        for (name, mode) in modes.iter() {
+            assert_eq!(parse(&["--present-mode", name]).unwrap().present_mode, *mode);
+        }
+        assert_eq!(parse(&["--present-mode", "vsync"]).unwrap_err(), "unknown present mode `vsync`");
+    }
+}
@@ -0,0 +1,390 @@
+//!
+//! Module containing the crystallographic unit cell and generation of symmetry mates of the asymmetric unit.
+//!
+
+use nalgebra_glm as glm;
+use std::collections::HashMap;
+
+/// Lattice centering translations of C-centered space groups.
+const C_CENTERING: [[f32; 3]; 1] = [[0.5, 0.5, 0.0]];
+/// Lattice centering translations of body-centered space groups.
+const I_CENTERING: [[f32; 3]; 1] = [[0.5, 0.5, 0.5]];
+
+/// Names of a space group, Its symmetry operations and lattice centering translations.
+type SpaceGroup = (&'static [&'static str], &'static [&'static str], &'static [[f32; 3]]);
+
+///
+/// Symmetry operations of common space groups of macromolecular crystals. Names are given without spaces.
+/// Operations of centered groups are listed without the centering, which is given separately.
+///
+const SPACE_GROUPS: [SpaceGroup; 14] = [
+    (&["P1"], &["x,y,z"], &[]),
+    (&["P2", "P121"], &["x,y,z", "-x,y,-z"], &[]),
+    (&["P21", "P1211"], &["x,y,z", "-x,y+1/2,-z"], &[]),
+    (&["C2", "C121"], &["x,y,z", "-x,y,-z"], &C_CENTERING),
+    (&["P222"], &["x,y,z", "-x,-y,z", "-x,y,-z", "x,-y,-z"], &[]),
+    (&["P21212"], &["x,y,z", "-x,-y,z", "-x+1/2,y+1/2,-z", "x+1/2,-y+1/2,-z"], &[]),
+    (
+        &["P212121"],
+        &["x,y,z", "-x+1/2,-y,z+1/2", "-x,y+1/2,-z+1/2", "x+1/2,-y+1/2,-z"],
+        &[],
+    ),
+    (&["C2221"], &["x,y,z", "-x,-y,z+1/2", "-x,y,-z+1/2", "x,-y,-z"], &C_CENTERING),
+    (&["I222"], &["x,y,z", "-x,-y,z", "-x,y,-z", "x,-y,-z"], &I_CENTERING),
+    (
+        &["P41212"],
+        &[
+            "x,y,z",
+            "-x,-y,z+1/2",
+            "-y+1/2,x+1/2,z+1/4",
+            "y+1/2,-x+1/2,z+3/4",
+            "-x+1/2,y+1/2,-z+1/4",
+            "x+1/2,-y+1/2,-z+3/4",
+            "y,x,-z",
+            "-y,-x,-z+1/2",
+        ],
+        &[],
+    ),
+    (
+        &["P43212"],
+        &[
+            "x,y,z",
+            "-x,-y,z+1/2",
+            "-y+1/2,x+1/2,z+3/4",
+            "y+1/2,-x+1/2,z+1/4",
+            "-x+1/2,y+1/2,-z+3/4",
+            "x+1/2,-y+1/2,-z+1/4",
+            "y,x,-z",
+            "-y,-x,-z+1/2",
+        ],
+        &[],
+    ),
+    (
+        &["P3121"],
+        &[
+            "x,y,z",
+            "-y,x-y,z+1/3",
+            "-x+y,-x,z+2/3",
+            "y,x,-z",
+            "x-y,-y,-z+2/3",
+            "-x,-x+y,-z+1/3",
+        ],
+        &[],
+    ),
+    (
+        &["P3221"],
+        &[
+            "x,y,z",
+            "-y,x-y,z+2/3",
+            "-x+y,-x,z+1/3",
+            "y,x,-z",
+            "x-y,-y,-z+1/3",
+            "-x,-x+y,-z+2/3",
+        ],
+        &[],
+    ),
+    (
+        &["P61"],
+        &[
+            "x,y,z",
+            "-y,x-y,z+1/3",
+            "-x+y,-x,z+2/3",
+            "-x,-y,z+1/2",
+            "y,-x+y,z+5/6",
+            "x-y,x,z+1/6",
+        ],
+        &[],
+    ),
+];
+
+///
+/// Parses a symmetry operation in the `x,y,z` notation of the International Tables, e.g. `-x+1/2,y,z+1/3`,
+/// into a matrix acting on fractional coordinates. Returns `None` if the notation is not valid.
+///
+pub fn parse_operation(operation: &str) -> Option<glm::Mat4> {
+    let rows: Vec<&str> = operation.split(',').collect();
+    if rows.len() != 3 {
+        return None;
+    }
+
+    let mut matrix = glm::Mat4::identity();
+    for (row, expression) in rows.iter().enumerate() {
+        let expression: String = expression
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if expression.is_empty() {
+            return None;
+        }
+
+        let mut coefficients = [0.0f32; 4];
+        // Split into signed terms, e.g. `-x+1/2` into `-x` and `+1/2`
+        let mut terms = Vec::new();
+        let mut start = 0;
+        for (index, c) in expression.char_indices().skip(1) {
+            if c == '+' || c == '-' {
+                terms.push(&expression[start..index]);
+                start = index;
+            }
+        }
+        terms.push(&expression[start..]);
+
+        for term in terms {
+            let (sign, term) = match term.chars().next() {
+                Some('-') => (-1.0, &term[1..]),
+                Some('+') => (1.0, &term[1..]),
+                _ => (1.0, term),
+            };
+
+            match term {
+                "x" => coefficients[0] += sign,
+                "y" => coefficients[1] += sign,
+                "z" => coefficients[2] += sign,
+                _ => {
+                    let value = match term.find('/') {
+                        Some(index) => term[..index].parse::<f32>().ok()? / term[index + 1..].parse::<f32>().ok()?,
+                        None => term.parse::<f32>().ok()?,
+                    };
+                    coefficients[3] += sign * value;
+                }
+            }
+        }
+
+        for (column, coefficient) in coefficients.iter().enumerate() {
+            matrix[(row, column)] = *coefficient;
+        }
+    }
+
+    Some(matrix)
+}
+
+///
+/// Returns symmetry operations of a space group from the built-in table, or `None` for unknown space groups.
+/// The name is given in the Hermann-Mauguin notation, e.g. `P 21 21 21`.
+///
+pub fn space_group_operations(space_group: &str) -> Option<Vec<glm::Mat4>> {
+    let name: String = space_group
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_ascii_uppercase();
+    let (_, operations, centering) = SPACE_GROUPS.iter().find(|(names, _, _)| names.contains(&name.as_str()))?;
+
+    let mut operations: Vec<glm::Mat4> = operations.iter().filter_map(|operation| parse_operation(operation)).collect();
+    let centered: Vec<glm::Mat4> = centering
+        .iter()
+        .flat_map(|translation| {
+            operations
+                .iter()
+                .map(move |operation| glm::translation(&glm::vec3(translation[0], translation[1], translation[2])) * operation)
+        })
+        .collect();
+    operations.extend(centered);
+
+    Some(operations)
+}
+
+///
+/// Crystallographic unit cell with the symmetry operations of Its space group.
+///
+#[derive(Clone, Debug)]
+pub struct Crystal {
+    /// Lengths of the cell edges `a`, `b` and `c` in Å.
+    pub lengths: glm::Vec3,
+    /// Angles `alpha`, `beta` and `gamma` between the cell edges in degrees.
+    pub angles: glm::Vec3,
+    /// Name of the space group in the Hermann-Mauguin notation.
+    pub space_group: String,
+    /// Symmetry operations acting on fractional coordinates. Empty if the space group is unknown.
+    pub operations: Vec<glm::Mat4>,
+}
+
+impl Crystal {
+    ///
+    /// Creates the unit cell. Symmetry operations are taken from the built-in table of space groups.
+    /// Returns `None` for placeholder cells of structures that are not crystals, e.g. NMR structures with a 1 Å cube.
+    ///
+    pub fn new(lengths: glm::Vec3, angles: glm::Vec3, space_group: String) -> Option<Self> {
+        if lengths.iter().any(|length| *length <= 1.0) || angles.iter().any(|angle| *angle <= 0.0 || *angle >= 180.0) {
+            return None;
+        }
+
+        Some(Self {
+            lengths,
+            angles,
+            operations: space_group_operations(&space_group).unwrap_or_default(),
+            space_group,
+        })
+    }
+
+    ///
+    /// Returns the matrix converting fractional coordinates to Cartesian coordinates in Å.
+    /// The `a` edge is aligned with the x axis and the `b` edge lies in the xy plane, as in PDB files.
+    ///
+    pub fn orthogonalization(&self) -> glm::Mat4 {
+        let (a, b, c) = (self.lengths.x, self.lengths.y, self.lengths.z);
+        let cos = self.angles.map(|angle| angle.to_radians().cos());
+        let sin_gamma = self.angles.z.to_radians().sin();
+        let volume = a * b * c * (1.0 - cos.x * cos.x - cos.y * cos.y - cos.z * cos.z + 2.0 * cos.x * cos.y * cos.z).sqrt();
+
+        let mut matrix = glm::Mat4::identity();
+        matrix[(0, 0)] = a;
+        matrix[(0, 1)] = b * cos.z;
+        matrix[(0, 2)] = c * cos.y;
+        matrix[(1, 1)] = b * sin_gamma;
+        matrix[(1, 2)] = c * (cos.x - cos.y * cos.z) / sin_gamma;
+        matrix[(2, 2)] = volume / (a * b * sin_gamma);
+
+        matrix
+    }
+
+    ///
+    /// Replaces the symmetry operations by operations acting on Cartesian coordinates, e.g. from `REMARK 290 SMTRY`.
+    ///
+    pub fn set_cartesian_operations(&mut self, operations: &[glm::Mat4]) {
+        let orthogonalization = self.orthogonalization();
+        let fractionalization = glm::inverse(&orthogonalization);
+
+        self.operations = operations
+            .iter()
+            .map(|operation| fractionalization * operation * orthogonalization)
+            .collect();
+    }
+
+    ///
+    /// Returns Cartesian transforms of the symmetry mates with at least one atom closer than `radius`
+    /// to an atom of the asymmetric unit given by Its atom `positions`. The asymmetric unit itself is not included.
+    ///
+    pub fn symmetry_mates(&self, positions: &[glm::Vec3], radius: f32) -> Vec<glm::Mat4> {
+        if positions.is_empty() || self.operations.is_empty() {
+            return Vec::new();
+        }
+
+        let orthogonalization = self.orthogonalization();
+        let fractionalization = glm::inverse(&orthogonalization);
+        let transform_point = |matrix: &glm::Mat4, point: &glm::Vec3| (matrix * glm::vec4(point.x, point.y, point.z, 1.0)).xyz();
+
+        // Bounding sphere of the asymmetric unit
+        let center = positions.iter().fold(glm::vec3(0.0, 0.0, 0.0), |sum, position| sum + position) / positions.len() as f32;
+        let extent = positions
+            .iter()
+            .fold(0.0f32, |extent, position| extent.max(glm::distance(&center, position)));
+        let fractional_center = transform_point(&fractionalization, &center);
+
+        // Atoms of the asymmetric unit hashed into cells of size `radius`
+        let cell_size = radius.max(1.0);
+        let cell = |position: &glm::Vec3| {
+            (
+                (position.x / cell_size).floor() as i32,
+                (position.y / cell_size).floor() as i32,
+                (position.z / cell_size).floor() as i32,
+            )
+        };
+        let mut cells: HashMap<(i32, i32, i32), Vec<glm::Vec3>> = HashMap::new();
+        for position in positions {
+            cells.entry(cell(position)).or_default().push(*position);
+        }
+        let is_close = |position: &glm::Vec3| {
+            let (x, y, z) = cell(position);
+            (-1..=1).any(|dx| {
+                (-1..=1).any(|dy| {
+                    (-1..=1).any(|dz| match cells.get(&(x + dx, y + dy, z + dz)) {
+                        Some(cell) => cell.iter().any(|other| glm::distance(position, other) <= radius),
+                        None => false,
+                    })
+                })
+            })
+        };
+
+        // Number of lattice translations along every edge that can bring a copy close enough
+        let reach = self.lengths.map(|length| ((2.0 * extent + radius) / length).ceil() as i32 + 1);
+
+        let mut mates = Vec::new();
+        for operation in &self.operations {
+            // Translation bringing the center of the copy closest to the center of the asymmetric unit
+            let offset = (fractional_center - transform_point(operation, &fractional_center)).map(|e| e.round() as i32);
+
+            for i in -reach.x..=reach.x {
+                for j in -reach.y..=reach.y {
+                    for k in -reach.z..=reach.z {
+                        let translation = glm::vec3((offset.x + i) as f32, (offset.y + j) as f32, (offset.z + k) as f32);
+                        let transform = orthogonalization * glm::translation(&translation) * operation * fractionalization;
+
+                        // Skip the asymmetric unit itself
+                        let identity = glm::Mat4::identity();
+                        if (transform - identity).iter().all(|e| e.abs() < 1.0e-3) {
+                            continue;
+                        }
+
+                        if glm::distance(&transform_point(&transform, &center), &center) > 2.0 * extent + radius {
+                            continue;
+                        }
+                        if positions.iter().any(|position| is_close(&transform_point(&transform, position))) {
+                            mates.push(transform);
+                        }
+                    }
+                }
+            }
+        }
+
+        mates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn right_angles() -> glm::Vec3 {
+        glm::vec3(90.0, 90.0, 90.0)
+    }
+
+    #[test]
+    fn operations_are_parsed() {
+        let operation = parse_operation("-x+1/2,y,-z").unwrap();
+        let point = operation * glm::vec4(0.1, 0.2, 0.3, 1.0);
+        assert!(glm::distance(&point, &glm::vec4(0.4, 0.2, -0.3, 1.0)) < 1.0e-6);
+
+        let operation = parse_operation(" X-Y , x , Z+0.25 ").unwrap();
+        let point = operation * glm::vec4(0.1, 0.2, 0.3, 1.0);
+        assert!(glm::distance(&point, &glm::vec4(-0.1, 0.1, 0.55, 1.0)) < 1.0e-6);
+
+        for operation in ["x,y", "x,y,", "x,y,w", "x,y,z+a"].iter() {
+            assert!(parse_operation(operation).is_none(), "{} is parsed", operation);
+        }
+    }
+
+    #[test]
+    fn placeholder_cells_are_rejected() {
+        assert!(Crystal::new(glm::vec3(1.0, 1.0, 1.0), right_angles(), "P 1".to_string()).is_none());
+        assert!(Crystal::new(glm::vec3(50.0, 60.0, 0.0), right_angles(), "P 1".to_string()).is_none());
+        assert!(Crystal::new(glm::vec3(50.0, 60.0, 70.0), glm::vec3(90.0, 0.0, 90.0), "P 1".to_string()).is_none());
+        assert!(Crystal::new(glm::vec3(50.0, 60.0, 70.0), glm::vec3(90.0, 180.0, 90.0), "P 1".to_string()).is_none());
+
+        let crystal = Crystal::new(glm::vec3(50.0, 60.0, 70.0), right_angles(), "P 21 21 21".to_string()).unwrap();
+        assert_eq!(crystal.operations.len(), 4);
+        let crystal = Crystal::new(glm::vec3(50.0, 60.0, 70.0), right_angles(), "P 65 2 2".to_string()).unwrap();
+        assert!(crystal.operations.is_empty());
+    }
+
+    #[test]
+    fn symmetry_mates_within_radius_are_found() {
+        let crystal = Crystal::new(glm::vec3(20.0, 24.0, 28.0), right_angles(), "P 21 21 21".to_string()).unwrap();
+        // Single atom at the fractional position (0.1, 0.2, 0.3). Its closest copies come in pairs at 12.96, 15.21, 18.00,
+        // 19.70 and 20.00 Å, the last being lattice translations
+        let positions = [glm::vec3(2.0, 4.8, 8.4)];
+
+        let counts: Vec<usize> = [10.0, 14.0, 16.5, 19.0, 20.1]
+            .iter()
+            .map(|&radius| crystal.symmetry_mates(&positions, radius).len())
+            .collect();
+        assert_eq!(counts, vec![0, 2, 4, 6, 10]);
+
+        for mate in crystal.symmetry_mates(&positions, 16.5) {
+            let position = (mate * glm::vec4(2.0, 4.8, 8.4, 1.0)).xyz();
+            let distance = glm::distance(&position, &positions[0]);
+            assert!(distance > 12.9 && distance < 16.5, "mate at {} Å", distance);
+        }
+    }
+}
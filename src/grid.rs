@@ -3,6 +3,8 @@
 //! for sphere marching.
 //!
 
+use crate::patches::*;
+use crate::pipelines::grid::*;
 use crate::utils::*;
 use nalgebra_glm as glm;
 use wgpu;
@@ -11,7 +13,7 @@ use wgpu;
 /// Pointer to block of memory containing one grid cell of voxel grid.
 ///
 #[repr(C)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct VoxelPointer {
     pub start: u32,
     pub length: u32,
@@ -19,30 +21,201 @@ pub struct VoxelPointer {
 
 unsafe impl bytemuck::Zeroable for VoxelPointer {}
 unsafe impl bytemuck::Pod for VoxelPointer {}
+
+/// Largest size of the voxel pointers of a dense grid in bytes. Larger grids are stored sparsely. wgpu does not report
+/// the largest storage buffer binding of the device, so this is the smallest one Vulkan guarantees.
+pub const MAX_DENSE_GRID_SIZE: usize = 128 << 20;
+
+/// Key of an empty slot of the hash table of a sparse grid.
+pub const EMPTY_CELL: u32 = u32::MAX;
+
 ///
-/// Voxel grid. Contains information about AABB of the scene and GPU buffers containing the voxel grid in flat format for GPU.
+/// Way the voxel grid is built.
 ///
-pub struct VoxelGrid {
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GridBuilder {
+    /// Atoms are binned on the CPU and uploaded.
+    Cpu,
+    /// Atoms are uploaded and binned by compute shaders. Grids that must be stored sparsely are built on the CPU.
+    Gpu,
+    /// Atoms are binned on the GPU and the result is compared to the grid built on the CPU.
+    Validate,
+}
+
+/// Smallest voxel length. Keeps the number of grid cells of large structures manageable if the solvent radius is small.
+pub const MIN_VOXEL_LENGTH: f32 = 4.0;
+
+///
+/// Returns the voxel length needed for the given largest atom radius and solvent radius. Atoms can form patches
+/// of the surface up to `radius + 2 * solvent_radius` away from their centres and ray marching searches only
+/// the neighbouring cells, so voxels must be at least that long. The length is a whole number, so the padded
+/// bounding box is divisible by It.
+///
+pub fn voxel_length(radius_max: f32, solvent_radius: f32) -> f32 {
+    (radius_max + 2.0 * solvent_radius).ceil().max(MIN_VOXEL_LENGTH)
+}
+
+///
+/// Voxel grid built on the CPU in the flat format of the GPU. Atoms are centred in the grid, so positions in the grid
+/// (render space) differ from positions of the scene by `origin`.
+///
+#[derive(Clone, Debug)]
+pub struct VoxelGridData {
+    /// Position of the scene that is moved to the centre of the render space.
+    pub origin: glm::Vec3,
     pub bb_min: glm::Vec3,
     pub bb_max: glm::Vec3,
     pub bb_diff: glm::Vec3,
+    /// Number of cells along every axis.
     pub bb_size: glm::Vec3,
     pub voxel_length: f32,
+    /// Largest radius among the atoms.
+    pub radius_max: f32,
+    /// Largest solvent radius for which the voxels are long enough.
+    pub solvent_radius_max: f32,
 
-    pub voxels: wgpu::Buffer,
-    pub voxels_len: usize,
-    pub voxel_pointers: wgpu::Buffer,
-    pub voxel_pointers_len: usize,
+    /// Atoms in render space sorted by cells, four floats `(x, y, z, radius)` per atom.
+    pub voxels: Vec<f32>,
+    /// Colors of `voxels`, four floats per atom.
+    pub colors: Vec<f32>,
+    /// Surfaces of `voxels`.
+    pub surfaces: Vec<u32>,
+    /// Range of `voxels` of every cell of a dense grid. Cells are ordered by x, then y, then z.
+    /// Slots of the hash table of a sparse grid that have their cells in `hash_keys`.
+    pub voxel_pointers: Vec<VoxelPointer>,
+    /// Cells of the slots of `voxel_pointers` of a sparse grid, or `EMPTY_CELL`. Empty for a dense grid.
+    /// The hash table stores only cells containing atoms and is resolved by linear probing from `hash_cell`.
+    pub hash_keys: Vec<u32>,
+    /// Slot in `voxels` of every atom in the order of the input. Patches of the surface refer to atoms through It.
+    pub atom_slots: Vec<u32>,
 }
 
-impl VoxelGrid {
+impl VoxelGridData {
+    ///
+    /// Bins atoms into cells long enough for the given solvent radius. Requires atoms in format `(x, y, z, radius)`.
+    /// Every atom has an RGBA color the surface near It is tinted with and an index of the surface It belongs to.
+    /// The bounding box of an empty grid is centred at the origin.
+    ///
+    pub fn new(solvent_radius: f32, atoms: Vec<glm::Vec4>, colors: Vec<glm::Vec4>, surfaces: Vec<u32>) -> Self {
+        Self::with_dense_budget(solvent_radius, atoms, colors, surfaces, MAX_DENSE_GRID_SIZE)
+    }
+
+    ///
+    /// Same as `new`, but the grid is stored sparsely if Its voxel pointers would take more than `dense_budget` bytes.
+    ///
+    pub fn with_dense_budget(
+        solvent_radius: f32,
+        atoms: Vec<glm::Vec4>,
+        colors: Vec<glm::Vec4>,
+        surfaces: Vec<u32>,
+        dense_budget: usize,
+    ) -> Self {
+        Self::layout(solvent_radius, &atoms)
+            .fill(&atoms, &colors, &surfaces, dense_budget)
+            .0
+    }
+
+    ///
+    /// Bins atoms into the cells of this grid, which must contain them. Returns the new grid together with the index
+    /// of the atom in every slot of `voxels`.
+    ///
+    fn fill(&self, atoms: &[glm::Vec4], colors: &[glm::Vec4], surfaces: &[u32], dense_budget: usize) -> (Self, Vec<usize>) {
+        assert_eq!(atoms.len(), colors.len(), "Every atom needs a color");
+        assert_eq!(atoms.len(), surfaces.len(), "Every atom needs a surface");
+
+        let mut grid = Self {
+            origin: self.origin,
+            bb_min: self.bb_min,
+            bb_max: self.bb_max,
+            bb_diff: self.bb_diff,
+            bb_size: self.bb_size,
+            voxel_length: self.voxel_length,
+            radius_max: self.radius_max,
+            solvent_radius_max: self.solvent_radius_max,
+
+            voxels: Vec::with_capacity(4 * atoms.len()),
+            colors: Vec::with_capacity(4 * atoms.len()),
+            surfaces: Vec::with_capacity(atoms.len()),
+            voxel_pointers: Vec::new(),
+            hash_keys: Vec::new(),
+            atom_slots: Vec::new(),
+        };
+
+        // Center the molecules
+        let atoms: Vec<glm::Vec4> = atoms.iter().map(|atom| grid.to_render_atom(atom)).collect();
+
+        // Sort atoms by their cells, keeping the order of atoms within a cell
+        let cells: Vec<usize> = atoms
+            .iter()
+            .map(|atom| grid.cell_index(&atom.xyz()).expect("Atom lies outside of Its bounding box"))
+            .collect();
+        let mut order: Vec<usize> = (0..atoms.len()).collect();
+        order.sort_by_key(|&index| cells[index]);
+        grid.atom_slots = vec![0; atoms.len()];
+        for (slot, &index) in order.iter().enumerate() {
+            grid.atom_slots[index] = slot as u32;
+        }
+
+        let mut occupied_cells: Vec<(usize, VoxelPointer)> = Vec::new();
+        for (position, &index) in order.iter().enumerate() {
+            match occupied_cells.last_mut() {
+                Some((cell, pointer)) if *cell == cells[index] => pointer.length += 1,
+                _ => occupied_cells.push((
+                    cells[index],
+                    VoxelPointer {
+                        start: position as u32,
+                        length: 1,
+                    },
+                )),
+            }
+
+            grid.voxels.extend_from_slice(atoms[index].as_slice());
+            grid.colors.extend_from_slice(colors[index].as_slice());
+            grid.surfaces.push(surfaces[index]);
+        }
+
+        if grid.cell_count() * std::mem::size_of::<VoxelPointer>() <= dense_budget {
+            let mut count = 0;
+            let mut occupied_cells = occupied_cells.into_iter().peekable();
+            for cell in 0..grid.cell_count() {
+                let pointer = match occupied_cells.peek() {
+                    Some((occupied_cell, pointer)) if *occupied_cell == cell => *pointer,
+                    _ => VoxelPointer { start: count, length: 0 },
+                };
+                if pointer.length > 0 {
+                    occupied_cells.next();
+                }
+
+                grid.voxel_pointers.push(pointer);
+                count += pointer.length;
+            }
+        } else {
+            // At most half of the slots are used, so probing stays short
+            let slots = (2 * occupied_cells.len()).next_power_of_two();
+            grid.hash_keys = vec![EMPTY_CELL; slots];
+            grid.voxel_pointers = vec![VoxelPointer { start: 0, length: 0 }; slots];
+            for (cell, pointer) in occupied_cells {
+                let mut slot = hash_cell(cell as u32, slots);
+                while grid.hash_keys[slot] != EMPTY_CELL {
+                    slot = (slot + 1) % slots;
+                }
+                grid.hash_keys[slot] = cell as u32;
+                grid.voxel_pointers[slot] = pointer;
+            }
+        }
+
+        (grid, order)
+    }
+
     ///
-    /// Initializes the voxel grid. Requires
+    /// Computes the bounding box and the voxel length of a grid of the atoms without binning them. The atoms must pass
+    /// `validate`.
     ///
-    pub fn new(device: &wgpu::Device, radius_max: f32, mut atoms: Vec<glm::Vec4>) -> Self {
+    pub fn layout(solvent_radius: f32, atoms: &[glm::Vec4]) -> Self {
         // Calculate voxel length
-        let solvent_radius_max = 2.0;
-        let voxel_length = 2.0 * radius_max + 2.0 * solvent_radius_max;
+        let radius_max = atoms.iter().fold(0.0f32, |radius_max, atom| radius_max.max(atom.w));
+        let voxel_length = voxel_length(radius_max, solvent_radius);
+        let solvent_radius_max = (voxel_length - radius_max) / 2.0;
 
         // Find bounding box of the entire structure
         let mut bb_max = glm::vec3(std::f32::NEG_INFINITY, std::f32::NEG_INFINITY, std::f32::NEG_INFINITY);
@@ -51,18 +224,17 @@ impl VoxelGrid {
             bb_max = glm::max2(&bb_max, &glm::vec4_to_vec3(atom));
             bb_min = glm::min2(&bb_min, &glm::vec4_to_vec3(atom));
         }
+        if atoms.is_empty() {
+            bb_max = glm::vec3(0.0, 0.0, 0.0);
+            bb_min = glm::vec3(0.0, 0.0, 0.0);
+        }
         bb_min -= glm::vec3(1.0, 1.0, 1.0);
         bb_max += glm::vec3(1.0, 1.0, 1.0);
         let bb_center = (bb_max + bb_min) / 2.0;
 
-        // Center the molecules (+their bounding box)
+        // Center the bounding box
         bb_max = bb_max - bb_center;
         bb_min = bb_min - bb_center;
-        for atom in atoms.iter_mut() {
-            atom.x -= bb_center.x;
-            atom.y -= bb_center.y;
-            atom.z -= bb_center.z;
-        }
 
         // Pad the bounding box to size divisible by voxel length
         bb_max.apply(|e| e.round_to_multiple(voxel_length as i32));
@@ -71,62 +243,1062 @@ impl VoxelGrid {
         let bb_diff = bb_max - bb_min;
         let bb_size = bb_diff.apply_into(|e| e.abs() / voxel_length as f32);
 
-        let mut voxels_nested: Vec<Vec<glm::Vec4>> = vec![Vec::new(); (bb_size.x * bb_size.y * bb_size.z) as usize];
+        Self {
+            origin: bb_center,
+            bb_min,
+            bb_max,
+            bb_diff,
+            bb_size,
+            voxel_length,
+            radius_max,
+            solvent_radius_max,
 
-        for atom in atoms.iter() {
-            let grid_position_vec3 = (atom.xyz() - bb_min) / voxel_length;
-            let grid_position_ivec3 = glm::vec3(
-                grid_position_vec3.x as i32,
-                grid_position_vec3.y as i32,
-                grid_position_vec3.z as i32,
-            );
+            voxels: Vec::new(),
+            colors: Vec::new(),
+            surfaces: Vec::new(),
+            voxel_pointers: Vec::new(),
+            hash_keys: Vec::new(),
+            atom_slots: Vec::new(),
+        }
+    }
 
-            let bb_size = glm::vec3(bb_size.x as i32, bb_size.y as i32, bb_size.z as i32);
-            let width = bb_size.x;
-            let height = bb_size.y;
-            let x = grid_position_ivec3.x;
-            let y = grid_position_ivec3.y;
-            let z = grid_position_ivec3.z;
-            let index = (width * height * z) + (width * y) + x;
+    ///
+    /// Checks that a grid of the atoms can be built, i.e. their positions and radii are finite and their bounding box
+    /// does not have more cells than can be indexed. Returns a description of the problem otherwise.
+    ///
+    pub fn validate(solvent_radius: f32, atoms: &[glm::Vec4]) -> Result<(), String> {
+        if let Some(index) = atoms.iter().position(|atom| atom.iter().any(|value| !value.is_finite())) {
+            return Err(format!("atom {} has a position or a radius that is not finite", index + 1));
+        }
 
-            voxels_nested[index as usize].push(glm::vec4(atom.x, atom.y, atom.z, 1.0));
+        let layout = Self::layout(solvent_radius, atoms);
+        let cell_count = layout.bb_size.iter().fold(1.0f64, |count, &size| count * f64::from(size));
+        if cell_count >= f64::from(EMPTY_CELL) {
+            return Err(format!(
+                "the structure spans {:.0} Å, which is too much for a voxel grid",
+                glm::comp_max(&layout.bb_diff)
+            ));
         }
 
-        let mut voxels: Vec<f32> = Vec::new();
-        let mut voxel_pointers = Vec::new();
-        let mut count = 0;
-        for voxel in voxels_nested.iter_mut() {
-            voxel_pointers.push(VoxelPointer {
-                start: count,
-                length: voxel.len() as u32,
-            });
-            count += voxel.len() as u32;
+        Ok(())
+    }
+
+    ///
+    /// Describes the first difference of two grids, or returns `None` if they are identical.
+    ///
+    pub fn difference(&self, other: &Self) -> Option<String> {
+        fn first_difference<T: PartialEq>(a: &[T], b: &[T]) -> Option<usize> {
+            if a.len() != b.len() {
+                return Some(a.len().min(b.len()));
+            }
+            a.iter().zip(b).position(|(a, b)| a != b)
+        }
+
+        if self.origin != other.origin || self.bb_min != other.bb_min || self.bb_max != other.bb_max {
+            return Some(String::from("bounding boxes differ"));
+        }
+        if self.voxel_length != other.voxel_length {
+            return Some(format!("voxel lengths {} and {} differ", self.voxel_length, other.voxel_length));
+        }
+        if let Some(slot) = first_difference(&self.hash_keys, &other.hash_keys) {
+            return Some(format!("hash keys differ at slot {}", slot));
+        }
+        if let Some(cell) = first_difference(&self.voxel_pointers, &other.voxel_pointers) {
+            return Some(format!("voxel pointers differ at cell {}", cell));
+        }
+        if let Some(index) = first_difference(&self.voxels, &other.voxels) {
+            return Some(format!("voxels differ at atom {}", index / 4));
+        }
+        if let Some(index) = first_difference(&self.colors, &other.colors) {
+            return Some(format!("colors differ at atom {}", index / 4));
+        }
+        if let Some(index) = first_difference(&self.surfaces, &other.surfaces) {
+            return Some(format!("surfaces differ at atom {}", index));
+        }
+        if let Some(index) = first_difference(&self.atom_slots, &other.atom_slots) {
+            return Some(format!("slots differ at input atom {}", index));
+        }
+
+        None
+    }
+
+    ///
+    /// Returns `true` if only the cells containing atoms are stored in a hash table.
+    ///
+    pub fn is_sparse(&self) -> bool {
+        !self.hash_keys.is_empty()
+    }
+
+    ///
+    /// Returns the range of `voxels` of a cell in the same way as the ray marching shader.
+    ///
+    pub fn voxel_pointer(&self, cell: usize) -> VoxelPointer {
+        match self.pointer_index(cell) {
+            Some(index) => self.voxel_pointers[index],
+            None => VoxelPointer { start: 0, length: 0 },
+        }
+    }
+
+    ///
+    /// Returns the index of the voxel pointer of a cell, or `None` if a sparse grid does not store the cell.
+    ///
+    pub fn pointer_index(&self, cell: usize) -> Option<usize> {
+        if !self.is_sparse() {
+            return Some(cell);
+        }
+
+        let slots = self.hash_keys.len();
+        let mut slot = hash_cell(cell as u32, slots);
+        loop {
+            match self.hash_keys[slot] {
+                key if key == cell as u32 => return Some(slot),
+                EMPTY_CELL => return None,
+                _ => slot = (slot + 1) % slots,
+            }
+        }
+    }
+
+    ///
+    /// Returns the number of cells of the grid.
+    ///
+    pub fn cell_count(&self) -> usize {
+        (self.bb_size.x * self.bb_size.y * self.bb_size.z) as usize
+    }
+
+    ///
+    /// Converts a position in the coordinates of the scene the grid was built from to render space.
+    ///
+    pub fn to_render(&self, position: &glm::Vec3) -> glm::Vec3 {
+        position - self.origin
+    }
+
+    ///
+    /// Moves an atom in format `(x, y, z, radius)` from the scene to render space.
+    ///
+    pub fn to_render_atom(&self, atom: &glm::Vec4) -> glm::Vec4 {
+        let position = self.to_render(&atom.xyz());
+        glm::vec4(position.x, position.y, position.z, atom.w)
+    }
+
+    ///
+    /// Returns the index of the cell containing a position in render space, or `None` if the position lies outside of
+    /// the grid. Positions on the boundary of two cells belong to the cell with higher coordinates.
+    ///
+    pub fn cell_index(&self, position: &glm::Vec3) -> Option<usize> {
+        // Shaders round multiplication correctly unlike division, so the GPU builder finds the same cells
+        let grid_position = (position - self.bb_min) * (1.0 / self.voxel_length);
+        if grid_position.iter().any(|value| !value.is_finite()) {
+            return None;
+        }
+        let x = grid_position.x.floor() as i64;
+        let y = grid_position.y.floor() as i64;
+        let z = grid_position.z.floor() as i64;
+
+        let width = self.bb_size.x as i64;
+        let height = self.bb_size.y as i64;
+        let depth = self.bb_size.z as i64;
+        if x < 0 || y < 0 || z < 0 || x >= width || y >= height || z >= depth {
+            return None;
+        }
+
+        Some(((width * height * z) + (width * y) + x) as usize)
+    }
+}
+
+///
+/// Returns the first slot of a cell in a hash table with the given power of two number of slots.
+/// Must match `hash_cell` of the ray marching shader.
+///
+pub fn hash_cell(cell: u32, slots: usize) -> usize {
+    (cell.wrapping_mul(2_654_435_761) as usize) & (slots - 1)
+}
+
+///
+/// CPU copy of a voxel grid whose atoms can be moved in place. Cells containing atoms have spare slots, so atoms can
+/// move between cells without binning all atoms again.
+///
+/// Empty cells have no slots, because a dense grid would need spare slots in all of Its cells, most of which are empty,
+/// and a sparse grid has no entries of empty cells in Its hash table. An atom entering an empty cell makes `move_atoms`
+/// fail, and all atoms are binned again and uploaded whole like a new grid. The entered cell gets spare slots then, so
+/// an atom moving out of the surface costs this once rather than on every move.
+///
+struct MovableAtoms {
+    /// Grid with spare slots after the atoms of every cell.
+    data: VoxelGridData,
+    /// Number of slots of the cell of every voxel pointer.
+    capacities: Vec<u32>,
+    /// Voxel pointer of every atom in the order of the input.
+    pointers: Vec<usize>,
+    /// Atom in every slot of `data.voxels`, or `None` for spare slots.
+    slot_atoms: Vec<Option<usize>>,
+    /// Slots changed since the last upload.
+    dirty_slots: std::ops::Range<usize>,
+    /// Voxel pointers changed since the last upload.
+    dirty_pointers: std::ops::Range<usize>,
+    /// Atoms in the order of the input whose slots changed since the last upload.
+    dirty_atoms: std::ops::Range<usize>,
+}
+
+impl MovableAtoms {
+    ///
+    /// Bins atoms into the cells of the layout, which must contain them.
+    ///
+    fn new(layout: &VoxelGridData, atoms: &[glm::Vec4], colors: &[glm::Vec4], surfaces: &[u32], dense_budget: usize) -> Self {
+        let (mut data, order) = layout.fill(atoms, colors, surfaces, dense_budget);
+        let voxels = std::mem::take(&mut data.voxels);
+        let voxel_colors = std::mem::take(&mut data.colors);
+        let voxel_surfaces = std::mem::take(&mut data.surfaces);
 
-            for v in voxel {
-                voxels.push(v[0]);
-                voxels.push(v[1]);
-                voxels.push(v[2]);
-                voxels.push(v[3]);
+        let mut movable = Self {
+            data,
+            capacities: Vec::new(),
+            pointers: vec![0; atoms.len()],
+            slot_atoms: Vec::new(),
+            dirty_slots: 0..0,
+            dirty_pointers: 0..0,
+            dirty_atoms: 0..0,
+        };
+
+        // Move cells apart to make room for a quarter more atoms
+        for (index, pointer) in movable.data.voxel_pointers.iter_mut().enumerate() {
+            let spare = if pointer.length > 0 { pointer.length / 4 + 1 } else { 0 };
+            for old_slot in pointer.start as usize..(pointer.start + pointer.length) as usize {
+                let atom = order[old_slot];
+                movable.data.atom_slots[atom] = movable.slot_atoms.len() as u32;
+                movable.pointers[atom] = index;
+                movable.slot_atoms.push(Some(atom));
+
+                movable.data.voxels.extend_from_slice(&voxels[4 * old_slot..4 * old_slot + 4]);
+                movable.data.colors.extend_from_slice(&voxel_colors[4 * old_slot..4 * old_slot + 4]);
+                movable.data.surfaces.push(voxel_surfaces[old_slot]);
+            }
+            for _ in 0..spare {
+                movable.slot_atoms.push(None);
+                movable.data.voxels.extend_from_slice(&[0.0; 4]);
+                movable.data.colors.extend_from_slice(&[0.0; 4]);
+                movable.data.surfaces.push(0);
+            }
+
+            pointer.start = (movable.slot_atoms.len() - pointer.length as usize - spare as usize) as u32;
+            movable.capacities.push(pointer.length + spare);
+        }
+
+        movable
+    }
+
+    ///
+    /// Returns `true` if the atoms in the order of the input have the given colors and surfaces.
+    ///
+    fn matches(&self, colors: &[glm::Vec4], surfaces: &[u32]) -> bool {
+        colors.len() == self.data.atom_slots.len()
+            && surfaces.len() == self.data.atom_slots.len()
+            && self.data.atom_slots.iter().enumerate().all(|(atom, &slot)| {
+                let slot = slot as usize;
+                self.data.colors[4 * slot..4 * slot + 4] == *colors[atom].as_slice() && self.data.surfaces[slot] == surfaces[atom]
+            })
+    }
+
+    ///
+    /// Moves atoms to new positions inside of the bounding box. Returns `false` if an atom enters a cell without a spare
+    /// slot, leaving the atoms partially moved.
+    ///
+    fn move_atoms(&mut self, atoms: &[glm::Vec4]) -> bool {
+        for (atom, position) in atoms.iter().enumerate() {
+            let position = self.data.to_render_atom(position);
+            let cell = self
+                .data
+                .cell_index(&position.xyz())
+                .expect("Atom lies outside of the bounding box");
+            let pointer = match self.data.pointer_index(cell) {
+                Some(pointer) => pointer,
+                None => return false,
+            };
+            if pointer != self.pointers[atom] && !self.change_cell(atom, pointer) {
+                return false;
             }
+
+            let slot = self.data.atom_slots[atom] as usize;
+            self.data.voxels[4 * slot..4 * slot + 4].copy_from_slice(position.as_slice());
+            mark(&mut self.dirty_slots, slot);
         }
 
-        let voxels_len = voxels.len() as usize;
-        let voxels = device.create_buffer_with_data(bytemuck::cast_slice(&voxels), wgpu::BufferUsage::STORAGE_READ);
+        true
+    }
 
-        let voxel_pointers_len = voxel_pointers.len() as usize;
-        let voxel_pointers = device.create_buffer_with_data(bytemuck::cast_slice(&voxel_pointers), wgpu::BufferUsage::STORAGE_READ);
+    ///
+    /// Moves an atom to the spare slot of another cell. The last atom of Its current cell takes Its slot.
+    ///
+    fn change_cell(&mut self, atom: usize, pointer: usize) -> bool {
+        let new_pointer = self.data.voxel_pointers[pointer];
+        if new_pointer.length == self.capacities[pointer] {
+            return false;
+        }
+
+        let slot = self.data.atom_slots[atom] as usize;
+        let mut color = [0.0; 4];
+        color.copy_from_slice(&self.data.colors[4 * slot..4 * slot + 4]);
+        let surface = self.data.surfaces[slot];
+
+        // Remove the atom from Its cell
+        let old_pointer = self.pointers[atom];
+        self.data.voxel_pointers[old_pointer].length -= 1;
+        let last_slot = (self.data.voxel_pointers[old_pointer].start + self.data.voxel_pointers[old_pointer].length) as usize;
+        if last_slot != slot {
+            let last_atom = self.slot_atoms[last_slot].expect("Last slot of a cell is empty");
+            self.data.voxels.copy_within(4 * last_slot..4 * last_slot + 4, 4 * slot);
+            self.data.colors.copy_within(4 * last_slot..4 * last_slot + 4, 4 * slot);
+            self.data.surfaces[slot] = self.data.surfaces[last_slot];
+            self.slot_atoms[slot] = Some(last_atom);
+            self.data.atom_slots[last_atom] = slot as u32;
+            mark(&mut self.dirty_atoms, last_atom);
+        }
+        self.slot_atoms[last_slot] = None;
+
+        // Append It to the new cell
+        let new_slot = (new_pointer.start + new_pointer.length) as usize;
+        self.data.voxel_pointers[pointer].length += 1;
+        self.data.colors[4 * new_slot..4 * new_slot + 4].copy_from_slice(&color);
+        self.data.surfaces[new_slot] = surface;
+        self.slot_atoms[new_slot] = Some(atom);
+        self.data.atom_slots[atom] = new_slot as u32;
+        mark(&mut self.dirty_atoms, atom);
+        self.pointers[atom] = pointer;
+
+        for &slot in &[slot, last_slot, new_slot] {
+            mark(&mut self.dirty_slots, slot);
+        }
+        mark(&mut self.dirty_pointers, old_pointer);
+        mark(&mut self.dirty_pointers, pointer);
+
+        true
+    }
+}
+
+///
+/// Voxel grid. Contains information about AABB of the scene and GPU buffers containing the voxel grid in flat format for GPU.
+/// Atoms are centred in the grid, so positions in the grid (render space) differ from positions of the scene by `origin`.
+///
+pub struct VoxelGrid {
+    /// Position of the scene that is moved to the centre of the render space.
+    pub origin: glm::Vec3,
+    pub bb_min: glm::Vec3,
+    pub bb_max: glm::Vec3,
+    pub bb_diff: glm::Vec3,
+    pub bb_size: glm::Vec3,
+    pub voxel_length: f32,
+    /// Largest radius among the atoms.
+    pub radius_max: f32,
+    /// Largest solvent radius for which the voxels are long enough.
+    pub solvent_radius_max: f32,
+
+    pub voxels: wgpu::Buffer,
+    pub voxels_len: usize,
+    pub colors: wgpu::Buffer,
+    pub colors_len: usize,
+    pub surfaces: wgpu::Buffer,
+    pub surfaces_len: usize,
+    pub voxel_pointers: wgpu::Buffer,
+    pub voxel_pointers_len: usize,
+    /// Keys of the hash table of a sparse grid. Contains one unused key for a dense grid.
+    pub hash_keys: wgpu::Buffer,
+    pub hash_keys_len: usize,
+    /// Slot in `voxels` of every atom in the order of the input.
+    pub atom_slots: wgpu::Buffer,
+    pub atom_slots_len: usize,
+    /// Pairs and triples of atoms forming patches of the surface in every cell.
+    pub patches: PatchBuffers,
+
+    /// CPU copy of the grid kept once atoms are moved in place.
+    movable: Option<MovableAtoms>,
+    /// CPU copy of the patches kept once atoms are moved in place.
+    movable_patches: Option<MovablePatches>,
+}
+
+impl VoxelGrid {
+    ///
+    /// Initializes the voxel grid for the given solvent radius. Requires atoms in format `(x, y, z, radius)`.
+    /// Every atom has an RGBA color the surface near It is tinted with and an index of the surface It belongs to.
+    /// Atoms of different surfaces form patches together only if the surfaces are not separated during ray marching.
+    /// The grid has no patches until they are found once for It and set by `set_patches`.
+    ///
+    pub fn new(device: &wgpu::Device, solvent_radius: f32, atoms: Vec<glm::Vec4>, colors: Vec<glm::Vec4>, surfaces: Vec<u32>) -> Self {
+        let layout = VoxelGridData::layout(solvent_radius, &atoms);
+        Self::upload(
+            device,
+            &layout.fill(&atoms, &colors, &surfaces, MAX_DENSE_GRID_SIZE).0,
+            &PatchLists::empty(),
+        )
+    }
+
+    ///
+    /// Creates GPU buffers of a voxel grid built on the CPU and of Its patches. Buffers of an empty grid contain one
+    /// unused element, because empty buffers cannot be bound.
+    ///
+    pub fn upload(device: &wgpu::Device, data: &VoxelGridData, patches: &PatchLists) -> Self {
+        fn non_empty<T: Copy + Default>(values: &[T]) -> std::borrow::Cow<'_, [T]> {
+            if values.is_empty() {
+                std::borrow::Cow::Owned(vec![T::default()])
+            } else {
+                std::borrow::Cow::Borrowed(values)
+            }
+        }
+
+        // Grids can be read back and atoms can be moved
+        let usage = wgpu::BufferUsage::STORAGE_READ | wgpu::BufferUsage::COPY_SRC | wgpu::BufferUsage::COPY_DST;
+
+        let voxels_len = data.voxels.len();
+        let voxels = device.create_buffer_with_data(bytemuck::cast_slice(&non_empty(&data.voxels)), usage);
+
+        let colors_len = data.colors.len();
+        let colors = device.create_buffer_with_data(bytemuck::cast_slice(&non_empty(&data.colors)), usage);
+
+        let surfaces_len = data.surfaces.len();
+        let surfaces = device.create_buffer_with_data(bytemuck::cast_slice(&non_empty(&data.surfaces)), usage);
+
+        let voxel_pointers_len = data.voxel_pointers.len();
+        let voxel_pointers = device.create_buffer_with_data(bytemuck::cast_slice(&data.voxel_pointers), usage);
+
+        let hash_keys_len = data.hash_keys.len();
+        let hash_keys = device.create_buffer_with_data(bytemuck::cast_slice(&non_empty(&data.hash_keys)), usage);
+
+        let atom_slots_len = data.atom_slots.len();
+        let atom_slots = device.create_buffer_with_data(bytemuck::cast_slice(&non_empty(&data.atom_slots)), usage);
 
         Self {
-            bb_min,
-            bb_max,
-            bb_diff,
-            bb_size,
-            voxel_length,
+            origin: data.origin,
+            bb_min: data.bb_min,
+            bb_max: data.bb_max,
+            bb_diff: data.bb_diff,
+            bb_size: data.bb_size,
+            voxel_length: data.voxel_length,
+            radius_max: data.radius_max,
+            solvent_radius_max: data.solvent_radius_max,
 
             voxels,
             voxels_len,
+            colors,
+            colors_len,
+            surfaces,
+            surfaces_len,
             voxel_pointers,
             voxel_pointers_len,
+            hash_keys,
+            hash_keys_len,
+            atom_slots,
+            atom_slots_len,
+            patches: PatchBuffers::upload(device, patches),
+
+            movable: None,
+            movable_patches: None,
+        }
+    }
+
+    ///
+    /// Builds a dense voxel grid on the GPU. The result is identical to the grid built on the CPU by `new`, which is
+    /// used for grids that must be stored sparsely. Patches are set by `set_patches` as well.
+    ///
+    pub fn build(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipeline: &GridPipeline,
+        solvent_radius: f32,
+        atoms: &[glm::Vec4],
+        colors: &[glm::Vec4],
+        surfaces: &[u32],
+    ) -> Self {
+        assert_eq!(atoms.len(), colors.len(), "Every atom needs a color");
+        assert_eq!(atoms.len(), surfaces.len(), "Every atom needs a surface");
+
+        let layout = VoxelGridData::layout(solvent_radius, atoms);
+        let cell_count = layout.cell_count();
+        if atoms.is_empty() || cell_count * std::mem::size_of::<VoxelPointer>() > MAX_DENSE_GRID_SIZE {
+            return Self::new(device, solvent_radius, atoms.to_vec(), colors.to_vec(), surfaces.to_vec());
+        }
+
+        let globals = GridGlobals {
+            origin: layout.origin.into(),
+            voxel_scale: 1.0 / layout.voxel_length,
+            bb_min: layout.bb_min.into(),
+            atom_count: atoms.len() as u32,
+            bb_size: layout.bb_size.into(),
+            cell_count: cell_count as u32,
+        };
+        let globals_buffer = device.create_buffer_with_data(bytemuck::cast_slice(&[globals]), wgpu::BufferUsage::UNIFORM);
+
+        // Inputs
+        let flatten = |vectors: &[glm::Vec4]| vectors.iter().flat_map(|vector| vector.iter().copied()).collect::<Vec<f32>>();
+        let input_atoms = device.create_buffer_with_data(bytemuck::cast_slice(&flatten(atoms)), wgpu::BufferUsage::STORAGE_READ);
+        let input_colors = device.create_buffer_with_data(bytemuck::cast_slice(&flatten(colors)), wgpu::BufferUsage::STORAGE_READ);
+        let input_surfaces = device.create_buffer_with_data(bytemuck::cast_slice(surfaces), wgpu::BufferUsage::STORAGE_READ);
+
+        // Intermediate buffers. Lengths of cells are counted from zero. Outputs are read by ray marching, can be read back and atoms can be moved.
+        let usage =
+            wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::STORAGE_READ | wgpu::BufferUsage::COPY_SRC | wgpu::BufferUsage::COPY_DST;
+        let create_buffer = |label, size: usize| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: size as wgpu::BufferAddress,
+                usage,
+            })
+        };
+        let atom_cells = create_buffer("Atom cells buffer", 2 * atoms.len() * std::mem::size_of::<u32>());
+        let atom_indices = create_buffer("Atom indices buffer", atoms.len() * std::mem::size_of::<u32>());
+        let voxel_pointers = device.create_buffer_with_data(bytemuck::cast_slice(&vec![0u32; 2 * cell_count]), usage);
+
+        // Outputs
+        let voxels_len = 4 * atoms.len();
+        let voxels = create_buffer("Voxels buffer", voxels_len * std::mem::size_of::<f32>());
+        let colors_len = 4 * atoms.len();
+        let colors = create_buffer("Voxel colors buffer", colors_len * std::mem::size_of::<f32>());
+        let surfaces_len = atoms.len();
+        let surfaces = create_buffer("Voxel surfaces buffer", surfaces_len * std::mem::size_of::<u32>());
+        let atom_slots_len = atoms.len();
+        let atom_slots = create_buffer("Atom slots buffer", atom_slots_len * std::mem::size_of::<u32>());
+        let hash_keys = device.create_buffer_with_data(bytemuck::cast_slice(&[EMPTY_CELL]), wgpu::BufferUsage::STORAGE_READ);
+
+        let buffer = |binding, buffer, size: usize| wgpu::Binding {
+            binding,
+            resource: wgpu::BindingResource::Buffer {
+                buffer,
+                range: 0..size as wgpu::BufferAddress,
+            },
+        };
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Grid bind group"),
+            layout: &pipeline.bind_group_layout,
+            bindings: &[
+                buffer(0, &globals_buffer, std::mem::size_of::<GridGlobals>()),
+                buffer(1, &input_atoms, 4 * atoms.len() * std::mem::size_of::<f32>()),
+                buffer(2, &input_colors, 4 * atoms.len() * std::mem::size_of::<f32>()),
+                buffer(3, &input_surfaces, atoms.len() * std::mem::size_of::<u32>()),
+                buffer(4, &atom_cells, 2 * atoms.len() * std::mem::size_of::<u32>()),
+                buffer(5, &voxel_pointers, cell_count * std::mem::size_of::<VoxelPointer>()),
+                buffer(6, &atom_indices, atoms.len() * std::mem::size_of::<u32>()),
+                buffer(7, &voxels, voxels_len * std::mem::size_of::<f32>()),
+                buffer(8, &colors, colors_len * std::mem::size_of::<f32>()),
+                buffer(9, &surfaces, surfaces_len * std::mem::size_of::<u32>()),
+                buffer(10, &atom_slots, atom_slots_len * std::mem::size_of::<u32>()),
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Grid encoder"),
+        });
+        {
+            let (atom_groups_x, atom_groups_y) = work_groups(atoms.len() as u32);
+            let (cell_groups_x, cell_groups_y) = work_groups(cell_count as u32);
+
+            let mut cpass = encoder.begin_compute_pass();
+            cpass.set_bind_group(0, &bind_group, &[]);
+            cpass.set_pipeline(&pipeline.count_pipeline);
+            cpass.dispatch(atom_groups_x, atom_groups_y, 1);
+            cpass.set_pipeline(&pipeline.prefix_sum_pipeline);
+            cpass.dispatch(1, 1, 1);
+            cpass.set_pipeline(&pipeline.scatter_pipeline);
+            cpass.dispatch(atom_groups_x, atom_groups_y, 1);
+            cpass.set_pipeline(&pipeline.sort_pipeline);
+            cpass.dispatch(cell_groups_x, cell_groups_y, 1);
+        }
+        queue.submit(&[encoder.finish()]);
+
+        Self {
+            origin: layout.origin,
+            bb_min: layout.bb_min,
+            bb_max: layout.bb_max,
+            bb_diff: layout.bb_diff,
+            bb_size: layout.bb_size,
+            voxel_length: layout.voxel_length,
+            radius_max: layout.radius_max,
+            solvent_radius_max: layout.solvent_radius_max,
+
+            voxels,
+            voxels_len,
+            colors,
+            colors_len,
+            surfaces,
+            surfaces_len,
+            voxel_pointers,
+            voxel_pointers_len: cell_count,
+            hash_keys,
+            hash_keys_len: 0,
+            atom_slots,
+            atom_slots_len,
+            patches: PatchBuffers::upload(device, &PatchLists::empty()),
+
+            movable: None,
+            movable_patches: None,
+        }
+    }
+
+    ///
+    /// Reads the voxel grid back from the GPU, e.g. to compare the grids built on the GPU and on the CPU.
+    ///
+    pub async fn download(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> VoxelGridData {
+        async fn read<T: bytemuck::Pod>(device: &wgpu::Device, queue: &wgpu::Queue, buffer: &wgpu::Buffer, len: usize) -> Vec<T> {
+            if len == 0 {
+                return Vec::new();
+            }
+
+            let size = (len * std::mem::size_of::<T>()) as wgpu::BufferAddress;
+            let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Voxel grid staging buffer"),
+                size,
+                usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+            });
+
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Download voxel grid encoder"),
+            });
+            encoder.copy_buffer_to_buffer(buffer, 0, &staging_buffer, 0, size);
+            queue.submit(&[encoder.finish()]);
+
+            let mapping = staging_buffer.map_read(0, size);
+            device.poll(wgpu::Maintain::Wait);
+            let mapping = mapping.await.expect("Could not read the voxel grid");
+
+            bytemuck::cast_slice(mapping.as_slice()).to_vec()
+        }
+
+        VoxelGridData {
+            voxels: read(device, queue, &self.voxels, self.voxels_len).await,
+            colors: read(device, queue, &self.colors, self.colors_len).await,
+            surfaces: read(device, queue, &self.surfaces, self.surfaces_len).await,
+            voxel_pointers: read(device, queue, &self.voxel_pointers, self.voxel_pointers_len).await,
+            hash_keys: read(device, queue, &self.hash_keys, self.hash_keys_len).await,
+            atom_slots: read(device, queue, &self.atom_slots, self.atom_slots_len).await,
+            ..self.layout()
+        }
+    }
+
+    ///
+    /// Returns the bounding box and the voxel length of the grid without any atoms.
+    ///
+    pub fn layout(&self) -> VoxelGridData {
+        VoxelGridData {
+            origin: self.origin,
+            bb_min: self.bb_min,
+            bb_max: self.bb_max,
+            bb_diff: self.bb_diff,
+            bb_size: self.bb_size,
+            voxel_length: self.voxel_length,
+            radius_max: self.radius_max,
+            solvent_radius_max: self.solvent_radius_max,
+
+            voxels: Vec::new(),
+            colors: Vec::new(),
+            surfaces: Vec::new(),
+            voxel_pointers: Vec::new(),
+            hash_keys: Vec::new(),
+            atom_slots: Vec::new(),
+        }
+    }
+
+    ///
+    /// Replaces the patches of the surface, e.g. after the solvent radius changed. The patches must be found for the
+    /// layout of the grid and the atoms It was built from, in the same order.
+    ///
+    pub fn set_patches(&mut self, device: &wgpu::Device, patches: &PatchLists) {
+        self.patches = PatchBuffers::upload(device, patches);
+        self.movable_patches = None;
+    }
+
+    ///
+    /// Moves atoms of the grid to new positions and radii. Atoms must be in the same order as when the grid was built.
+    /// Only atoms that cross boundaries of cells are binned again and only the changed parts of the buffers are uploaded.
+    /// All atoms are binned again if an atom enters a cell that was empty.
+    /// Patches of the surface are found again only in the cells near moved atoms, unless most of the atoms move.
+    /// Returns `false` if the grid must be built again because the atoms leave Its bounding box, their radii or
+    /// the solvent radius need longer voxels, their number, colors or surfaces changed, or their patches take too much
+    /// memory.
+    ///
+    pub fn update_atoms(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        solvent_radius: f32,
+        atoms: &[glm::Vec4],
+        colors: &[glm::Vec4],
+        surfaces: &[u32],
+    ) -> bool {
+        if atoms.len() != colors.len() || atoms.len() != surfaces.len() || !self.supports(solvent_radius) {
+            return false;
+        }
+
+        let layout = self.layout();
+        let inside = atoms
+            .iter()
+            .all(|atom| atom.w <= layout.radius_max && layout.cell_index(&layout.to_render_atom(atom).xyz()).is_some());
+        if !inside {
+            return false;
+        }
+
+        // Patches are found again only in the cells near moved atoms if possible
+        let mut movable_patches = self.movable_patches.take();
+        let patches_moved = match movable_patches.as_mut() {
+            Some(movable_patches) => movable_patches.move_atoms(&layout, atoms, solvent_radius),
+            None => false,
+        };
+        let patches = if patches_moved {
+            None
+        } else {
+            let lists = match PatchLists::new(&layout, atoms, solvent_radius) {
+                Ok(lists) => lists,
+                Err(_) => return false,
+            };
+            movable_patches = MovablePatches::new(&layout, atoms, solvent_radius, &lists);
+            Some(PatchBuffers::upload(
+                device,
+                movable_patches.as_ref().map_or(&lists, |patches| &patches.lists),
+            ))
+        };
+
+        let moved = match self.movable.as_mut() {
+            Some(movable) if movable.matches(colors, surfaces) => movable.move_atoms(atoms),
+            _ => false,
+        };
+        if !moved {
+            // Bin all atoms into cells with spare slots, keeping the bounding box, the kind of the grid and the patches
+            let dense_budget = if self.hash_keys_len > 0 { 0 } else { MAX_DENSE_GRID_SIZE };
+            let movable = MovableAtoms::new(&layout, atoms, colors, surfaces, dense_budget);
+            let mut grid = Self::upload(device, &movable.data, &PatchLists::empty());
+            std::mem::swap(&mut grid.patches, &mut self.patches);
+            *self = grid;
+            self.movable = Some(movable);
+        }
+
+        fn write_buffer<T: bytemuck::Pod>(
+            device: &wgpu::Device,
+            encoder: &mut wgpu::CommandEncoder,
+            buffer: &wgpu::Buffer,
+            values: &[T],
+            range: std::ops::Range<usize>,
+        ) {
+            if range.start == range.end {
+                return;
+            }
+
+            let size = std::mem::size_of::<T>();
+            let staging_buffer = device.create_buffer_with_data(bytemuck::cast_slice(&values[range.clone()]), wgpu::BufferUsage::COPY_SRC);
+            encoder.copy_buffer_to_buffer(
+                &staging_buffer,
+                0,
+                buffer,
+                (range.start * size) as wgpu::BufferAddress,
+                (range.len() * size) as wgpu::BufferAddress,
+            );
+        }
+
+        let movable = self.movable.as_mut().expect("Atoms were moved");
+        let slots = std::mem::replace(&mut movable.dirty_slots, 0..0);
+        let pointers = std::mem::replace(&mut movable.dirty_pointers, 0..0);
+        let moved_atoms = std::mem::replace(&mut movable.dirty_atoms, 0..0);
+        let data = &movable.data;
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Move atoms encoder"),
+        });
+        write_buffer(device, &mut encoder, &self.voxels, &data.voxels, 4 * slots.start..4 * slots.end);
+        write_buffer(device, &mut encoder, &self.colors, &data.colors, 4 * slots.start..4 * slots.end);
+        write_buffer(device, &mut encoder, &self.surfaces, &data.surfaces, slots);
+        write_buffer(device, &mut encoder, &self.voxel_pointers, &data.voxel_pointers, pointers);
+        write_buffer(device, &mut encoder, &self.atom_slots, &data.atom_slots, moved_atoms);
+        match patches {
+            Some(patches) => self.patches = patches,
+            None => {
+                let movable_patches = movable_patches.as_mut().expect("Patches were moved");
+                let slots = std::mem::replace(&mut movable_patches.dirty_slots, 0..0);
+                let patch_atoms = std::mem::replace(&mut movable_patches.dirty_atoms, 0..0);
+                let lists = &movable_patches.lists;
+                write_buffer(device, &mut encoder, &self.patches.keys, &lists.keys, slots.clone());
+                write_buffer(device, &mut encoder, &self.patches.pointers, &lists.pointers, slots);
+                write_buffer(device, &mut encoder, &self.patches.atoms, &lists.atoms, patch_atoms);
+            }
+        }
+        queue.submit(&[encoder.finish()]);
+        self.movable_patches = movable_patches;
+
+        true
+    }
+
+    ///
+    /// Returns `true` if the voxels are long enough for all patches of the surface with the given solvent radius.
+    ///
+    pub fn supports(&self, solvent_radius: f32) -> bool {
+        solvent_radius <= self.solvent_radius_max
+    }
+
+    ///
+    /// Converts a position in render space to the coordinates of the scene the grid was built from.
+    ///
+    pub fn to_scene(&self, position: &glm::Vec3) -> glm::Vec3 {
+        position + self.origin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(atoms: &[glm::Vec4]) -> VoxelGridData {
+        sized_grid(atoms, MAX_DENSE_GRID_SIZE)
+    }
+
+    fn sized_grid(atoms: &[glm::Vec4], dense_budget: usize) -> VoxelGridData {
+        let colors = vec![glm::vec4(1.0, 1.0, 1.0, 1.0); atoms.len()];
+        let surfaces = (0..atoms.len() as u32).collect();
+        VoxelGridData::with_dense_budget(1.0, atoms.to_vec(), colors, surfaces, dense_budget)
+    }
+
+    fn cell_of_atom(grid: &VoxelGridData, surface: u32) -> usize {
+        let index = grid.surfaces.iter().position(|&s| s == surface).unwrap() as u32;
+        (0..grid.cell_count())
+            .find(|&cell| {
+                let pointer = grid.voxel_pointer(cell);
+                pointer.start <= index && index < pointer.start + pointer.length
+            })
+            .unwrap()
+    }
+
+    fn assert_consistent(grid: &VoxelGridData, atom_count: usize) {
+        assert_eq!(grid.voxels.len(), 4 * atom_count);
+        assert_eq!(grid.colors.len(), 4 * atom_count);
+        assert_eq!(grid.surfaces.len(), atom_count);
+        assert_eq!(grid.atom_slots.len(), atom_count);
+        for (atom, &slot) in grid.atom_slots.iter().enumerate() {
+            assert_eq!(grid.surfaces[slot as usize], atom as u32);
+        }
+        if grid.is_sparse() {
+            assert_eq!(grid.hash_keys.len(), grid.voxel_pointers.len());
+        } else {
+            assert_eq!(grid.voxel_pointers.len(), grid.cell_count());
+        }
+
+        let mut start = 0;
+        for cell in 0..grid.cell_count() {
+            let pointer = grid.voxel_pointer(cell);
+            if pointer.length == 0 {
+                continue;
+            }
+
+            assert_eq!(pointer.start, start);
+            for atom in grid.voxels[4 * pointer.start as usize..4 * (pointer.start + pointer.length) as usize].chunks(4) {
+                assert_eq!(grid.cell_index(&glm::vec3(atom[0], atom[1], atom[2])), Some(cell));
+            }
+            start += pointer.length;
+        }
+        assert_eq!(start as usize, atom_count);
+    }
+
+    #[test]
+    fn negative_coordinates() {
+        let atoms = [
+            glm::vec4(-13.7, -2.1, -40.0, 1.5),
+            glm::vec4(-0.2, -25.9, -7.3, 1.2),
+            glm::vec4(-31.0, -0.9, -19.6, 1.8),
+            glm::vec4(-5.5, -5.5, -5.5, 1.0),
+        ];
+        let grid = grid(&atoms);
+
+        assert_consistent(&grid, atoms.len());
+        for (surface, atom) in atoms.iter().enumerate() {
+            let position = atom.xyz() - grid.origin;
+            assert_eq!(cell_of_atom(&grid, surface as u32), grid.cell_index(&position).unwrap());
+        }
+    }
+
+    #[test]
+    fn atoms_on_cell_boundaries() {
+        let atoms = [glm::vec4(0.0, 0.0, 0.0, 1.0), glm::vec4(8.0, 0.0, 0.0, 1.0)];
+        let grid = grid(&atoms);
+
+        assert_eq!(grid.voxel_length, 4.0);
+        assert_eq!(grid.bb_min, glm::vec3(-8.0, -4.0, -4.0));
+        assert_eq!(grid.bb_max, glm::vec3(8.0, 4.0, 4.0));
+        assert_eq!(grid.bb_size, glm::vec3(4.0, 2.0, 2.0));
+        assert_consistent(&grid, atoms.len());
+
+        // Atoms centred at x = -4 and x = 4 lie on boundaries of cells and belong to the cells with higher coordinates
+        assert_eq!(cell_of_atom(&grid, 0), 4 * 2 + 4 + 1);
+        assert_eq!(cell_of_atom(&grid, 1), 4 * 2 + 4 + 3);
+    }
+
+    #[test]
+    fn positions_outside_of_grid() {
+        let grid = grid(&[glm::vec4(0.0, 0.0, 0.0, 1.0)]);
+
+        assert_eq!(grid.cell_index(&grid.bb_min), Some(0));
+        assert_eq!(grid.cell_index(&grid.bb_max), None);
+        assert_eq!(grid.cell_index(&(grid.bb_min - glm::vec3(0.001, 0.0, 0.0))), None);
+    }
+
+    #[test]
+    fn sparse_grid_over_budget() {
+        // Two distant clusters leave most cells of the bounding box empty
+        let atoms: Vec<glm::Vec4> = (0..20)
+            .map(|i| {
+                let offset = if i % 2 == 0 { 0.0 } else { 400.0 };
+                glm::vec4(offset + (i / 2) as f32 * 1.3, (i % 3) as f32 * 2.1, -(i % 5) as f32 * 1.7, 1.5)
+            })
+            .collect();
+        let dense = sized_grid(&atoms, MAX_DENSE_GRID_SIZE);
+        let sparse = sized_grid(&atoms, 0);
+
+        assert!(!dense.is_sparse());
+        assert!(sparse.is_sparse());
+        assert!(sparse.voxel_pointers.len() < dense.voxel_pointers.len());
+        assert!(sparse.hash_keys.len().is_power_of_two());
+        assert_consistent(&sparse, atoms.len());
+
+        assert_eq!(sparse.voxels, dense.voxels);
+        for cell in 0..dense.cell_count() {
+            let dense_pointer = dense.voxel_pointer(cell);
+            let sparse_pointer = sparse.voxel_pointer(cell);
+            assert_eq!(sparse_pointer.length, dense_pointer.length);
+            if dense_pointer.length > 0 {
+                assert_eq!(sparse_pointer.start, dense_pointer.start);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_sparse_grid() {
+        let grid = sized_grid(&[], 0);
+
+        assert_eq!(grid.hash_keys, vec![EMPTY_CELL]);
+        assert_consistent(&grid, 0);
+    }
+
+    #[test]
+    fn difference_of_grids() {
+        let atoms = [glm::vec4(0.0, 0.0, 0.0, 1.0), glm::vec4(8.0, 0.0, 0.0, 1.0)];
+        let grid = grid(&atoms);
+        assert_eq!(grid.difference(&grid.clone()), None);
+
+        let mut other = grid.clone();
+        other.voxels[5] += 0.5;
+        assert_eq!(grid.difference(&other), Some(String::from("voxels differ at atom 1")));
+
+        let mut other = grid.clone();
+        other.voxel_pointers.pop();
+        assert!(grid.difference(&other).is_some());
+    }
+
+    #[test]
+    fn invalid_atoms_are_rejected() {
+        let atom = glm::vec4(1.0, 2.0, 3.0, 1.5);
+        assert!(VoxelGridData::validate(1.4, &[]).is_ok());
+        assert!(VoxelGridData::validate(1.4, &[atom, glm::vec4(-1.0, -2.0, -3.0, 1.5)]).is_ok());
+
+        for value in [std::f32::NAN, std::f32::INFINITY, std::f32::NEG_INFINITY].iter() {
+            let mut invalid = atom;
+            invalid.y = *value;
+            assert!(VoxelGridData::validate(1.4, &[atom, invalid]).unwrap_err().contains("atom 2"));
+            invalid = atom;
+            invalid.w = *value;
+            assert!(VoxelGridData::validate(1.4, &[invalid, atom]).unwrap_err().contains("atom 1"));
+        }
+
+        let far = glm::vec4(1.0e4, 1.0e4, 1.0e4, 1.5);
+        assert!(VoxelGridData::validate(1.4, &[atom, far]).is_err());
+        let huge = glm::vec4(3.0e38, 0.0, 0.0, 1.5);
+        assert!(VoxelGridData::validate(1.4, &[atom, huge, glm::vec4(-huge.x, 0.0, 0.0, 1.5)]).is_err());
+    }
+
+    #[test]
+    fn empty_input() {
+        let grid = grid(&[]);
+
+        assert_eq!(grid.origin, glm::vec3(0.0, 0.0, 0.0));
+        assert_eq!(grid.radius_max, 0.0);
+        assert_eq!(grid.voxel_length, MIN_VOXEL_LENGTH);
+        assert_eq!(grid.bb_min, glm::vec3(-4.0, -4.0, -4.0));
+        assert_eq!(grid.bb_max, glm::vec3(4.0, 4.0, 4.0));
+        assert_consistent(&grid, 0);
+        assert!(grid.voxel_pointers.iter().all(|pointer| pointer.length == 0));
+    }
+
+    fn movable_atoms(atoms: &[glm::Vec4], dense_budget: usize) -> MovableAtoms {
+        let colors = vec![glm::vec4(1.0, 1.0, 1.0, 1.0); atoms.len()];
+        let surfaces: Vec<u32> = (0..atoms.len() as u32).collect();
+        MovableAtoms::new(&VoxelGridData::layout(1.0, atoms), atoms, &colors, &surfaces, dense_budget)
+    }
+
+    fn assert_moved(movable: &MovableAtoms, atoms: &[glm::Vec4]) {
+        let grid = &movable.data;
+        for (atom, position) in atoms.iter().enumerate() {
+            let slot = movable.data.atom_slots[atom] as usize;
+            let position = grid.to_render_atom(position);
+            assert_eq!(grid.voxels[4 * slot..4 * slot + 4], *position.as_slice());
+            assert_eq!(grid.surfaces[slot], atom as u32);
+            assert_eq!(movable.slot_atoms[slot], Some(atom));
+
+            let pointer = grid.voxel_pointers[grid.pointer_index(grid.cell_index(&position.xyz()).unwrap()).unwrap()];
+            assert!(pointer.start as usize <= slot && slot < (pointer.start + pointer.length) as usize);
+        }
+        let atom_count = movable.slot_atoms.iter().filter(|atom| atom.is_some()).count();
+        assert_eq!(atom_count, atoms.len());
+    }
+
+    #[test]
+    fn moving_atoms_within_cells() {
+        let atoms = [glm::vec4(0.0, 0.0, 0.0, 1.0), glm::vec4(20.0, 10.0, 5.0, 1.5)];
+        let mut movable = movable_atoms(&atoms, MAX_DENSE_GRID_SIZE);
+        assert_moved(&movable, &atoms);
+
+        let moved = [glm::vec4(0.1, -0.1, 0.05, 1.0), glm::vec4(19.9, 10.1, 5.0, 1.5)];
+        assert!(movable.move_atoms(&moved));
+        assert_moved(&movable, &moved);
+        assert_eq!(movable.dirty_pointers, 0..0);
+    }
+
+    #[test]
+    fn moving_atoms_across_cells() {
+        let atoms: Vec<glm::Vec4> = (0..40)
+            .map(|i| glm::vec4((i % 4) as f32 * 10.0, (i / 4) as f32 * 10.0, 0.0, 1.0))
+            .collect();
+        for &dense_budget in &[MAX_DENSE_GRID_SIZE, 0] {
+            let mut movable = movable_atoms(&atoms, dense_budget);
+
+            // Reverse positions of atoms, so that every atom enters the cell of another atom before It leaves
+            let mut moved = atoms.clone();
+            moved.reverse();
+            assert!(movable.move_atoms(&moved));
+            assert_moved(&movable, &moved);
+            assert!(movable.move_atoms(&atoms));
+            assert_moved(&movable, &atoms);
+        }
+    }
+
+    #[test]
+    fn moving_atoms_into_full_cell() {
+        let atoms: Vec<glm::Vec4> = (0..8).map(|i| glm::vec4(i as f32 * 10.0, 0.0, 0.0, 1.0)).collect();
+        let mut movable = movable_atoms(&atoms, MAX_DENSE_GRID_SIZE);
+
+        let moved = vec![atoms[0]; atoms.len()];
+        assert!(!movable.move_atoms(&moved));
+    }
+
+    #[test]
+    fn moving_atoms_into_empty_cell() {
+        let atoms: Vec<glm::Vec4> = (0..8).map(|i| glm::vec4(i as f32 * 10.0, 0.0, 0.0, 1.0)).collect();
+        let colors = vec![glm::vec4(1.0, 1.0, 1.0, 1.0); atoms.len()];
+        let surfaces: Vec<u32> = (0..atoms.len() as u32).collect();
+        let layout = VoxelGridData::layout(1.0, &atoms);
+
+        for &dense_budget in &[MAX_DENSE_GRID_SIZE, 0] {
+            let mut movable = MovableAtoms::new(&layout, &atoms, &colors, &surfaces, dense_budget);
+
+            // Cell between two atoms has no slots
+            let mut moved = atoms.clone();
+            moved[3].x += 5.0;
+            assert!(!movable.move_atoms(&moved));
+
+            // Binning all atoms again like `VoxelGrid::update_atoms` gives the entered cell a spare slot for another atom
+            let mut movable = MovableAtoms::new(&layout, &moved, &colors, &surfaces, dense_budget);
+            assert_moved(&movable, &moved);
+            moved[4] = moved[3];
+            assert!(movable.move_atoms(&moved));
+            assert_moved(&movable, &moved);
         }
     }
 }
@@ -0,0 +1,128 @@
+//!
+//! Reader of the [GROMACS GRO format](https://manual.gromacs.org/current/reference-manual/file-formats.html#gro).
+//!
+
+use super::Reader;
+use crate::molecule::{skipped_warning, Atom, Molecule};
+use crate::radii::element_from_atom_name;
+use nalgebra_glm as glm;
+
+/// GRO coordinates are in nanometers, the rest of the application works in ångströms.
+const NANOMETER: f32 = 10.0;
+
+///
+/// Returns trimmed content of 0-based columns `start..end` of a line. Missing columns are empty.
+///
+fn columns(line: &str, start: usize, end: usize) -> &str {
+    line.get(start.min(line.len())..end.min(line.len())).unwrap_or("").trim()
+}
+
+///
+/// Parses one atom line in the fixed format `residue_id(5) residue_name(5) name(5) index(5) x(8) y(8) z(8) ...`.
+/// Returns `None` if the coordinates are malformed.
+///
+fn read_atom(line: &str) -> Option<Atom> {
+    let x = columns(line, 20, 28).parse::<f32>().ok()?;
+    let y = columns(line, 28, 36).parse::<f32>().ok()?;
+    let z = columns(line, 36, 44).parse::<f32>().ok()?;
+
+    let name = columns(line, 10, 15).to_string();
+    let residue_name = columns(line, 5, 10).to_string();
+
+    Some(Atom {
+        position: glm::vec3(x, y, z) * NANOMETER,
+        element: element_from_atom_name(&name, &residue_name),
+        name,
+        residue_name,
+        residue_id: columns(line, 0, 5).parse().unwrap_or(0),
+        ..Default::default()
+    })
+}
+
+///
+/// Reader of GRO files. Every frame of a multi-frame file becomes a frame of the molecule.
+/// Frames with malformed atom lines or a different number of atoms than the first one are skipped with a warning.
+///
+pub struct GroReader;
+
+impl Reader for GroReader {
+    fn read(&self, content: &str) -> Molecule {
+        let mut molecule: Option<Molecule> = None;
+        let mut skipped = Vec::new();
+
+        let mut lines = content.lines();
+        let mut frame = 0;
+        // Every frame consists of a title, number of atoms, atoms and the box vectors
+        while lines.next().is_some() {
+            let count = match lines.next().and_then(|line| line.trim().parse::<usize>().ok()) {
+                Some(count) => count,
+                None => break,
+            };
+            frame += 1;
+
+            let atoms: Vec<Atom> = (&mut lines).take(count).filter_map(read_atom).collect();
+            lines.next();
+
+            if atoms.len() != count {
+                skipped.push(format!("frame {} has malformed atom lines", frame));
+                continue;
+            }
+
+            match molecule.as_mut() {
+                Some(molecule) => {
+                    if !molecule.push_frame(atoms.iter().map(|atom| atom.position).collect()) {
+                        skipped.push(format!("frame {} has {} atoms instead of {}", frame, count, molecule.atoms.len()));
+                    }
+                }
+                None => molecule = Some(Molecule::new(atoms)),
+            }
+        }
+
+        let mut molecule = molecule.unwrap_or_default();
+        molecule.warnings.extend(skipped_warning("frames", &skipped));
+
+        molecule
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_are_read_in_angstroms() {
+        let content = "\
+water, t= 0.0
+    3
+    1SOL     OW    1   0.126   1.624   1.679
+    1SOL    HW1    2   0.190   1.661   1.747
+    1SOL    HW2    3   0.177   1.568   1.613
+   1.86206   1.86206   1.86206
+water, t= 1.0
+    2
+    1SOL     OW    1   0.226   1.624   1.679
+    1SOL    HW1    2   0.290   1.661   1.747
+   1.86206   1.86206   1.86206
+water, t= 2.0
+    3
+    1SOL     OW    1   0.326   1.624   1.679
+    1SOL    HW1    2   0.390   1.661   1.747
+    1SOL    HW2    3   0.377   1.568   1.613
+   1.86206   1.86206   1.86206
+";
+        let molecule = GroReader.read(content);
+        let atom = &molecule.atoms[1];
+        assert_eq!((atom.residue_id, atom.residue_name.as_str(), atom.name.as_str()), (1, "SOL", "HW1"));
+        assert_eq!(atom.element, "H");
+        assert!(glm::distance(&atom.position, &glm::vec3(1.9, 16.61, 17.47)) < 1.0e-4);
+
+        // Frame with a different number of atoms is skipped
+        assert!(!molecule.ensemble);
+        assert_eq!(molecule.frame_count(), 2);
+        assert!(glm::distance(&molecule.frames[1][0], &glm::vec3(3.26, 16.24, 16.79)) < 1.0e-4);
+        assert_eq!(
+            molecule.warnings,
+            vec!["1 frames are skipped: frame 2 has 2 atoms instead of 3".to_string()]
+        );
+    }
+}
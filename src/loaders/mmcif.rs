@@ -0,0 +1,612 @@
+//!
+//! Reader of the [PDBx/mmCIF format](https://mmcif.wwpdb.org/).
+//!
+
+use super::Reader;
+use crate::assembly::{Assembly, AssemblyPart};
+use crate::crystal::{parse_operation, Crystal};
+use crate::molecule::{Atom, Molecule};
+use crate::radii::element_from_atom_name;
+use nalgebra_glm as glm;
+use std::collections::HashMap;
+
+///
+/// Data block of a CIF file. Every tag, e.g. `_atom_site.Cartn_x`, maps to Its values.
+/// Tags outside of loops have exactly one value, tags of a loop have one value per row.
+///
+pub struct Block {
+    values: HashMap<String, Vec<String>>,
+}
+
+impl Block {
+    ///
+    /// Parses the first data block of a CIF file.
+    ///
+    pub fn parse(content: &str) -> Self {
+        let tokens = tokenize(content);
+        let mut values: HashMap<String, Vec<String>> = HashMap::new();
+
+        let mut i = 0;
+        let mut in_block = false;
+        while i < tokens.len() {
+            let token = &tokens[i];
+            if token.is_reserved("data_") {
+                if in_block {
+                    break;
+                }
+                in_block = true;
+                i += 1;
+            } else if token.is_reserved("loop_") {
+                i += 1;
+
+                let mut tags = Vec::new();
+                while i < tokens.len() && tokens[i].is_tag() {
+                    tags.push(tokens[i].value.to_ascii_lowercase());
+                    i += 1;
+                }
+
+                let mut column = 0;
+                while i < tokens.len() && !tokens[i].is_keyword() && !tags.is_empty() {
+                    values.entry(tags[column].clone()).or_default().push(tokens[i].value.clone());
+                    column = (column + 1) % tags.len();
+                    i += 1;
+                }
+            } else if token.is_tag() {
+                if i + 1 < tokens.len() {
+                    values.insert(token.value.to_ascii_lowercase(), vec![tokens[i + 1].value.clone()]);
+                }
+                i += 2;
+            } else {
+                i += 1;
+            }
+        }
+
+        Self { values }
+    }
+
+    ///
+    /// Returns values of a tag. Tags are case insensitive.
+    ///
+    pub fn values(&self, tag: &str) -> Option<&Vec<String>> {
+        self.values.get(&tag.to_ascii_lowercase())
+    }
+
+    ///
+    /// Returns values of the first tag that is present. Used for `auth_*` tags with `label_*` fallbacks.
+    ///
+    pub fn first_values(&self, tags: &[&str]) -> Option<&Vec<String>> {
+        tags.iter().find_map(|tag| self.values(tag))
+    }
+}
+
+///
+/// Token of CIF content. Quoted strings and text fields are values, even if they look like tags or reserved words.
+///
+struct Token {
+    value: String,
+    quoted: bool,
+}
+
+impl Token {
+    fn is_tag(&self) -> bool {
+        !self.quoted && self.value.starts_with('_')
+    }
+
+    /// Returns `true` if the token starts with the reserved word, e.g. `data_` of `data_1ABC`. Reserved words are case insensitive.
+    fn is_reserved(&self, word: &str) -> bool {
+        !self.quoted
+            && self
+                .value
+                .get(..word.len())
+                .map_or(false, |prefix| prefix.eq_ignore_ascii_case(word))
+    }
+
+    /// Returns `true` for tags and reserved words, which terminate values of a loop.
+    fn is_keyword(&self) -> bool {
+        self.is_tag() || self.is_reserved("loop_") || self.is_reserved("data_") || self.is_reserved("save_")
+    }
+}
+
+/// Returns `true` for values denoting missing (`?`) or inapplicable (`.`) data.
+pub fn is_null(value: &str) -> bool {
+    value == "?" || value == "."
+}
+
+///
+/// Splits CIF content into tokens. Handles comments, quoted strings and semicolon-delimited text fields.
+///
+fn tokenize(content: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+
+    let mut lines = content.lines();
+    while let Some(line) = lines.next() {
+        // Multi-line text field
+        if let Some(first_line) = line.strip_prefix(';') {
+            let mut text = first_line.to_string();
+            for line in &mut lines {
+                if line.starts_with(';') {
+                    break;
+                }
+                text.push('\n');
+                text.push_str(line);
+            }
+            tokens.push(Token {
+                value: text.trim().to_string(),
+                quoted: true,
+            });
+            continue;
+        }
+
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            if c.is_whitespace() {
+                i += 1;
+            } else if c == '#' {
+                break;
+            } else if c == '\'' || c == '"' {
+                // Quoted string ends by the quote followed by whitespace or end of the line
+                let start = i + 1;
+                let mut end = start;
+                while end < chars.len() && !(chars[end] == c && (end + 1 == chars.len() || chars[end + 1].is_whitespace())) {
+                    end += 1;
+                }
+                tokens.push(Token {
+                    value: chars[start..end.min(chars.len())].iter().collect(),
+                    quoted: true,
+                });
+                i = end + 1;
+            } else {
+                let start = i;
+                while i < chars.len() && !chars[i].is_whitespace() {
+                    i += 1;
+                }
+                tokens.push(Token {
+                    value: chars[start..i].iter().collect(),
+                    quoted: false,
+                });
+            }
+        }
+    }
+
+    tokens
+}
+
+///
+/// Reader of mmCIF files. Rows of `_atom_site` with malformed coordinates are skipped.
+/// Files with several models, e.g. NMR ensembles, are read as an ensemble with one frame per model.
+/// Biological assemblies are read from `_pdbx_struct_assembly_gen` and the unit cell from `_cell` and `_symmetry`.
+///
+pub struct MmcifReader;
+
+impl Reader for MmcifReader {
+    fn read(&self, content: &str) -> Molecule {
+        let block = Block::parse(content);
+
+        let mut molecule = Molecule::from_models(read_models(&block));
+        molecule.assemblies = read_assemblies(&block);
+        molecule.crystal = read_crystal(&block);
+
+        molecule
+    }
+}
+
+///
+/// Reads atoms of the `_atom_site` category of an already parsed block, split by `pdbx_PDB_model_num`.
+///
+pub fn read_models(block: &Block) -> Vec<Vec<Atom>> {
+    let (x, y, z) = match (
+        block.values("_atom_site.cartn_x"),
+        block.values("_atom_site.cartn_y"),
+        block.values("_atom_site.cartn_z"),
+    ) {
+        (Some(x), Some(y), Some(z)) => (x, y, z),
+        _ => return Vec::new(),
+    };
+    let model_numbers = block.values("_atom_site.pdbx_pdb_model_num");
+
+    let names = block.first_values(&["_atom_site.auth_atom_id", "_atom_site.label_atom_id"]);
+    let residue_names = block.first_values(&["_atom_site.auth_comp_id", "_atom_site.label_comp_id"]);
+    let residue_ids = block.first_values(&["_atom_site.auth_seq_id", "_atom_site.label_seq_id"]);
+    let chains = block.first_values(&["_atom_site.auth_asym_id", "_atom_site.label_asym_id"]);
+    let elements = block.values("_atom_site.type_symbol");
+    let b_factors = block.values("_atom_site.b_iso_or_equiv");
+    let groups = block.values("_atom_site.group_pdb");
+    let alt_locs = block.values("_atom_site.label_alt_id");
+    let occupancies = block.values("_atom_site.occupancy");
+
+    let value = |column: Option<&Vec<String>>, row: usize| -> String {
+        match column.and_then(|column| column.get(row)) {
+            Some(value) if !is_null(value) => value.clone(),
+            _ => String::new(),
+        }
+    };
+
+    let mut models: Vec<Vec<Atom>> = Vec::new();
+    let mut model_number = None;
+    for row in 0..x.len() {
+        let position = match (
+            x[row].parse::<f32>(),
+            y.get(row).map(|y| y.parse::<f32>()),
+            z.get(row).map(|z| z.parse::<f32>()),
+        ) {
+            (Ok(x), Some(Ok(y)), Some(Ok(z))) => glm::vec3(x, y, z),
+            _ => continue,
+        };
+
+        let name = value(names, row);
+        let residue_name = value(residue_names, row);
+        let element = match value(elements, row) {
+            element if element.is_empty() => element_from_atom_name(&name, &residue_name),
+            element => element.to_ascii_uppercase(),
+        };
+
+        let row_model_number = value(model_numbers, row);
+        if model_number.as_ref() != Some(&row_model_number) {
+            models.push(Vec::new());
+            model_number = Some(row_model_number);
+        }
+
+        models.last_mut().unwrap().push(Atom {
+            position,
+            element,
+            name,
+            residue_name,
+            residue_id: value(residue_ids, row).parse().unwrap_or(0),
+            chain: value(chains, row),
+            b_factor: value(b_factors, row).parse().unwrap_or(0.0),
+            hetatm: value(groups, row) == "HETATM",
+            alt_loc: value(alt_locs, row).chars().next(),
+            occupancy: value(occupancies, row).parse().unwrap_or(1.0),
+            ..Default::default()
+        });
+    }
+
+    models
+}
+
+///
+/// Parses an operation expression of `_pdbx_struct_assembly_gen`, e.g. `1`, `1,2,5-8` or `(1-60)(61)`.
+/// Returns a list of operation ids per parenthesized group. Operations of consecutive groups are combined
+/// as products, the rightmost group is applied first.
+///
+fn parse_operation_expression(expression: &str) -> Vec<Vec<String>> {
+    let groups: Vec<&str> = if expression.contains('(') {
+        expression
+            .split(&['(', ')'][..])
+            .map(|group| group.trim())
+            .filter(|group| !group.is_empty())
+            .collect()
+    } else {
+        vec![expression]
+    };
+
+    groups
+        .iter()
+        .map(|group| {
+            let mut ids = Vec::new();
+            for item in group.split(',').map(|item| item.trim()) {
+                let range = item.find('-').and_then(|index| {
+                    let first = item[..index].trim().parse::<i32>().ok()?;
+                    let last = item[index + 1..].trim().parse::<i32>().ok()?;
+                    Some((first, last))
+                });
+
+                match range {
+                    Some((first, last)) => ids.extend((first..=last).map(|id| id.to_string())),
+                    None if !item.is_empty() => ids.push(item.to_string()),
+                    None => {}
+                }
+            }
+            ids
+        })
+        .collect()
+}
+
+///
+/// Reads biological assemblies of an already parsed block from `_pdbx_struct_assembly_gen`
+/// and `_pdbx_struct_oper_list`.
+///
+/// Assemblies list `label_asym_id`s, which are mapped to the chains of the atoms, i.e. `auth_asym_id`s if present.
+///
+pub fn read_assemblies(block: &Block) -> Vec<Assembly> {
+    let value = |tag: &str, row: usize| -> Option<&str> {
+        block
+            .values(tag)
+            .and_then(|values| values.get(row))
+            .map(|value| value.as_str())
+            .filter(|value| !is_null(value))
+    };
+
+    // Operations as homogeneous matrices
+    let mut operations: HashMap<&str, glm::Mat4> = HashMap::new();
+    for (row, id) in block.values("_pdbx_struct_oper_list.id").into_iter().flatten().enumerate() {
+        let mut operation = glm::Mat4::identity();
+        for i in 0..3 {
+            for j in 0..3 {
+                let tag = format!("_pdbx_struct_oper_list.matrix[{}][{}]", i + 1, j + 1);
+                operation[(i, j)] = value(&tag, row).and_then(|value| value.parse().ok()).unwrap_or(operation[(i, j)]);
+            }
+            let tag = format!("_pdbx_struct_oper_list.vector[{}]", i + 1);
+            operation[(i, 3)] = value(&tag, row).and_then(|value| value.parse().ok()).unwrap_or(0.0);
+        }
+        operations.insert(id, operation);
+    }
+
+    // Chains of the atoms belonging to every `label_asym_id`
+    let mut chains: HashMap<&str, &str> = HashMap::new();
+    if let (Some(label_asym_ids), Some(auth_asym_ids)) = (
+        block.values("_atom_site.label_asym_id"),
+        block.first_values(&["_atom_site.auth_asym_id", "_atom_site.label_asym_id"]),
+    ) {
+        for (label_asym_id, auth_asym_id) in label_asym_ids.iter().zip(auth_asym_ids.iter()) {
+            chains.entry(label_asym_id).or_insert(auth_asym_id);
+        }
+    }
+
+    let mut assemblies: Vec<Assembly> = Vec::new();
+    for (row, assembly_id) in block
+        .values("_pdbx_struct_assembly_gen.assembly_id")
+        .into_iter()
+        .flatten()
+        .enumerate()
+    {
+        let expression = value("_pdbx_struct_assembly_gen.oper_expression", row).unwrap_or("");
+        let asym_ids = value("_pdbx_struct_assembly_gen.asym_id_list", row).unwrap_or("");
+
+        // Products of operations of all groups
+        let mut transforms = vec![glm::Mat4::identity()];
+        for group in parse_operation_expression(expression) {
+            let group: Vec<glm::Mat4> = group.iter().filter_map(|id| operations.get(id.as_str()).copied()).collect();
+            transforms = transforms
+                .iter()
+                .flat_map(|transform| group.iter().map(move |operation| transform * operation))
+                .collect();
+        }
+
+        let mut part_chains: Vec<String> = Vec::new();
+        for asym_id in asym_ids.split(',').map(|asym_id| asym_id.trim()) {
+            let chain = chains.get(asym_id).copied().unwrap_or(asym_id).to_string();
+            if !part_chains.contains(&chain) {
+                part_chains.push(chain);
+            }
+        }
+
+        let part = AssemblyPart {
+            chains: part_chains,
+            transforms,
+        };
+        match assemblies.iter_mut().find(|assembly| assembly.name == *assembly_id) {
+            Some(assembly) => assembly.parts.push(part),
+            None => assemblies.push(Assembly {
+                name: assembly_id.clone(),
+                parts: vec![part],
+            }),
+        }
+    }
+
+    // Oligomeric state is appended once all parts are collected, as the name identifies the assembly above
+    for (row, id) in block.values("_pdbx_struct_assembly.id").into_iter().flatten().enumerate() {
+        if let (Some(assembly), Some(details)) = (
+            assemblies.iter_mut().find(|assembly| assembly.name == *id),
+            value("_pdbx_struct_assembly.oligomeric_details", row),
+        ) {
+            assembly.name = format!("{} ({})", id, details.to_ascii_lowercase());
+        }
+    }
+
+    assemblies.retain(|assembly| assembly.parts.iter().any(|part| !part.transforms.is_empty()));
+    assemblies
+}
+
+///
+/// Reads the unit cell of an already parsed block. Symmetry operations are read from `_space_group_symop`
+/// or `_symmetry_equiv` and fall back to the built-in table of space groups.
+///
+pub fn read_crystal(block: &Block) -> Option<Crystal> {
+    let value = |tag: &str| -> Option<f32> { block.values(tag)?.first()?.parse().ok() };
+
+    let lengths = glm::vec3(value("_cell.length_a")?, value("_cell.length_b")?, value("_cell.length_c")?);
+    let angles = glm::vec3(value("_cell.angle_alpha")?, value("_cell.angle_beta")?, value("_cell.angle_gamma")?);
+    let space_group = block
+        .first_values(&["_symmetry.space_group_name_h-m", "_space_group.name_h-m_alt"])
+        .and_then(|values| values.first())
+        .cloned()
+        .unwrap_or_default();
+    let mut crystal = Crystal::new(lengths, angles, space_group)?;
+
+    if let Some(operations) = block.first_values(&["_space_group_symop.operation_xyz", "_symmetry_equiv.pos_as_xyz"]) {
+        let operations: Vec<glm::Mat4> = operations.iter().filter_map(|operation| parse_operation(operation)).collect();
+        if !operations.is_empty() {
+            crystal.operations = operations;
+        }
+    }
+
+    Some(crystal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ATOM_SITE: &str = "\
+data_TEST
+#
+loop_
+_atom_site.group_PDB
+_atom_site.id
+_atom_site.type_symbol
+_atom_site.label_atom_id
+_atom_site.label_alt_id
+_atom_site.label_comp_id
+_atom_site.label_asym_id
+_atom_site.label_seq_id
+_atom_site.Cartn_x
+_atom_site.Cartn_y
+_atom_site.Cartn_z
+_atom_site.occupancy
+_atom_site.B_iso_or_equiv
+_atom_site.auth_seq_id
+_atom_site.auth_asym_id
+_atom_site.pdbx_PDB_model_num
+ATOM   1 N N     . ALA A 1 11.104 6.134 -6.504 1.00 20.00 10  X 1
+ATOM   2 C CA    B ALA A 1 11.639 6.071 -5.147 0.40 21.50 10  X 1
+HETATM 3 ? \"O1'\" . HOH B . 1.000  2.000 3.000  1.00 30.00 201 W 1
+ATOM   1 N N     . ALA A 1 12.104 6.134 -6.504 1.00 20.00 10  X 2
+ATOM   2 C CA    B ALA A 1 12.639 6.071 -5.147 0.40 21.50 10  X 2
+HETATM 3 ? \"O1'\" . HOH B . 2.000  2.000 3.000  1.00 30.00 201 W 2
+#
+";
+
+    #[test]
+    fn auth_values_fall_back_to_label_values() {
+        let molecule = MmcifReader.read(ATOM_SITE);
+        assert_eq!(molecule.atoms.len(), 3);
+
+        // Names have only `label_*` columns, residue ids and chains prefer the `auth_*` ones
+        let summary: Vec<(&str, &str, i32, &str)> = molecule
+            .atoms
+            .iter()
+            .map(|atom| (atom.name.as_str(), atom.residue_name.as_str(), atom.residue_id, atom.chain.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![("N", "ALA", 10, "X"), ("CA", "ALA", 10, "X"), ("O1'", "HOH", 201, "W")]
+        );
+
+        let atom = &molecule.atoms[1];
+        assert_eq!((atom.alt_loc, atom.occupancy, atom.b_factor), (Some('B'), 0.4, 21.5));
+        assert_eq!(molecule.atoms[2].element, "O");
+        assert!(molecule.atoms[2].hetatm);
+
+        // Models are split by `pdbx_PDB_model_num`
+        assert!(molecule.ensemble);
+        assert_eq!(molecule.frame_count(), 2);
+        assert_eq!(molecule.frames[1][2], glm::vec3(2.0, 2.0, 3.0));
+    }
+
+    #[test]
+    fn auth_values_are_preferred() {
+        let content = "\
+data_TEST
+loop_
+_atom_site.label_atom_id
+_atom_site.label_comp_id
+_atom_site.label_asym_id
+_atom_site.auth_atom_id
+_atom_site.auth_comp_id
+_atom_site.auth_asym_id
+_atom_site.Cartn_x
+_atom_site.Cartn_y
+_atom_site.Cartn_z
+C1 LIG C CL1 UNL Z 0.0 0.0 0.0
+";
+        let molecule = MmcifReader.read(content);
+        let atom = &molecule.atoms[0];
+        assert_eq!(
+            (
+                atom.name.as_str(),
+                atom.residue_name.as_str(),
+                atom.chain.as_str(),
+                atom.element.as_str()
+            ),
+            ("CL1", "UNL", "Z", "CL")
+        );
+    }
+
+    #[test]
+    fn quoted_values_are_not_keywords() {
+        let content = "\
+DATA_TEST
+_struct.title '_underscored title'
+_struct.pdbx_descriptor
+;_text field
+;
+LOOP_
+_entity.id
+_entity.pdbx_description
+1 \"_first\"
+2 'loop_'
+data_NEXT
+_struct.title ignored
+";
+        let block = Block::parse(content);
+        assert_eq!(block.values("_struct.title"), Some(&vec!["_underscored title".to_string()]));
+        assert_eq!(block.values("_struct.pdbx_descriptor"), Some(&vec!["_text field".to_string()]));
+        assert_eq!(block.values("_entity.id"), Some(&vec!["1".to_string(), "2".to_string()]));
+        assert_eq!(
+            block.values("_entity.pdbx_description"),
+            Some(&vec!["_first".to_string(), "loop_".to_string()])
+        );
+    }
+
+    #[test]
+    fn operation_expressions_are_expanded() {
+        assert_eq!(parse_operation_expression("1"), vec![vec!["1"]]);
+        assert_eq!(parse_operation_expression("1,2,5-7"), vec![vec!["1", "2", "5", "6", "7"]]);
+        assert_eq!(parse_operation_expression("(1-60)")[0].len(), 60);
+        assert_eq!(parse_operation_expression("(1,2)(3-5)"), vec![vec!["1", "2"], vec!["3", "4", "5"]]);
+        assert_eq!(parse_operation_expression("(X0)(1-2)"), vec![vec!["X0"], vec!["1", "2"]]);
+    }
+
+    #[test]
+    fn assemblies_combine_operation_groups() {
+        let mut content = String::from("data_TEST\nloop_\n_pdbx_struct_oper_list.id\n");
+        for i in 1..=3 {
+            for j in 1..=3 {
+                content += &format!("_pdbx_struct_oper_list.matrix[{}][{}]\n", i, j);
+            }
+        }
+        for i in 1..=3 {
+            content += &format!("_pdbx_struct_oper_list.vector[{}]\n", i);
+        }
+        // Operation 1 rotates by 90 degrees around the z axis, the others translate along the x axis by their id
+        content += "1 0 -1 0 1 0 0 0 0 1 0 0 0\n";
+        for id in 2..=60 {
+            content += &format!("{} 1 0 0 0 1 0 0 0 1 {} 0 0\n", id, id);
+        }
+        content += "\
+loop_
+_pdbx_struct_assembly_gen.assembly_id
+_pdbx_struct_assembly_gen.oper_expression
+_pdbx_struct_assembly_gen.asym_id_list
+1 '(1-60)' A
+2 '(1,2)(3-5)' A,B
+loop_
+_atom_site.label_asym_id
+_atom_site.auth_asym_id
+_atom_site.Cartn_x
+_atom_site.Cartn_y
+_atom_site.Cartn_z
+A X 0.0 0.0 0.0
+B Y 0.0 0.0 1.0
+C Z 5.0 5.0 5.0
+";
+        let molecule = MmcifReader.read(&content);
+        assert_eq!(molecule.assemblies.len(), 2);
+
+        // Chains are mapped from `label_asym_id` to `auth_asym_id`
+        let assembly = &molecule.assemblies[0];
+        assert_eq!(assembly.parts[0].chains, vec!["X"]);
+        assert_eq!(assembly.build(&molecule.atoms).len(), 60);
+
+        // Products of both groups with the right group applied first
+        let assembly = &molecule.assemblies[1];
+        assert_eq!(assembly.parts[0].chains, vec!["X", "Y"]);
+        let atoms = assembly.build(&molecule.atoms);
+        assert_eq!(atoms.len(), 12);
+        let positions: Vec<glm::Vec3> = atoms.iter().filter(|atom| atom.chain == "X").map(|atom| atom.position).collect();
+        let expected = [(0.0, 3.0), (0.0, 4.0), (0.0, 5.0), (5.0, 0.0), (6.0, 0.0), (7.0, 0.0)];
+        assert_eq!(positions.len(), expected.len());
+        for (position, &(x, y)) in positions.iter().zip(expected.iter()) {
+            assert!(
+                glm::distance(position, &glm::vec3(x, y, 0.0)) < 1.0e-5,
+                "{} differs from ({}, {})",
+                position,
+                x,
+                y
+            );
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//!
+//! Module of which each submodule contains a reader of one structure file format.
+//!
+
+pub mod gro;
+pub mod mmcif;
+pub mod mol2;
+pub mod pdb;
+pub mod pqr;
+pub mod sdf;
+pub mod xyz;
+
+use crate::molecule::{AltLocPolicy, Molecule};
+use nalgebra_glm as glm;
+use std::io::Read;
+use std::path::Path;
+
+///
+/// Common interface of all structure file readers.
+///
+pub trait Reader {
+    ///
+    /// Reads a molecule from the content of a file. Radii are left unassigned.
+    ///
+    fn read(&self, content: &str) -> Molecule;
+}
+
+///
+/// Supported structure file formats.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Pdb,
+    Pqr,
+    Gro,
+    Mmcif,
+    Xyz,
+    Mol2,
+    Sdf,
+}
+
+impl Format {
+    ///
+    /// Chooses the format by the extension of the file. Unknown extensions are resolved by looking at the content.
+    /// Extensions of compressed files, e.g. `.pdb.gz`, are skipped.
+    ///
+    pub fn detect(path: &Path, content: &str) -> Self {
+        let path = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("gz") | Some("bz2") => path.file_stem().map(Path::new).unwrap_or(path),
+            _ => path,
+        };
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+
+        match extension.as_str() {
+            "pdb" | "ent" => Format::Pdb,
+            "pqr" => Format::Pqr,
+            "gro" => Format::Gro,
+            "cif" | "mmcif" => Format::Mmcif,
+            "xyz" => Format::Xyz,
+            "mol2" => Format::Mol2,
+            "sdf" | "sd" | "mol" => Format::Sdf,
+            _ => Self::sniff(content),
+        }
+    }
+
+    ///
+    /// Chooses the format by the content of the file.
+    ///
+    pub fn sniff(content: &str) -> Self {
+        let lines: Vec<&str> = content.lines().take(5).collect();
+        let first_line = content
+            .lines()
+            .map(|line| line.trim())
+            .find(|line| !line.is_empty() && !line.starts_with('#'));
+
+        if let Some(line) = first_line {
+            if line.get(..5).map_or(false, |prefix| prefix.eq_ignore_ascii_case("data_")) {
+                return Format::Mmcif;
+            }
+            if line.starts_with("@<TRIPOS>") {
+                return Format::Mol2;
+            }
+        }
+
+        // XYZ starts by the number of atoms
+        if let Some(line) = lines.first() {
+            if line.trim().parse::<usize>().is_ok() {
+                return Format::Xyz;
+            }
+        }
+
+        // Fourth line of molfiles is the counts line
+        if let Some(line) = lines.get(3) {
+            if line.contains("V2000") || line.contains("V3000") {
+                return Format::Sdf;
+            }
+        }
+
+        // PDB2PQR mentions itself in the remarks
+        if lines.iter().any(|line| line.starts_with("REMARK") && line.contains("PQR")) {
+            return Format::Pqr;
+        }
+
+        Format::Pdb
+    }
+
+    ///
+    /// Returns the reader of the format.
+    ///
+    pub fn reader(self) -> &'static dyn Reader {
+        match self {
+            Format::Pdb => &pdb::PdbReader,
+            Format::Pqr => &pqr::PqrReader,
+            Format::Gro => &gro::GroReader,
+            Format::Mmcif => &mmcif::MmcifReader,
+            Format::Xyz => &xyz::XyzReader,
+            Format::Mol2 => &mol2::Mol2Reader,
+            Format::Sdf => &sdf::SdfReader,
+        }
+    }
+}
+
+///
+/// Error of loading a structure file.
+///
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file could not be read.
+    Io(std::io::Error),
+    /// The file looks compressed, but It could not be decompressed.
+    Decompression(std::io::Error),
+    /// The file was read in the given format, but no atoms were found in It.
+    NoAtoms(Format),
+    /// The atom with the given index, counted from one, has a position in some frame or a radius that is not finite.
+    NotFinite(usize),
+    /// The thread loading the file stopped before finishing, e.g. because a reader panicked.
+    Stopped,
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "could not read the file: {}", e),
+            LoadError::Decompression(e) => write!(f, "could not decompress the file: {}", e),
+            LoadError::NoAtoms(format) => write!(f, "no atoms found when reading the file as {:?}", format),
+            LoadError::NotFinite(atom) => write!(f, "atom {} has a position or a radius that is not a finite number", atom),
+            LoadError::Stopped => write!(f, "loading stopped unexpectedly"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+///
+/// Decompresses gzip or bzip2 compressed content, recognized by Its magic bytes. Other content is returned unchanged.
+///
+pub fn decompress(content: Vec<u8>) -> std::io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    if content.starts_with(&[0x1f, 0x8b]) {
+        flate2::read::MultiGzDecoder::new(content.as_slice()).read_to_end(&mut decompressed)?;
+    } else if content.starts_with(b"BZh") {
+        bzip2::read::BzDecoder::new(content.as_slice()).read_to_end(&mut decompressed)?;
+    } else {
+        return Ok(content);
+    }
+
+    Ok(decompressed)
+}
+
+///
+/// Reads a molecule from a structure file of any supported format. Radii are left unassigned.
+/// Alternate conformers are discarded according to the `alt_loc_policy`.
+/// The `progress` is called with the loaded fraction of the file between 0 and 1.
+/// Returns an error if the file cannot be read, contains no atoms or an atom with coordinates that are not finite.
+///
+pub fn load(path: &Path, alt_loc_policy: AltLocPolicy, mut progress: impl FnMut(f32)) -> Result<Molecule, LoadError> {
+    let content = read(path, |read| progress(0.5 * read)).map_err(LoadError::Io)?;
+    let content = decompress(content).map_err(LoadError::Decompression)?;
+    let content = String::from_utf8_lossy(&content);
+    progress(0.6);
+
+    let format = Format::detect(path, &content);
+    let mut molecule = format.reader().read(&content);
+    if molecule.atoms.is_empty() {
+        return Err(LoadError::NoAtoms(format));
+    }
+    if let Some(atom) = first_non_finite_atom(&molecule) {
+        return Err(LoadError::NotFinite(atom + 1));
+    }
+    // Symmetry operations are read from the file or from the built-in table, which has only common space groups
+    if let Some(crystal) = molecule.crystal.as_ref().filter(|crystal| crystal.operations.is_empty()) {
+        let warning = match crystal.space_group.trim() {
+            "" => "space group is not given, symmetry mates cannot be shown".to_string(),
+            space_group => format!("space group {} is not supported, symmetry mates cannot be shown", space_group),
+        };
+        molecule.warnings.push(warning);
+    }
+    progress(0.9);
+    molecule.apply_alt_loc_policy(alt_loc_policy);
+    progress(1.0);
+
+    Ok(molecule)
+}
+
+///
+/// Returns the index of the first atom with a position in any frame or a file radius that is not finite.
+///
+fn first_non_finite_atom(molecule: &Molecule) -> Option<usize> {
+    let is_finite = |position: &glm::Vec3| position.iter().all(|value| value.is_finite());
+
+    let atoms = molecule
+        .atoms
+        .iter()
+        .position(|atom| !is_finite(&atom.position) || atom.file_radius.map_or(false, |radius| !radius.is_finite()));
+    let frames = molecule
+        .frames
+        .iter()
+        .filter_map(|frame| frame.iter().position(|position| !is_finite(position)));
+
+    atoms.into_iter().chain(frames).min()
+}
+
+///
+/// Reads a whole file in chunks and reports the read fraction of It after each chunk.
+///
+fn read(path: &Path, mut progress: impl FnMut(f32)) -> std::io::Result<Vec<u8>> {
+    const CHUNK_SIZE: usize = 1 << 20;
+
+    let mut file = std::fs::File::open(path)?;
+    let length = file.metadata()?.len() as usize;
+    let mut content = Vec::with_capacity(length);
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+        let read = match file.read(&mut chunk) {
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if read == 0 {
+            break;
+        }
+        content.extend_from_slice(&chunk[..read]);
+        progress((content.len() as f32 / length.max(1) as f32).min(1.0));
+    }
+
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_without_extension_is_sniffed() {
+        let contents = [
+            ("# comment\ndata_1ABC\n_entry.id 1ABC\n", Format::Mmcif),
+            ("DATA_1ABC\n", Format::Mmcif),
+            ("@<TRIPOS>MOLECULE\nbenzene\n", Format::Mol2),
+            ("3\nwater\nO 0.0 0.0 0.0\nH 0.96 0.0 0.0\nH -0.24 0.93 0.0\n", Format::Xyz),
+            ("water\n  program\n\n  3  2  0  0  0  0  0  0  0  0999 V2000\n", Format::Sdf),
+            ("water\n  program\n\n  0  0  0     0  0            999 V3000\n", Format::Sdf),
+            (
+                "REMARK   1 PQR file generated by PDB2PQR\nATOM      1  N   ALA A   1\n",
+                Format::Pqr,
+            ),
+            ("HEADER    PROTEIN\nATOM      1  N   ALA A   1\n", Format::Pdb),
+            ("", Format::Pdb),
+        ];
+
+        for (content, format) in contents.iter() {
+            assert_eq!(Format::sniff(content), *format, "{:?}", content);
+            assert_eq!(Format::detect(Path::new("structure"), content), *format, "{:?}", content);
+        }
+    }
+
+    #[test]
+    fn extension_takes_precedence_over_content() {
+        let content = "3\nwater\nO 0.0 0.0 0.0\n";
+        assert_eq!(Format::detect(Path::new("water.xyz"), content), Format::Xyz);
+        assert_eq!(Format::detect(Path::new("1ABC.CIF"), content), Format::Mmcif);
+        assert_eq!(Format::detect(Path::new("1abc.pdb.gz"), content), Format::Pdb);
+        assert_eq!(Format::detect(Path::new("frames.gro.bz2"), content), Format::Gro);
+        assert_eq!(Format::detect(Path::new("water.gz"), content), Format::Xyz);
+    }
+}
@@ -0,0 +1,107 @@
+//!
+//! Reader of the [Tripos MOL2 format](http://chemyang.ccnu.edu.cn/ccb/server/AIMMS/mol2.pdf).
+//!
+
+use super::Reader;
+use crate::molecule::{Atom, Molecule};
+use nalgebra_glm as glm;
+
+///
+/// Parses one line of the `ATOM` record in the format `id name x y z type [residue_id [residue_name [charge ...]]]`.
+/// Returns `None` if the line is malformed.
+///
+fn read_atom(line: &str) -> Option<Atom> {
+    let columns: Vec<&str> = line.split_whitespace().collect();
+    if columns.len() < 6 {
+        return None;
+    }
+
+    let x = columns[2].parse::<f32>().ok()?;
+    let y = columns[3].parse::<f32>().ok()?;
+    let z = columns[4].parse::<f32>().ok()?;
+
+    // Sybyl atom types are `element.hybridization`, e.g. `C.ar` or `N.3`
+    let element = columns[5].split('.').next().unwrap_or("").to_ascii_uppercase();
+
+    // Substructure names usually append the residue number to the residue name, e.g. `ALA12`
+    let residue_name = columns
+        .get(7)
+        .map(|name| name.trim_end_matches(|c: char| c.is_ascii_digit()).to_string())
+        .unwrap_or_default();
+
+    Some(Atom {
+        position: glm::vec3(x, y, z),
+        element,
+        name: columns[1].to_string(),
+        residue_name,
+        residue_id: columns.get(6).and_then(|id| id.parse().ok()).unwrap_or(0),
+        charge: columns.get(8).and_then(|charge| charge.parse().ok()),
+        ..Default::default()
+    })
+}
+
+///
+/// Reader of MOL2 files. Only the first molecule is read and malformed atom lines are skipped.
+///
+pub struct Mol2Reader;
+
+impl Reader for Mol2Reader {
+    fn read(&self, content: &str) -> Molecule {
+        let mut atoms = Vec::new();
+        let mut molecules = 0;
+        let mut in_atoms = false;
+        for line in content.lines() {
+            let line = line.trim();
+            if line.starts_with("@<TRIPOS>") {
+                if line == "@<TRIPOS>MOLECULE" {
+                    molecules += 1;
+                    if molecules > 1 {
+                        break;
+                    }
+                }
+                in_atoms = line == "@<TRIPOS>ATOM";
+                continue;
+            }
+
+            if in_atoms && !line.is_empty() && !line.starts_with('#') {
+                if let Some(atom) = read_atom(line) {
+                    atoms.push(atom);
+                }
+            }
+        }
+
+        Molecule::new(atoms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn atoms_of_first_molecule_are_read() {
+        let content = "\
+# comment
+@<TRIPOS>MOLECULE
+alanine
+@<TRIPOS>ATOM
+      1 N          -0.9660    1.1780    0.0000 N.3       1 ALA12      -0.3000
+      2 CA          0.2580    0.3940    0.0000 C.3       1 ALA12       0.1000
+      3 CB
+@<TRIPOS>BOND
+     1     1     2    1
+@<TRIPOS>MOLECULE
+second
+@<TRIPOS>ATOM
+      1 O           0.0000    0.0000    0.0000 O.2
+";
+        let molecule = Mol2Reader.read(content);
+        assert_eq!(molecule.atoms.len(), 2);
+
+        let atom = &molecule.atoms[1];
+        assert_eq!((atom.name.as_str(), atom.element.as_str()), ("CA", "C"));
+        assert_eq!((atom.residue_name.as_str(), atom.residue_id), ("ALA", 1));
+        assert_eq!(atom.position, glm::vec3(0.258, 0.394, 0.0));
+        assert_eq!(atom.charge, Some(0.1));
+    }
+}
@@ -0,0 +1,316 @@
+//!
+//! Reader of the fixed-column [PDB format](https://www.wwpdb.org/documentation/file-format).
+//!
+
+use super::Reader;
+use crate::assembly::{Assembly, AssemblyPart};
+use crate::crystal::Crystal;
+use crate::molecule::{Atom, Molecule};
+use crate::radii::element_from_pdb_name;
+use nalgebra_glm as glm;
+
+///
+/// Returns trimmed content of 1-based inclusive columns `start..=end` of a record.
+/// Lines are often shorter than the specification, missing columns are empty.
+///
+fn columns(line: &str, start: usize, end: usize) -> &str {
+    let end = end.min(line.len());
+    if start > end {
+        return "";
+    }
+
+    line.get(start - 1..end).unwrap_or("").trim()
+}
+
+///
+/// Parses one `ATOM` or `HETATM` record. Returns `None` if the coordinates are malformed.
+///
+fn read_atom(line: &str) -> Option<Atom> {
+    let x = columns(line, 31, 38).parse::<f32>().ok()?;
+    let y = columns(line, 39, 46).parse::<f32>().ok()?;
+    let z = columns(line, 47, 54).parse::<f32>().ok()?;
+
+    let name = columns(line, 13, 16).to_string();
+    let residue_name = columns(line, 18, 20).to_string();
+    // Element columns are missing in old files, then the justification of the name tells two letter symbols apart
+    let element = match columns(line, 77, 78) {
+        "" => element_from_pdb_name(line.get(12..16.min(line.len())).unwrap_or(""), &residue_name),
+        element => element.to_ascii_uppercase(),
+    };
+
+    Some(Atom {
+        position: glm::vec3(x, y, z),
+        element,
+        name,
+        residue_name,
+        residue_id: columns(line, 23, 26).parse().unwrap_or(0),
+        chain: columns(line, 22, 22).to_string(),
+        b_factor: columns(line, 61, 66).parse().unwrap_or(0.0),
+        hetatm: line.starts_with("HETATM"),
+        alt_loc: columns(line, 17, 17).chars().next(),
+        occupancy: columns(line, 55, 60).parse().unwrap_or(1.0),
+        ..Default::default()
+    })
+}
+
+///
+/// Parses biological assemblies from `REMARK 350` records, e.g.
+///
+/// ```text
+/// REMARK 350 BIOMOLECULE: 1
+/// REMARK 350 AUTHOR DETERMINED BIOLOGICAL UNIT: DIMERIC
+/// REMARK 350 APPLY THE FOLLOWING TO CHAINS: A, B
+/// REMARK 350   BIOMT1   1  1.000000  0.000000  0.000000        0.00000
+/// REMARK 350   BIOMT2   1  0.000000  1.000000  0.000000        0.00000
+/// REMARK 350   BIOMT3   1  0.000000  0.000000  1.000000        0.00000
+/// ```
+///
+fn read_assemblies(content: &str) -> Vec<Assembly> {
+    let mut assemblies: Vec<Assembly> = Vec::new();
+    let mut chains_continued = false;
+
+    for line in content.lines().filter(|line| line.starts_with("REMARK 350")) {
+        let remark = line.get(10..).unwrap_or("").trim();
+
+        if let Some(id) = remark.strip_prefix("BIOMOLECULE:") {
+            assemblies.push(Assembly {
+                name: id.trim().to_string(),
+                parts: Vec::new(),
+            });
+            continue;
+        }
+        let assembly = match assemblies.last_mut() {
+            Some(assembly) => assembly,
+            None => continue,
+        };
+
+        let chains = if let Some(chains) = remark.strip_prefix("APPLY THE FOLLOWING TO CHAINS:") {
+            assembly.parts.push(AssemblyPart {
+                chains: Vec::new(),
+                transforms: Vec::new(),
+            });
+            Some(chains)
+        } else if chains_continued {
+            remark.strip_prefix("AND CHAINS:")
+        } else {
+            None
+        };
+        if let (Some(chains), Some(part)) = (chains, assembly.parts.last_mut()) {
+            part.chains.extend(
+                chains
+                    .split(',')
+                    .map(|chain| chain.trim().to_string())
+                    .filter(|chain| !chain.is_empty()),
+            );
+            chains_continued = true;
+            continue;
+        }
+        chains_continued = false;
+
+        if remark.starts_with("BIOMT") {
+            let row = columns(line, 19, 19).parse::<usize>().unwrap_or(0);
+            let values: Vec<f32> = remark.split_whitespace().skip(2).filter_map(|value| value.parse().ok()).collect();
+            let part = match assembly.parts.last_mut() {
+                Some(part) if (1..=3).contains(&row) && values.len() == 4 => part,
+                _ => continue,
+            };
+
+            if row == 1 {
+                part.transforms.push(glm::Mat4::identity());
+            }
+            if let Some(transform) = part.transforms.last_mut() {
+                for (column, value) in values.iter().enumerate() {
+                    transform[(row - 1, column)] = *value;
+                }
+            }
+        } else if let Some(description) = remark
+            .strip_prefix("AUTHOR DETERMINED BIOLOGICAL UNIT:")
+            .or_else(|| remark.strip_prefix("SOFTWARE DETERMINED QUATERNARY STRUCTURE:"))
+        {
+            // Author determined unit comes first and is preferred
+            if assembly.parts.is_empty() && !assembly.name.contains('(') {
+                assembly.name = format!("{} ({})", assembly.name, description.trim().to_ascii_lowercase());
+            }
+        }
+    }
+
+    assemblies.retain(|assembly| assembly.parts.iter().any(|part| !part.transforms.is_empty()));
+    assemblies
+}
+
+///
+/// Parses the unit cell from the `CRYST1` record. Symmetry operations are read from `REMARK 290 SMTRY` records,
+/// which have the same format as `BIOMT` records, and fall back to the built-in table of space groups.
+///
+fn read_crystal(content: &str) -> Option<Crystal> {
+    let line = content.lines().find(|line| line.starts_with("CRYST1"))?;
+    let value = |start, end| columns(line, start, end).parse::<f32>().ok();
+
+    let lengths = glm::vec3(value(7, 15)?, value(16, 24)?, value(25, 33)?);
+    let angles = glm::vec3(value(34, 40)?, value(41, 47)?, value(48, 54)?);
+    let mut crystal = Crystal::new(lengths, angles, columns(line, 56, 66).to_string())?;
+
+    let mut operations: Vec<glm::Mat4> = Vec::new();
+    for line in content.lines().filter(|line| line.starts_with("REMARK 290   SMTRY")) {
+        let row = columns(line, 19, 19).parse::<usize>().unwrap_or(0);
+        let values: Vec<f32> = line
+            .get(19..)
+            .unwrap_or("")
+            .split_whitespace()
+            .skip(1)
+            .filter_map(|value| value.parse().ok())
+            .collect();
+        if !(1..=3).contains(&row) || values.len() != 4 {
+            continue;
+        }
+
+        if row == 1 {
+            operations.push(glm::Mat4::identity());
+        }
+        if let Some(operation) = operations.last_mut() {
+            for (column, value) in values.iter().enumerate() {
+                operation[(row - 1, column)] = *value;
+            }
+        }
+    }
+    if !operations.is_empty() {
+        crystal.set_cartesian_operations(&operations);
+    }
+
+    Some(crystal)
+}
+
+///
+/// Reader of PDB files. Malformed atom records are skipped.
+///
+/// Files with several `MODEL`s, e.g. NMR ensembles, are read as an ensemble with one frame per model.
+/// Models with a different number of atoms than the first one are skipped.
+/// Biological assemblies are read from `REMARK 350`, the unit cell from `CRYST1` and symmetry operations from
+/// `REMARK 290` or the built-in table of space groups.
+///
+pub struct PdbReader;
+
+impl Reader for PdbReader {
+    fn read(&self, content: &str) -> Molecule {
+        let mut models: Vec<Vec<Atom>> = vec![Vec::new()];
+        for line in content.lines() {
+            if line.starts_with("ENDMDL") {
+                models.push(Vec::new());
+            } else if line.starts_with("ATOM") || line.starts_with("HETATM") {
+                if let Some(atom) = read_atom(line) {
+                    models.last_mut().unwrap().push(atom);
+                }
+            }
+        }
+
+        let mut molecule = Molecule::from_models(models);
+        molecule.assemblies = read_assemblies(content);
+        molecule.crystal = read_crystal(content);
+
+        molecule
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENSEMBLE: &str = "\
+HEADER    TEST ENSEMBLE
+MODEL        1
+ATOM      1  N   ALA A   1      11.104   6.134  -6.504  1.00 20.00           N
+ATOM      2  CA AALA A   1    -100.000-200.500-300.250  0.50 21.50           C
+ATOM      3  C   ALA A   1      malformed coordinates
+HETATM    4 CA    CA B 101       1.000   2.500   3.250  1.00 30.00
+ENDMDL
+MODEL        2
+ATOM      1  N   ALA A   1      12.104   6.134  -6.504  1.00 20.00           N
+ATOM      2  CA AALA A   1    -101.000-200.500-300.250  0.50 21.50           C
+HETATM    4 CA    CA B 101       2.000   2.500   3.250  1.00 30.00
+ENDMDL
+END
+";
+
+    #[test]
+    fn fixed_columns_are_parsed() {
+        let molecule = PdbReader.read(ENSEMBLE);
+        assert_eq!(molecule.atoms.len(), 3);
+
+        // Coordinates without spaces between them
+        let atom = &molecule.atoms[1];
+        assert_eq!(atom.position, glm::vec3(-100.0, -200.5, -300.25));
+        assert_eq!(
+            (atom.name.as_str(), atom.residue_name.as_str(), atom.chain.as_str(), atom.residue_id),
+            ("CA", "ALA", "A", 1)
+        );
+        assert_eq!((atom.alt_loc, atom.occupancy, atom.b_factor), (Some('A'), 0.5, 21.5));
+        assert_eq!(atom.element, "C");
+        assert!(!atom.hetatm);
+
+        // Record ending before the element columns
+        let atom = &molecule.atoms[2];
+        assert_eq!((atom.element.as_str(), atom.chain.as_str(), atom.residue_id), ("CA", "B", 101));
+        assert!(atom.hetatm);
+    }
+
+    #[test]
+    fn models_are_split_by_endmdl() {
+        let molecule = PdbReader.read(ENSEMBLE);
+        assert!(molecule.ensemble);
+        assert_eq!(molecule.frame_count(), 2);
+        assert_eq!(molecule.frames[0][0], glm::vec3(11.104, 6.134, -6.504));
+        assert_eq!(molecule.frames[1][0], glm::vec3(12.104, 6.134, -6.504));
+        assert_eq!(molecule.frames[1][2], glm::vec3(2.0, 2.5, 3.25));
+
+        // Model with a missing atom
+        let content = ENSEMBLE.replace("ATOM      2  CA AALA A   1    -101.000", "REMARK");
+        let molecule = PdbReader.read(&content);
+        assert!(!molecule.ensemble);
+        assert_eq!(molecule.frame_count(), 1);
+        assert_eq!(
+            molecule.warnings,
+            vec!["1 models are skipped: model 2 has 2 atoms instead of 3".to_string()]
+        );
+
+        // Single model without MODEL records
+        let single: String = ENSEMBLE
+            .lines()
+            .take(6)
+            .filter(|line| !line.starts_with("MODEL"))
+            .map(|line| line.to_string() + "\n")
+            .collect();
+        let molecule = PdbReader.read(&single);
+        assert!(!molecule.ensemble);
+        assert_eq!((molecule.atoms.len(), molecule.frame_count()), (3, 1));
+    }
+
+    #[test]
+    fn biomt_records_are_read() {
+        let content = "\
+REMARK 350 BIOMOLECULE: 1
+REMARK 350 AUTHOR DETERMINED BIOLOGICAL UNIT: DIMERIC
+REMARK 350 SOFTWARE DETERMINED QUATERNARY STRUCTURE: TETRAMERIC
+REMARK 350 APPLY THE FOLLOWING TO CHAINS: A, B,
+REMARK 350                    AND CHAINS: C
+REMARK 350   BIOMT1   1  1.000000  0.000000  0.000000        0.00000
+REMARK 350   BIOMT2   1  0.000000  1.000000  0.000000        0.00000
+REMARK 350   BIOMT3   1  0.000000  0.000000  1.000000        0.00000
+REMARK 350   BIOMT1   2 -1.000000  0.000000  0.000000       10.00000
+REMARK 350   BIOMT2   2  0.000000 -1.000000  0.000000        0.00000
+REMARK 350   BIOMT3   2  0.000000  0.000000  1.000000        0.00000
+REMARK 350 BIOMOLECULE: 2
+REMARK 350 APPLY THE FOLLOWING TO CHAINS: D
+ATOM      1  CA  ALA A   1       1.000   2.000   3.000  1.00  0.00           C
+ATOM      2  CA  ALA D   1       1.000   2.000   3.000  1.00  0.00           C
+";
+        let molecule = PdbReader.read(content);
+        // Assemblies without transforms are dropped
+        assert_eq!(molecule.assemblies.len(), 1);
+
+        let assembly = &molecule.assemblies[0];
+        assert_eq!(assembly.name, "1 (dimeric)");
+        assert_eq!(assembly.parts[0].chains, vec!["A", "B", "C"]);
+        let positions: Vec<glm::Vec3> = assembly.build(&molecule.atoms).iter().map(|atom| atom.position).collect();
+        assert_eq!(positions, vec![glm::vec3(1.0, 2.0, 3.0), glm::vec3(9.0, -2.0, 3.0)]);
+    }
+}
@@ -0,0 +1,110 @@
+//!
+//! Reader of the [PQR format](https://pdb2pqr.readthedocs.io/en/latest/formats/pqr.html) produced by PDB2PQR.
+//!
+
+use super::Reader;
+use crate::molecule::{Atom, Molecule};
+use crate::radii::element_from_atom_name;
+use nalgebra_glm as glm;
+
+///
+/// Parses one `ATOM` or `HETATM` record. Unlike PDB the fields are whitespace-separated in the format
+/// `record serial name residue_name [chain] residue_id x y z charge radius`.
+/// Returns `None` if the record is malformed.
+///
+fn read_atom(line: &str) -> Option<Atom> {
+    let columns: Vec<&str> = line.split_whitespace().collect();
+    if columns.len() < 10 {
+        return None;
+    }
+
+    let n = columns.len();
+    let radius = columns[n - 1].parse::<f32>().ok()?;
+    let charge = columns[n - 2].parse::<f32>().ok()?;
+    let z = columns[n - 3].parse::<f32>().ok()?;
+    let y = columns[n - 4].parse::<f32>().ok()?;
+    let x = columns[n - 5].parse::<f32>().ok()?;
+
+    let name = columns[2].to_string();
+    let residue_name = columns[3].to_string();
+    let chain = if n >= 11 { columns[4].to_string() } else { String::new() };
+
+    Some(Atom {
+        position: glm::vec3(x, y, z),
+        element: element_from_atom_name(&name, &residue_name),
+        name,
+        residue_name,
+        residue_id: columns[n - 6].parse().unwrap_or(0),
+        chain,
+        hetatm: line.starts_with("HETATM"),
+        charge: Some(charge),
+        file_radius: Some(radius),
+        ..Default::default()
+    })
+}
+
+///
+/// Reader of PQR files. Radii and charges are taken from the file. Malformed atom records are skipped.
+/// Radii that are zero or negative are dropped with a warning, so such atoms get radii like atoms of other formats.
+///
+pub struct PqrReader;
+
+impl Reader for PqrReader {
+    fn read(&self, content: &str) -> Molecule {
+        let mut atoms: Vec<Atom> = content
+            .lines()
+            .filter(|line| line.starts_with("ATOM") || line.starts_with("HETATM"))
+            .filter_map(read_atom)
+            .collect();
+
+        // Some force fields give hydrogen atoms no radius
+        let mut not_positive = 0;
+        for atom in atoms
+            .iter_mut()
+            .filter(|atom| atom.file_radius.map_or(false, |radius| radius <= 0.0))
+        {
+            atom.file_radius = None;
+            not_positive += 1;
+        }
+
+        let mut molecule = Molecule::new(atoms);
+        if not_positive > 0 {
+            molecule.warnings.push(format!(
+                "{} atoms have a radius that is not positive, radii of the radii table or of their elements are used for them",
+                not_positive
+            ));
+        }
+
+        molecule
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn radii_must_be_positive() {
+        let content = "\
+REMARK   1 PQR file generated by PDB2PQR
+ATOM      1  N   ALA A   1      11.104   6.134  -6.504 -0.3000 1.8240
+ATOM      2  H   ALA     1      11.504   6.934  -6.904  0.3000 0.0000
+ATOM      3  OG  SER A   2      12.000   7.000  -7.000 -0.6500 -1.0000
+ATOM      4  HG  SER A   2      12.500   7.500  -7.500
+";
+        let molecule = PqrReader.read(content);
+        assert_eq!(molecule.atoms.len(), 3);
+
+        let atom = &molecule.atoms[0];
+        assert_eq!((atom.chain.as_str(), atom.residue_id, atom.element.as_str()), ("A", 1, "N"));
+        assert_eq!((atom.charge, atom.file_radius), (Some(-0.3), Some(1.824)));
+
+        // Records without a chain
+        assert_eq!((molecule.atoms[1].chain.as_str(), molecule.atoms[1].residue_id), ("", 1));
+
+        let radii: Vec<Option<f32>> = molecule.atoms.iter().map(|atom| atom.file_radius).collect();
+        assert_eq!(radii, vec![Some(1.824), None, None]);
+        assert_eq!(molecule.warnings.len(), 1);
+        assert!(molecule.warnings[0].starts_with("2 atoms"));
+    }
+}
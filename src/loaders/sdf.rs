@@ -0,0 +1,134 @@
+//!
+//! Reader of the [MDL molfile and SDF formats](https://en.wikipedia.org/wiki/Chemical_table_file), both V2000 and V3000.
+//!
+
+use super::Reader;
+use crate::molecule::{Atom, Molecule};
+use nalgebra_glm as glm;
+
+///
+/// Creates an atom named by Its element from the columns of an atom line. Returns `None` if a coordinate is malformed.
+///
+fn atom(element: &str, x: &str, y: &str, z: &str) -> Option<Atom> {
+    let element = element.to_ascii_uppercase();
+
+    Some(Atom {
+        position: glm::vec3(x.parse().ok()?, y.parse().ok()?, z.parse().ok()?),
+        name: element.clone(),
+        element,
+        ..Default::default()
+    })
+}
+
+///
+/// Reads the atom block of a V2000 molfile. Atom lines have the format `x y z element ...`.
+///
+fn read_v2000(lines: &[&str]) -> Vec<Atom> {
+    let count = lines
+        .get(3)
+        .and_then(|line| line.get(0..3))
+        .and_then(|count| count.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+
+    lines
+        .iter()
+        .skip(4)
+        .take(count)
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            if columns.len() < 4 {
+                return None;
+            }
+
+            atom(columns[3], columns[0], columns[1], columns[2])
+        })
+        .collect()
+}
+
+///
+/// Reads the atom block of a V3000 molfile. Atom lines have the format `M  V30 index element x y z ...`.
+///
+fn read_v3000(lines: &[&str]) -> Vec<Atom> {
+    lines
+        .iter()
+        .skip_while(|line| !line.starts_with("M  V30 BEGIN ATOM"))
+        .skip(1)
+        .take_while(|line| !line.starts_with("M  V30 END ATOM"))
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            if columns.len() < 7 {
+                return None;
+            }
+
+            atom(columns[3], columns[4], columns[5], columns[6])
+        })
+        .collect()
+}
+
+///
+/// Reader of molfiles and SDF files. Only the first molecule is read and malformed atom lines are skipped.
+///
+pub struct SdfReader;
+
+impl Reader for SdfReader {
+    fn read(&self, content: &str) -> Molecule {
+        let lines: Vec<&str> = content.lines().take_while(|line| !line.starts_with("$$$$")).collect();
+
+        let atoms = match lines.get(3) {
+            Some(counts) if counts.contains("V3000") => read_v3000(&lines),
+            _ => read_v2000(&lines),
+        };
+
+        Molecule::new(atoms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn v2000_atoms_are_read() {
+        let content = "\
+chloromethane
+  program
+
+  2  1  0  0  0  0  0  0  0  0999 V2000
+    0.0000    0.0000    0.0000 C   0  0  0  0  0  0  0  0  0  0  0  0
+    1.7810   -0.1000    0.2500 Cl  0  0  0  0  0  0  0  0  0  0  0  0
+    9.0000    9.0000    9.0000 O   0  0  0  0  0  0  0  0  0  0  0  0
+  1  2  1  0
+M  END
+$$$$
+second
+";
+        let molecule = SdfReader.read(content);
+        let atoms: Vec<(&str, glm::Vec3)> = molecule.atoms.iter().map(|atom| (atom.element.as_str(), atom.position)).collect();
+        assert_eq!(atoms, vec![("C", glm::vec3(0.0, 0.0, 0.0)), ("CL", glm::vec3(1.781, -0.1, 0.25))]);
+    }
+
+    #[test]
+    fn v3000_atoms_are_read() {
+        let content = "\
+chloromethane
+  program
+
+  0  0  0     0  0            999 V3000
+M  V30 BEGIN CTAB
+M  V30 COUNTS 2 1 0 0 0
+M  V30 BEGIN ATOM
+M  V30 1 C 0.0 0.0 0.0 0
+M  V30 2 Cl 1.781 -0.1 0.25 0
+M  V30 3 O malformed
+M  V30 END ATOM
+M  V30 BEGIN BOND
+M  V30 1 1 1 2
+M  V30 END BOND
+M  V30 END CTAB
+M  END
+";
+        let molecule = SdfReader.read(content);
+        let atoms: Vec<(&str, glm::Vec3)> = molecule.atoms.iter().map(|atom| (atom.element.as_str(), atom.position)).collect();
+        assert_eq!(atoms, vec![("C", glm::vec3(0.0, 0.0, 0.0)), ("CL", glm::vec3(1.781, -0.1, 0.25))]);
+    }
+}
@@ -0,0 +1,61 @@
+//!
+//! Reader of the [XYZ format](https://en.wikipedia.org/wiki/XYZ_file_format).
+//!
+
+use super::Reader;
+use crate::molecule::{Atom, Molecule};
+use nalgebra_glm as glm;
+
+///
+/// Parses one atom line in the format `element x y z`. Returns `None` if the line is malformed.
+///
+fn read_atom(line: &str) -> Option<Atom> {
+    let mut columns = line.split_whitespace();
+    let element = columns.next()?.to_ascii_uppercase();
+    let x = columns.next()?.parse::<f32>().ok()?;
+    let y = columns.next()?.parse::<f32>().ok()?;
+    let z = columns.next()?.parse::<f32>().ok()?;
+
+    Some(Atom {
+        position: glm::vec3(x, y, z),
+        name: element.clone(),
+        element,
+        ..Default::default()
+    })
+}
+
+///
+/// Reader of XYZ files. Only the first frame is read and malformed atom lines are skipped.
+///
+pub struct XyzReader;
+
+impl Reader for XyzReader {
+    fn read(&self, content: &str) -> Molecule {
+        let mut lines = content.lines();
+        let count = match lines.next().and_then(|line| line.trim().parse::<usize>().ok()) {
+            Some(count) => count,
+            None => return Molecule::default(),
+        };
+
+        // Skip the comment line
+        lines.next();
+
+        Molecule::new(lines.take(count).filter_map(read_atom).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_frame_is_read() {
+        let content = "3\nwater\nO 0.0 0.0 0.0\nh 0.96 0.0 0.0\nH -0.24 0.93\n3\nsecond frame\nO 1.0 0.0 0.0\n";
+        let molecule = XyzReader.read(content);
+        let atoms: Vec<(&str, glm::Vec3)> = molecule.atoms.iter().map(|atom| (atom.element.as_str(), atom.position)).collect();
+        assert_eq!(atoms, vec![("O", glm::vec3(0.0, 0.0, 0.0)), ("H", glm::vec3(0.96, 0.0, 0.0))]);
+        assert_eq!(molecule.frame_count(), 1);
+
+        assert!(XyzReader.read("water\n").atoms.is_empty());
+    }
+}
@@ -0,0 +1,236 @@
+//!
+//! Module loading structure files on a worker thread, so the scene keeps rendering while large files are parsed.
+//! The worker also builds the voxel grid of the scene the molecule is expected to be put into. Only uploading It to the
+//! GPU is left to the main thread, which builds the grid again if the scene changed in the meantime.
+//!
+
+use crate::cache::*;
+use crate::grid::VoxelGridData;
+use crate::loaders::{self, LoadError};
+use crate::molecule::*;
+use crate::patches::PatchLists;
+use crate::radii::*;
+use crate::scene::*;
+use crate::selection::*;
+use nalgebra_glm as glm;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+///
+/// Options of the application that are needed to prepare a loaded molecule for display.
+///
+#[derive(Clone, Debug)]
+pub struct LoadRequest {
+    pub path: PathBuf,
+    pub alt_loc_policy: AltLocPolicy,
+    pub radii_table: Option<RadiiTable>,
+    pub selection: Option<Selection>,
+    /// Distance within which symmetry mates are generated. `None` if symmetry mates are not shown.
+    pub symmetry_radius: Option<f32>,
+    /// Scene the molecule is put into, so that the worker builds Its voxel grid. `None` leaves the grid to the main thread.
+    pub scene: Option<SceneRequest>,
+}
+
+///
+/// Scene a loaded molecule is expected to be put into, described by atoms of the other visible molecules and by the
+/// placement the loaded molecule gets.
+///
+#[derive(Clone, Debug)]
+pub struct SceneRequest {
+    /// Atoms of the visible molecules preceding the loaded one.
+    pub before: SceneAtoms,
+    /// Atoms of the visible molecules following the loaded one.
+    pub after: SceneAtoms,
+    /// Index of the loaded molecule in the scene, which is also the index of Its surface.
+    pub index: usize,
+    pub visible: bool,
+    pub color: glm::Vec4,
+    pub translation: glm::Vec3,
+    pub rotation: glm::Vec3,
+    pub solvent_radius: f32,
+    /// Cache the grid is read from or stored in if the scene is large.
+    pub cache: Option<GridCache>,
+}
+
+///
+/// Voxel grid and patches of the surface built by the worker thread for the expected scene.
+///
+pub struct PreparedGrid {
+    /// Atoms of the expected scene. The grid may be used only if the actual scene has the same atoms.
+    pub scene: SceneAtoms,
+    pub solvent_radius: f32,
+    /// Error if no voxel grid can be built of the scene.
+    pub grid: Result<(VoxelGridData, PatchLists), String>,
+    /// Error if the built grid could not be stored in the cache. The grid can be used anyway.
+    pub cache_error: Option<String>,
+}
+
+///
+/// Molecule loaded by the worker thread together with everything that is needed to show It.
+///
+pub struct LoadedMolecule {
+    /// Molecule with assigned radii.
+    pub molecule: Molecule,
+    /// Indices of atoms that were not matched by the radii table.
+    pub unmatched_atoms: Vec<usize>,
+    /// Atoms of the first frame of the molecule.
+    pub scene: SceneAtoms,
+    /// Voxel grid of the expected scene. `None` if the grid was not requested or the scene is empty.
+    pub grid: Option<PreparedGrid>,
+}
+
+///
+/// Message sent from the worker thread.
+///
+enum LoadMessage {
+    Progress(f32),
+    Finished(Box<Result<LoadedMolecule, LoadError>>),
+}
+
+///
+/// Structure file being loaded on a worker thread.
+///
+pub struct LoadingTask {
+    path: PathBuf,
+    progress: f32,
+    receiver: mpsc::Receiver<LoadMessage>,
+    /// Index of the molecule of the scene the loaded molecule replaces. `None` adds It to the scene.
+    pub target: Option<usize>,
+    /// Set if the options of the request changed during loading, so the scene prepared by the worker must be rebuilt.
+    pub outdated: bool,
+}
+
+impl LoadingTask {
+    ///
+    /// Starts loading on a new thread. Dropping the task abandons the result of the thread.
+    ///
+    pub fn spawn(request: LoadRequest, target: Option<usize>) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let path = request.path.clone();
+
+        std::thread::spawn(move || {
+            let result = load(request, |progress| {
+                let _ = sender.send(LoadMessage::Progress(progress));
+            });
+            let _ = sender.send(LoadMessage::Finished(Box::new(result)));
+        });
+
+        Self {
+            path,
+            progress: 0.0,
+            receiver,
+            target,
+            outdated: false,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    ///
+    /// Returns the loaded fraction between 0 and 1.
+    ///
+    pub fn progress(&self) -> f32 {
+        self.progress
+    }
+
+    ///
+    /// Receives the progress of the worker thread without blocking. Returns the result once the loading finished.
+    ///
+    pub fn poll(&mut self) -> Option<Result<LoadedMolecule, LoadError>> {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(LoadMessage::Progress(progress)) => self.progress = progress,
+                Ok(LoadMessage::Finished(result)) => return Some(*result),
+                Err(mpsc::TryRecvError::Empty) => return None,
+                Err(mpsc::TryRecvError::Disconnected) => return Some(Err(LoadError::Stopped)),
+            }
+        }
+    }
+}
+
+///
+/// Loads the molecule, assigns radii to It and collects atoms of Its first frame. The voxel grid of the scene is built
+/// as well if It is requested.
+///
+fn load(request: LoadRequest, mut progress: impl FnMut(f32)) -> Result<LoadedMolecule, LoadError> {
+    let mut molecule = loaders::load(&request.path, request.alt_loc_policy, |loaded| progress(0.6 * loaded))?;
+
+    let unmatched_atoms = assign_radii(&mut molecule.atoms, request.radii_table.as_ref());
+    progress(0.65);
+
+    let symmetry = molecule
+        .crystal
+        .as_ref()
+        .and_then(|crystal| request.symmetry_radius.map(|radius| (crystal, radius)));
+    let scene = SceneAtoms::collect(&molecule, &[0], request.selection.as_ref(), None, symmetry);
+    progress(0.7);
+
+    let (molecule, scene, grid) = match request.scene {
+        Some(scene_request) => {
+            let mut placed = SceneMolecule::new(request.path, molecule, scene_request.color, scene);
+            placed.visible = scene_request.visible;
+            placed.translation = scene_request.translation;
+            placed.rotation = scene_request.rotation;
+            let grid = prepare_grid(scene_request, &placed);
+
+            (placed.molecule, placed.atoms, grid)
+        }
+        None => (molecule, scene, None),
+    };
+    progress(1.0);
+
+    Ok(LoadedMolecule {
+        molecule,
+        unmatched_atoms,
+        scene,
+        grid,
+    })
+}
+
+///
+/// Builds the voxel grid and finds patches of the surface of the scene the placed molecule is put into. Grids of large
+/// scenes are read from the cache if they were built before, and stored in It otherwise. Failing to store the grid is
+/// reported with the grid.
+///
+fn prepare_grid(request: SceneRequest, molecule: &SceneMolecule) -> Option<PreparedGrid> {
+    let mut scene = request.before;
+    if molecule.visible {
+        scene.extend(molecule.placed_atoms(request.index as u32));
+    }
+    scene.extend(request.after);
+    if scene.atoms.is_empty() {
+        return None;
+    }
+
+    let solvent_radius = request.solvent_radius;
+    let cache = request.cache.filter(|_| scene.atoms.len() >= CACHE_MIN_ATOMS);
+    let mut cache_error = None;
+    let grid = VoxelGridData::validate(solvent_radius, &scene.atoms).and_then(|_| {
+        let cache = cache.map(|cache| (cache, CacheKey::new(&scene, solvent_radius)));
+        if let Some(cached) = cache
+            .as_ref()
+            .and_then(|(cache, key)| cache.load(*key, scene.atoms.len(), solvent_radius))
+        {
+            return Ok(cached);
+        }
+
+        let data = VoxelGridData::new(solvent_radius, scene.atoms.clone(), scene.colors.clone(), scene.surfaces.clone());
+        let patches = PatchLists::new(&data, &scene.atoms, solvent_radius)?;
+        if let Some((cache, key)) = cache {
+            if let Err(e) = cache.store(key, solvent_radius, &data, &patches) {
+                cache_error = Some(format!("Could not cache the voxel grid in {}: {}", cache.directory.display(), e));
+            }
+        }
+
+        Ok((data, patches))
+    });
+
+    Some(PreparedGrid {
+        scene,
+        solvent_radius,
+        grid,
+        cache_error,
+    })
+}
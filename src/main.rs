@@ -2,9 +2,22 @@
 //!
 
 mod application;
+mod assembly;
+mod cache;
 mod camera;
+mod cli;
+mod crystal;
 mod grid;
+mod loaders;
+mod loading;
+mod molecule;
+mod patches;
 mod pipelines;
+mod radii;
+mod scene;
+#[cfg(test)]
+mod sdf;
+mod selection;
 mod ui;
 mod utils;
 
@@ -24,6 +37,19 @@ fn main() {
         event_loop::{ControlFlow, EventLoop},
     };
 
+    // Process command line arguments
+    let arguments = match cli::Arguments::parse(std::env::args().skip(1)) {
+        Ok(arguments) => arguments,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(1);
+        }
+    };
+    if arguments.help {
+        print!("{}", cli::USAGE);
+        return;
+    }
+
     // Create event loop
     let event_loop = EventLoop::new();
 
@@ -31,7 +57,10 @@ fn main() {
     let (window, size, surface) = {
         let window = winit::window::Window::new(&event_loop).unwrap();
         window.set_title("Implicit Representation of Molecular Surfaces");
-        window.set_inner_size(winit::dpi::PhysicalSize { width: 1280, height: 720 });
+        window.set_inner_size(winit::dpi::PhysicalSize {
+            width: arguments.width,
+            height: arguments.height,
+        });
         let size = window.inner_size();
         let surface = wgpu::Surface::create(&window);
         (window, size, surface)
@@ -40,6 +69,38 @@ fn main() {
     // Initialize the application itself
     let mut application = futures::executor::block_on(application::Application::new(size.width, size.height, &surface));
 
+    // Apply the command line settings
+    if let Some(grid_builder) = arguments.grid_builder {
+        application.set_grid_builder(grid_builder);
+    }
+    if arguments.no_grid_cache {
+        application.set_grid_cache(None);
+    }
+    if let Some(solvent_radius) = arguments.solvent_radius {
+        application.set_solvent_radius(solvent_radius);
+    }
+    if let Some(max_steps) = arguments.max_steps {
+        application.set_max_steps(max_steps);
+    }
+    if let Some(radii) = arguments.radii.as_ref() {
+        match radii::RadiiTable::from_file(radii) {
+            Ok(radii_table) => application.set_radii_table(Some(radii_table)),
+            Err(e) => application.set_error(Some(format!("Could not load radii table {}", e))),
+        }
+    }
+    if let Some(selection) = arguments.selection.as_ref() {
+        if let Err(e) = application.set_selection(selection) {
+            application.set_error(Some(format!("Invalid selection `{}`: {}", selection, e)));
+        }
+    }
+    for file in arguments.files {
+        application.add_molecule(file);
+    }
+    if let Some(camera_distance) = arguments.camera_distance {
+        application.camera.distance = camera_distance;
+        application.camera_changed = true;
+    }
+
     // Create the swapchain
     let sc_format = wgpu::TextureFormat::Bgra8UnormSrgb;
     let mut sc_desc = wgpu::SwapChainDescriptor {
@@ -47,7 +108,7 @@ fn main() {
         format: sc_format,
         width: size.width,
         height: size.height,
-        present_mode: wgpu::PresentMode::Mailbox,
+        present_mode: arguments.present_mode,
     };
     let mut swap_chain = application.device().create_swap_chain(&surface, &sc_desc);
 
@@ -58,9 +119,10 @@ fn main() {
     let mut renderer = Renderer::new(application.device(), Settings::default());
     let mut output = (Primitive::None, mouse::Interaction::default());
     let clipboard = Clipboard::new(&window);
-    let mut ui = ui::UserInterface::new();
+    let mut ui = ui::UserInterface::new(&application);
 
     let mut ui_on = true;
+    let mut was_loading = false;
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
@@ -135,7 +197,10 @@ fn main() {
                 // Then, we process the events, obtaining messages in return.
                 let messages = user_interface.update(events.drain(..), clipboard.as_ref().map(|c| c as _), &renderer);
 
-                let user_interface = if messages.is_empty() {
+                // While a structure file is loaded in the background, the progress changes
+                // without any interaction, so the user interface is rebuilt every frame.
+                let loading = application.loading().is_some();
+                let user_interface = if messages.is_empty() && !loading && !was_loading {
                     // If there are no messages, no interactions we care about have
                     // happened. We can simply leave our user interface as it is.
                     user_interface
@@ -163,6 +228,8 @@ fn main() {
                     )
                 };
 
+                was_loading = loading;
+
                 // Finally, we just need to draw a new output for our renderer,
                 output = user_interface.draw(&mut renderer);
 
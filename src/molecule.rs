@@ -0,0 +1,276 @@
+//!
+//! Module containing representation of loaded molecular structures.
+//!
+
+use crate::assembly::Assembly;
+use crate::crystal::Crystal;
+use nalgebra_glm as glm;
+use std::collections::HashMap;
+
+///
+/// Single atom of a loaded structure.
+///
+#[derive(Clone, Debug)]
+pub struct Atom {
+    /// Position in the coordinate frame of the structure file.
+    pub position: glm::Vec3,
+    /// Radius used for the surface computation.
+    pub radius: f32,
+    /// Chemical element symbol in upper case, e.g. `C` or `FE`.
+    pub element: String,
+    /// Atom name, e.g. `CA`.
+    pub name: String,
+    /// Name of the residue the atom belongs to, e.g. `ALA`.
+    pub residue_name: String,
+    /// Sequence number of the residue the atom belongs to.
+    pub residue_id: i32,
+    /// Identifier of the chain the atom belongs to.
+    pub chain: String,
+    /// Temperature factor.
+    pub b_factor: f32,
+    /// Whether the atom is a heteroatom, i.e. not part of a standard residue.
+    pub hetatm: bool,
+    /// Alternate location indicator of atoms with several conformers, e.g. `A` or `B`.
+    pub alt_loc: Option<char>,
+    /// Fraction of the crystal occupied by this conformer of the atom.
+    pub occupancy: f32,
+    /// Partial charge if the structure file provides it, e.g. PQR.
+    pub charge: Option<f32>,
+    /// Radius given by the structure file, e.g. PQR. Takes precedence over the radii table and the element radius.
+    pub file_radius: Option<f32>,
+}
+
+impl Default for Atom {
+    fn default() -> Self {
+        Self {
+            position: glm::vec3(0.0, 0.0, 0.0),
+            radius: 0.0,
+            element: String::new(),
+            name: String::new(),
+            residue_name: String::new(),
+            residue_id: 0,
+            chain: String::new(),
+            b_factor: 0.0,
+            hetatm: false,
+            alt_loc: None,
+            occupancy: 1.0,
+            charge: None,
+            file_radius: None,
+        }
+    }
+}
+
+impl Atom {
+    ///
+    /// Returns the atom in the `(x, y, z, radius)` format used by the voxel grid.
+    ///
+    pub fn to_vec4(&self) -> glm::Vec4 {
+        glm::vec4(self.position.x, self.position.y, self.position.z, self.radius)
+    }
+}
+
+///
+/// Joins descriptions of frames or models skipped when reading a structure file into one warning, e.g.
+/// `2 frames are skipped: frame 3 has 10 atoms instead of 12, ...`. Only the first few are listed.
+/// Returns `None` if nothing is skipped.
+///
+pub fn skipped_warning(what: &str, skipped: &[String]) -> Option<String> {
+    const LISTED: usize = 5;
+
+    if skipped.is_empty() {
+        return None;
+    }
+    let mut listed: Vec<&str> = skipped.iter().take(LISTED).map(|skipped| skipped.as_str()).collect();
+    if skipped.len() > LISTED {
+        listed.push("...");
+    }
+
+    Some(format!("{} {} are skipped: {}", skipped.len(), what, listed.join(", ")))
+}
+
+///
+/// Policy deciding which alternate conformers of atoms are kept when a structure is loaded.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AltLocPolicy {
+    /// Keeps the conformer with the highest total occupancy of every residue.
+    HighestOccupancy,
+    /// Keeps the conformer with the given indicator. Residues without It fall back to the highest occupancy.
+    Letter(char),
+    /// Keeps all conformers.
+    All,
+}
+
+impl std::fmt::Display for AltLocPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AltLocPolicy::HighestOccupancy => write!(f, "highest occupancy"),
+            AltLocPolicy::Letter(letter) => write!(f, "altLoc {}", letter),
+            AltLocPolicy::All => write!(f, "all"),
+        }
+    }
+}
+
+///
+/// Loaded structure. Trajectories and multi-model files share the atoms and differ only in atom positions per frame.
+///
+#[derive(Clone, Debug, Default)]
+pub struct Molecule {
+    /// Atoms of the structure. Their positions are the positions in the current frame.
+    pub atoms: Vec<Atom>,
+    /// Atom positions of every frame, including the first one.
+    pub frames: Vec<Vec<glm::Vec3>>,
+    /// Whether the frames are models of an ensemble, e.g. NMR, rather than a trajectory.
+    pub ensemble: bool,
+    /// Sorted alternate location indicators present in the structure file.
+    pub alt_locs: Vec<char>,
+    /// Number of atoms discarded by the `AltLocPolicy`.
+    pub discarded_atoms: usize,
+    /// Biological assemblies the structure file describes.
+    pub assemblies: Vec<Assembly>,
+    /// Unit cell and space group of crystal structures.
+    pub crystal: Option<Crystal>,
+    /// Problems of the structure file that did not prevent reading It, shown to the user once the molecule is loaded.
+    pub warnings: Vec<String>,
+}
+
+impl Molecule {
+    ///
+    /// Creates a single-frame molecule.
+    ///
+    pub fn new(atoms: Vec<Atom>) -> Self {
+        let frame = atoms.iter().map(|atom| atom.position).collect();
+
+        Self {
+            atoms,
+            frames: vec![frame],
+            ensemble: false,
+            alt_locs: Vec::new(),
+            discarded_atoms: 0,
+            assemblies: Vec::new(),
+            crystal: None,
+            warnings: Vec::new(),
+        }
+    }
+
+    ///
+    /// Creates an ensemble from separately parsed models. The first model is shown.
+    /// Models with a different number of atoms than the first one are skipped with a warning.
+    ///
+    pub fn from_models(models: Vec<Vec<Atom>>) -> Self {
+        let mut models = models.into_iter().filter(|model| !model.is_empty());
+
+        let mut molecule = Molecule::new(models.next().unwrap_or_default());
+        let mut skipped = Vec::new();
+        for (index, model) in models.enumerate() {
+            if !molecule.push_frame(model.iter().map(|atom| atom.position).collect()) {
+                skipped.push(format!(
+                    "model {} has {} atoms instead of {}",
+                    index + 2,
+                    model.len(),
+                    molecule.atoms.len()
+                ));
+            }
+        }
+        molecule.ensemble = molecule.frame_count() > 1;
+        molecule.warnings.extend(skipped_warning("models", &skipped));
+
+        molecule
+    }
+
+    ///
+    /// Appends a frame of a trajectory. Frames with a different number of atoms are ignored and `false` is returned.
+    ///
+    pub fn push_frame(&mut self, positions: Vec<glm::Vec3>) -> bool {
+        if positions.len() != self.atoms.len() {
+            return false;
+        }
+
+        self.frames.push(positions);
+        true
+    }
+
+    ///
+    /// Discards alternate conformers according to the policy, from the atoms and from every frame.
+    /// Conformers are chosen per residue, so atoms of one residue always come from the same conformer.
+    ///
+    pub fn apply_alt_loc_policy(&mut self, policy: AltLocPolicy) {
+        let mut alt_locs: Vec<char> = self.atoms.iter().filter_map(|atom| atom.alt_loc).collect();
+        alt_locs.sort();
+        alt_locs.dedup();
+        self.alt_locs = alt_locs;
+        self.discarded_atoms = 0;
+
+        if policy == AltLocPolicy::All || self.alt_locs.is_empty() {
+            return;
+        }
+
+        // Total occupancy of every conformer of every residue with alternate locations
+        let mut occupancies: HashMap<(&str, i32, char), f32> = HashMap::new();
+        for atom in &self.atoms {
+            if let Some(alt_loc) = atom.alt_loc {
+                *occupancies.entry((&atom.chain, atom.residue_id, alt_loc)).or_default() += atom.occupancy;
+            }
+        }
+
+        // Conformer kept for every residue. Ties are resolved in favour of the first indicator.
+        let mut kept: HashMap<(&str, i32), (char, f32)> = HashMap::new();
+        for (&(chain, residue_id, alt_loc), &occupancy) in &occupancies {
+            let preferred = policy == AltLocPolicy::Letter(alt_loc);
+            let entry = kept.entry((chain, residue_id)).or_insert((alt_loc, occupancy));
+            let entry_preferred = policy == AltLocPolicy::Letter(entry.0);
+
+            if (preferred && !entry_preferred)
+                || (preferred == entry_preferred && (occupancy > entry.1 || (occupancy == entry.1 && alt_loc < entry.0)))
+            {
+                *entry = (alt_loc, occupancy);
+            }
+        }
+
+        let keep: Vec<bool> = self
+            .atoms
+            .iter()
+            .map(|atom| match atom.alt_loc {
+                Some(alt_loc) => kept.get(&(atom.chain.as_str(), atom.residue_id)).map(|kept| kept.0) == Some(alt_loc),
+                None => true,
+            })
+            .collect();
+        fn retain<T>(values: &mut Vec<T>, keep: &[bool]) {
+            let mut keep = keep.iter();
+            values.retain(|_| *keep.next().unwrap_or(&true));
+        }
+
+        retain(&mut self.atoms, &keep);
+        for frame in &mut self.frames {
+            retain(frame, &keep);
+        }
+        self.discarded_atoms = keep.iter().filter(|keep| !**keep).count();
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    ///
+    /// Returns the atoms at their positions in the given frame.
+    ///
+    pub fn frame_atoms(&self, frame: usize) -> impl Iterator<Item = Atom> + '_ {
+        let positions = self.frames.get(frame).map(|positions| positions.as_slice()).unwrap_or(&[]);
+
+        self.atoms.iter().zip(positions.iter()).map(|(atom, position)| Atom {
+            position: *position,
+            ..atom.clone()
+        })
+    }
+
+    ///
+    /// Moves the atoms to their positions in the given frame.
+    ///
+    pub fn set_frame(&mut self, frame: usize) {
+        if let Some(positions) = self.frames.get(frame) {
+            for (atom, position) in self.atoms.iter_mut().zip(positions.iter()) {
+                atom.position = *position;
+            }
+        }
+    }
+}
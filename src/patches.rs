@@ -0,0 +1,714 @@
+//!
+//! Module precomputing for every cell of the voxel grid the pairs and triples of atoms that can form toroidal and
+//! spherical (reentrant) patches of the surface, so that ray marching does not search for them at every sample.
+//!
+
+use crate::grid::*;
+use crate::utils::*;
+use nalgebra_glm as glm;
+use std::collections::HashMap;
+use wgpu;
+
+/// Largest size of every buffer of the patch lists in bytes, for the same reason as `MAX_DENSE_GRID_SIZE`.
+pub const MAX_PATCH_BUFFER_SIZE: usize = 128 << 20;
+
+///
+/// Pointer to the pairs and triples of atoms of one cell in `PatchLists::atoms`. The triples follow the pairs.
+///
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PatchPointer {
+    pub start: u32,
+    pub pairs: u32,
+    pub triples: u32,
+}
+
+unsafe impl bytemuck::Zeroable for PatchPointer {}
+unsafe impl bytemuck::Pod for PatchPointer {}
+
+///
+/// Pairs and triples of atoms that can form patches of the surface in every cell of a voxel grid. Cells with patches
+/// are stored in a hash table in the same way as cells of a sparse voxel grid. Atoms are referred to by their indices
+/// in the order of the input.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct PatchLists {
+    /// Keys of the hash table. Empty slots contain `EMPTY_CELL`.
+    pub keys: Vec<u32>,
+    /// Patches of the cell of every slot of the hash table.
+    pub pointers: Vec<PatchPointer>,
+    /// Indices of two atoms of every pair followed by indices of three atoms of every triple of every cell.
+    pub atoms: Vec<u32>,
+}
+
+impl PatchLists {
+    ///
+    /// Finds the patches of atoms in format `(x, y, z, radius)` in the cells of the grid for the given solvent radius.
+    ///
+    /// Atoms form a patch only if a probe can touch each two of them, i.e. their spheres extended by the solvent radius
+    /// intersect. Ray marching considers only atoms closer than `radius + 2 * solvent_radius` to a sample, so a patch is
+    /// listed in the cells this close to all of Its atoms. The shader then finds the same patches as by testing all
+    /// pairs and triples of the neighbouring atoms.
+    ///
+    /// Returns an error if a buffer of the patch lists would be larger than `MAX_PATCH_BUFFER_SIZE`.
+    ///
+    pub fn new(grid: &VoxelGridData, atoms: &[glm::Vec4], solvent_radius: f32) -> Result<Self, String> {
+        Self::with_budget(grid, atoms, solvent_radius, MAX_PATCH_BUFFER_SIZE)
+    }
+
+    ///
+    /// Same as `new`, but returns an error if a buffer of the patch lists would be larger than `budget` bytes.
+    ///
+    pub fn with_budget(grid: &VoxelGridData, atoms: &[glm::Vec4], solvent_radius: f32, budget: usize) -> Result<Self, String> {
+        let atoms: Vec<glm::Vec4> = atoms.iter().map(|atom| grid.to_render_atom(atom)).collect();
+        let cell_position = |position: &glm::Vec3| -> glm::Vec3 { ((position - grid.bb_min) / grid.voxel_length).map(|x| x.floor()) };
+
+        // Bin atoms into cells to find their neighbours
+        let mut cells: HashMap<[i64; 3], Vec<u32>> = HashMap::new();
+        for (index, atom) in atoms.iter().enumerate() {
+            let cell = cell_position(&atom.xyz());
+            cells
+                .entry([cell.x as i64, cell.y as i64, cell.z as i64])
+                .or_default()
+                .push(index as u32);
+        }
+
+        // Atoms whose spheres extended by the solvent radius intersect, sorted by their indices
+        let mut neighbours = Vec::with_capacity(atoms.len());
+        for (index, atom) in atoms.iter().enumerate() {
+            let distance = atom.w + grid.radius_max + 2.0 * solvent_radius;
+            let min = cell_position(&atom.xyz().add_scalar(-distance));
+            let max = cell_position(&atom.xyz().add_scalar(distance));
+
+            let mut atom_neighbours = Vec::new();
+            for x in min.x as i64..=max.x as i64 {
+                for y in min.y as i64..=max.y as i64 {
+                    for z in min.z as i64..=max.z as i64 {
+                        let cell = match cells.get(&[x, y, z]) {
+                            Some(cell) => cell,
+                            None => continue,
+                        };
+                        atom_neighbours.extend(cell.iter().filter(|&&other| {
+                            let other_atom = atoms[other as usize];
+                            other as usize != index
+                                && glm::distance(&atom.xyz(), &other_atom.xyz()) < atom.w + other_atom.w + 2.0 * solvent_radius
+                        }));
+                    }
+                }
+            }
+            atom_neighbours.sort_unstable();
+            neighbours.push(atom_neighbours);
+        }
+
+        let mut pairs: Vec<(u32, [u32; 2])> = Vec::new();
+        let mut triples: Vec<(u32, [u32; 3])> = Vec::new();
+        for i in 0..atoms.len() {
+            for &j in neighbours[i].iter().filter(|&&j| j as usize > i) {
+                let pair_cells = patch_cells(grid, &[atoms[i], atoms[j as usize]], solvent_radius);
+                for &cell in pair_cells.iter() {
+                    pairs.push((cell, [i as u32, j]));
+                }
+
+                // Cells of a triple are the cells of Its pairs
+                for &k in common_neighbours(&neighbours[i], &neighbours[j as usize])
+                    .iter()
+                    .filter(|&&k| k > j)
+                {
+                    for &cell in pair_cells
+                        .iter()
+                        .filter(|&&cell| touches(grid, cell, &atoms[k as usize], solvent_radius))
+                    {
+                        triples.push((cell, [i as u32, j, k]));
+                    }
+                }
+            }
+
+            // Stop early, the patches could take more memory than there is
+            if (2 * pairs.len() + 3 * triples.len()) * std::mem::size_of::<u32>() > budget {
+                return Err(too_many_patches(budget));
+            }
+        }
+
+        let patches = Self::from_patches(pairs, triples);
+        if patches.keys.len() * std::mem::size_of::<PatchPointer>() > budget {
+            return Err(too_many_patches(budget));
+        }
+
+        Ok(patches)
+    }
+
+    ///
+    /// Returns patch lists without any patches.
+    ///
+    pub fn empty() -> Self {
+        Self::from_patches(Vec::new(), Vec::new())
+    }
+
+    ///
+    /// Puts patches given with their cells into the hash table, keeping their order within cells.
+    ///
+    fn from_patches(mut pairs: Vec<(u32, [u32; 2])>, mut triples: Vec<(u32, [u32; 3])>) -> Self {
+        pairs.sort_by_key(|(cell, _)| *cell);
+        triples.sort_by_key(|(cell, _)| *cell);
+
+        let mut cells: Vec<u32> = pairs
+            .iter()
+            .map(|(cell, _)| *cell)
+            .chain(triples.iter().map(|(cell, _)| *cell))
+            .collect();
+        cells.sort_unstable();
+        cells.dedup();
+
+        let slots = (2 * cells.len()).next_power_of_two();
+        let mut patches = Self {
+            keys: vec![EMPTY_CELL; slots],
+            pointers: vec![PatchPointer::default(); slots],
+            atoms: Vec::with_capacity(2 * pairs.len() + 3 * triples.len()),
+        };
+
+        let (mut pair, mut triple) = (0, 0);
+        for cell in cells {
+            let mut slot = hash_cell(cell, slots);
+            while patches.keys[slot] != EMPTY_CELL {
+                slot = (slot + 1) % slots;
+            }
+            patches.keys[slot] = cell;
+            patches.pointers[slot].start = patches.atoms.len() as u32;
+
+            while pair < pairs.len() && pairs[pair].0 == cell {
+                patches.atoms.extend_from_slice(&pairs[pair].1);
+                patches.pointers[slot].pairs += 1;
+                pair += 1;
+            }
+            while triple < triples.len() && triples[triple].0 == cell {
+                patches.atoms.extend_from_slice(&triples[triple].1);
+                patches.pointers[slot].triples += 1;
+                triple += 1;
+            }
+        }
+
+        patches
+    }
+
+    ///
+    /// Returns the patches of a cell in the same way as the ray marching shader.
+    ///
+    pub fn pointer(&self, cell: usize) -> PatchPointer {
+        let slots = self.keys.len();
+        let mut slot = hash_cell(cell as u32, slots);
+        loop {
+            match self.keys[slot] {
+                key if key == cell as u32 => return self.pointers[slot],
+                EMPTY_CELL => return PatchPointer::default(),
+                _ => slot = (slot + 1) % slots,
+            }
+        }
+    }
+
+    ///
+    /// Returns the pairs of atoms forming toroidal patches in a cell.
+    ///
+    pub fn pairs(&self, cell: usize) -> impl Iterator<Item = &[u32]> {
+        let pointer = self.pointer(cell);
+        let start = pointer.start as usize;
+        self.atoms[start..start + 2 * pointer.pairs as usize].chunks(2)
+    }
+
+    ///
+    /// Returns the triples of atoms forming spherical patches in a cell.
+    ///
+    pub fn triples(&self, cell: usize) -> impl Iterator<Item = &[u32]> {
+        let pointer = self.pointer(cell);
+        let start = (pointer.start + 2 * pointer.pairs) as usize;
+        self.atoms[start..start + 3 * pointer.triples as usize].chunks(3)
+    }
+}
+
+///
+/// CPU copy of patch lists whose atoms can be moved in place. Every cell has spare room after Its patches and the
+/// hash table has spare slots, so only the patches of the cells near moved atoms are found again.
+///
+pub struct MovablePatches {
+    /// Patch lists with spare room after the patches of every cell and after the last cell.
+    pub lists: PatchLists,
+    /// Number of indices of atoms the cell of every slot of the hash table has room for.
+    capacities: Vec<u32>,
+    /// Number of occupied slots of the hash table.
+    cell_count: usize,
+    /// Number of indices of atoms taken by the cells. Cells whose patches do not fit get new room after them.
+    used: usize,
+    /// Atoms in render space the patches were found for.
+    atoms: Vec<glm::Vec4>,
+    /// Atoms in every cell of the grid containing any.
+    cells: HashMap<usize, Vec<u32>>,
+    solvent_radius: f32,
+    /// Slots of the hash table changed since the last upload.
+    pub dirty_slots: std::ops::Range<usize>,
+    /// Indices of atoms of the patches changed since the last upload.
+    pub dirty_atoms: std::ops::Range<usize>,
+}
+
+impl MovablePatches {
+    ///
+    /// Makes room for more patches in the patch lists of the atoms. Returns `None` if an atom lies outside of the grid
+    /// or the buffers would be larger than `MAX_PATCH_BUFFER_SIZE`.
+    ///
+    pub fn new(grid: &VoxelGridData, atoms: &[glm::Vec4], solvent_radius: f32, lists: &PatchLists) -> Option<Self> {
+        let atoms: Vec<glm::Vec4> = atoms.iter().map(|atom| grid.to_render_atom(atom)).collect();
+        let mut cells: HashMap<usize, Vec<u32>> = HashMap::new();
+        for (index, atom) in atoms.iter().enumerate() {
+            cells.entry(grid.cell_index(&atom.xyz())?).or_default().push(index as u32);
+        }
+
+        let mut occupied: Vec<(u32, PatchPointer)> = lists
+            .keys
+            .iter()
+            .zip(lists.pointers.iter())
+            .filter(|&(&key, _)| key != EMPTY_CELL)
+            .map(|(&key, &pointer)| (key, pointer))
+            .collect();
+        occupied.sort_unstable_by_key(|(cell, _)| *cell);
+
+        // Make room for a quarter more patches in every cell, a quarter more cells and twice as many slots
+        let slots = (4 * (occupied.len() + 1)).next_power_of_two();
+        let length = |pointer: &PatchPointer| (2 * pointer.pairs + 3 * pointer.triples) as usize;
+        let capacity = |pointer: &PatchPointer| length(pointer) + length(pointer) / 4 + 5;
+        let used: usize = occupied.iter().map(|(_, pointer)| capacity(pointer)).sum();
+        let size = used + used / 4;
+        if size * std::mem::size_of::<u32>() > MAX_PATCH_BUFFER_SIZE || slots * std::mem::size_of::<PatchPointer>() > MAX_PATCH_BUFFER_SIZE
+        {
+            return None;
+        }
+
+        let mut patches = Self {
+            lists: PatchLists {
+                keys: vec![EMPTY_CELL; slots],
+                pointers: vec![PatchPointer::default(); slots],
+                atoms: vec![0; size],
+            },
+            capacities: vec![0; slots],
+            cell_count: occupied.len(),
+            used: 0,
+            atoms,
+            cells,
+            solvent_radius,
+            dirty_slots: 0..0,
+            dirty_atoms: 0..0,
+        };
+        for (cell, pointer) in occupied {
+            let mut slot = hash_cell(cell, slots);
+            while patches.lists.keys[slot] != EMPTY_CELL {
+                slot = (slot + 1) % slots;
+            }
+
+            let start = pointer.start as usize;
+            patches.lists.atoms[patches.used..patches.used + length(&pointer)]
+                .copy_from_slice(&lists.atoms[start..start + length(&pointer)]);
+            patches.lists.keys[slot] = cell;
+            patches.lists.pointers[slot] = PatchPointer {
+                start: patches.used as u32,
+                ..pointer
+            };
+            patches.capacities[slot] = capacity(&pointer) as u32;
+            patches.used += capacity(&pointer);
+        }
+
+        Some(patches)
+    }
+
+    ///
+    /// Moves atoms to new positions and radii inside of the grid and finds the patches of the cells they touch before
+    /// or after moving again. Returns `false` if the patch lists must be found again for all atoms, because the solvent
+    /// radius changed, most of the atoms moved or there is no more room, leaving the patches partially updated.
+    ///
+    pub fn move_atoms(&mut self, grid: &VoxelGridData, atoms: &[glm::Vec4], solvent_radius: f32) -> bool {
+        if atoms.len() != self.atoms.len() || solvent_radius != self.solvent_radius {
+            return false;
+        }
+
+        let atoms: Vec<glm::Vec4> = atoms.iter().map(|atom| grid.to_render_atom(atom)).collect();
+        let moved: Vec<usize> = (0..atoms.len()).filter(|&index| atoms[index] != self.atoms[index]).collect();
+        // Finding the patches of all atoms at once is faster
+        if 4 * moved.len() > atoms.len() {
+            return false;
+        }
+
+        let mut touched_cells = Vec::new();
+        for &index in moved.iter() {
+            let (old_atom, atom) = (self.atoms[index], atoms[index]);
+            touched_cells.extend(patch_cells(grid, &[old_atom], solvent_radius));
+            touched_cells.extend(patch_cells(grid, &[atom], solvent_radius));
+
+            let (old_cell, cell) = match (grid.cell_index(&old_atom.xyz()), grid.cell_index(&atom.xyz())) {
+                (Some(old_cell), Some(cell)) => (old_cell, cell),
+                _ => return false,
+            };
+            if old_cell != cell {
+                if let Some(old_cell_atoms) = self.cells.get_mut(&old_cell) {
+                    old_cell_atoms.retain(|&other| other as usize != index);
+                }
+                self.cells.entry(cell).or_default().push(index as u32);
+            }
+        }
+        self.atoms = atoms;
+
+        touched_cells.sort_unstable();
+        touched_cells.dedup();
+        touched_cells.into_iter().all(|cell| self.find_patches(grid, cell))
+    }
+
+    ///
+    /// Finds the patches of a cell again in the same order as `PatchLists::new`. Returns `false` if they do not fit.
+    ///
+    fn find_patches(&mut self, grid: &VoxelGridData, cell: u32) -> bool {
+        let size = [grid.bb_size.x as i64, grid.bb_size.y as i64, grid.bb_size.z as i64];
+        let position = [
+            cell as i64 % size[0],
+            cell as i64 / size[0] % size[1],
+            cell as i64 / (size[0] * size[1]),
+        ];
+
+        // Atoms touching a cell lie in It or in Its neighbours, because voxels are longer than `radius + 2 * solvent_radius`
+        let mut candidates = Vec::new();
+        for z in (position[2] - 1).max(0)..=(position[2] + 1).min(size[2] - 1) {
+            for y in (position[1] - 1).max(0)..=(position[1] + 1).min(size[1] - 1) {
+                for x in (position[0] - 1).max(0)..=(position[0] + 1).min(size[0] - 1) {
+                    if let Some(cell_atoms) = self.cells.get(&((z * size[1] * size[0] + y * size[0] + x) as usize)) {
+                        candidates.extend(
+                            cell_atoms
+                                .iter()
+                                .filter(|&&atom| touches(grid, cell, &self.atoms[atom as usize], self.solvent_radius)),
+                        );
+                    }
+                }
+            }
+        }
+        candidates.sort_unstable();
+
+        let atoms = &self.atoms;
+        let solvent_radius = self.solvent_radius;
+        let neighbours = |i: u32, j: u32| {
+            let (atom1, atom2) = (atoms[i as usize], atoms[j as usize]);
+            glm::distance(&atom1.xyz(), &atom2.xyz()) < atom1.w + atom2.w + 2.0 * solvent_radius
+        };
+
+        let mut patches = Vec::new();
+        let mut pairs = 0;
+        for (a, &i) in candidates.iter().enumerate() {
+            for &j in candidates[a + 1..].iter().filter(|&&j| neighbours(i, j)) {
+                patches.extend_from_slice(&[i, j]);
+                pairs += 1;
+            }
+        }
+        let mut triples = 0;
+        for (a, &i) in candidates.iter().enumerate() {
+            for (b, &j) in candidates.iter().enumerate().skip(a + 1).filter(|&(_, &j)| neighbours(i, j)) {
+                for &k in candidates[b + 1..].iter().filter(|&&k| neighbours(i, k) && neighbours(j, k)) {
+                    patches.extend_from_slice(&[i, j, k]);
+                    triples += 1;
+                }
+            }
+        }
+
+        self.write_patches(cell, &patches, pairs, triples)
+    }
+
+    ///
+    /// Replaces the patches of a cell. Returns `false` if there is no more room for them.
+    ///
+    fn write_patches(&mut self, cell: u32, patches: &[u32], pairs: u32, triples: u32) -> bool {
+        let slots = self.lists.keys.len();
+        let mut slot = hash_cell(cell, slots);
+        while self.lists.keys[slot] != cell && self.lists.keys[slot] != EMPTY_CELL {
+            slot = (slot + 1) % slots;
+        }
+        if self.lists.keys[slot] == EMPTY_CELL {
+            if patches.is_empty() {
+                return true;
+            }
+            // At most half of the slots are used, so probing stays short
+            if 2 * (self.cell_count + 1) > slots {
+                return false;
+            }
+            self.lists.keys[slot] = cell;
+            self.cell_count += 1;
+        }
+
+        let mut start = self.lists.pointers[slot].start as usize;
+        if patches.len() > self.capacities[slot] as usize {
+            if self.used + patches.len() > self.lists.atoms.len() {
+                return false;
+            }
+            let capacity = (patches.len() + patches.len() / 4 + 5).min(self.lists.atoms.len() - self.used);
+            start = self.used;
+            self.used += capacity;
+            self.capacities[slot] = capacity as u32;
+        }
+
+        self.lists.atoms[start..start + patches.len()].copy_from_slice(patches);
+        self.lists.pointers[slot] = PatchPointer {
+            start: start as u32,
+            pairs,
+            triples,
+        };
+        mark(&mut self.dirty_slots, slot);
+        if !patches.is_empty() {
+            mark(&mut self.dirty_atoms, start);
+            mark(&mut self.dirty_atoms, start + patches.len() - 1);
+        }
+
+        true
+    }
+}
+
+///
+/// GPU buffers of the patch lists read by ray marching.
+///
+pub struct PatchBuffers {
+    /// Keys of the hash table of cells with patches.
+    pub keys: wgpu::Buffer,
+    /// Number of slots of the hash table, which is a power of two.
+    pub keys_len: usize,
+    pub pointers: wgpu::Buffer,
+    /// Indices of atoms of pairs and triples. Contains one unused index if there are no patches.
+    pub atoms: wgpu::Buffer,
+    pub atoms_len: usize,
+}
+
+impl PatchBuffers {
+    pub fn upload(device: &wgpu::Device, patches: &PatchLists) -> Self {
+        // Patches of moved atoms can be written in place
+        let usage = wgpu::BufferUsage::STORAGE_READ | wgpu::BufferUsage::COPY_DST;
+        let atoms: &[u32] = if patches.atoms.is_empty() { &[0] } else { &patches.atoms };
+
+        Self {
+            keys: device.create_buffer_with_data(bytemuck::cast_slice(&patches.keys), usage),
+            keys_len: patches.keys.len(),
+            pointers: device.create_buffer_with_data(bytemuck::cast_slice(&patches.pointers), usage),
+            atoms: device.create_buffer_with_data(bytemuck::cast_slice(atoms), usage),
+            atoms_len: atoms.len(),
+        }
+    }
+}
+
+///
+/// Returns the indices contained in both sorted lists.
+///
+fn common_neighbours(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut common = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] < b[j] {
+            i += 1;
+        } else if a[i] > b[j] {
+            j += 1;
+        } else {
+            common.push(a[i]);
+            i += 1;
+            j += 1;
+        }
+    }
+    common
+}
+
+///
+/// Returns the cells closer than `radius + 2 * solvent_radius` to every atom.
+///
+fn patch_cells(grid: &VoxelGridData, atoms: &[glm::Vec4], solvent_radius: f32) -> Vec<u32> {
+    let size = [grid.bb_size.x as i64, grid.bb_size.y as i64, grid.bb_size.z as i64];
+    let cell_range = |axis: usize| {
+        atoms
+            .iter()
+            .map(|atom| atom_cell_range(grid, atom, solvent_radius, axis))
+            .fold(0..=size[axis] - 1, |range, atom_range| {
+                *range.start().max(atom_range.start())..=*range.end().min(atom_range.end())
+            })
+    };
+
+    let mut cells = Vec::new();
+    for z in cell_range(2) {
+        for y in cell_range(1) {
+            for x in cell_range(0) {
+                let cell = glm::vec3(x as f32, y as f32, z as f32);
+                if atoms
+                    .iter()
+                    .all(|atom| box_distance(grid, &cell, atom) < atom.w + 2.0 * solvent_radius)
+                {
+                    cells.push((z * size[1] * size[0] + y * size[0] + x) as u32);
+                }
+            }
+        }
+    }
+    cells
+}
+
+///
+/// Returns `true` if the atom is one of the atoms of which `patch_cells` returns the cell.
+///
+fn touches(grid: &VoxelGridData, cell: u32, atom: &glm::Vec4, solvent_radius: f32) -> bool {
+    let (width, height) = (grid.bb_size.x as u32, grid.bb_size.y as u32);
+    let cell = [cell % width, cell / width % height, cell / (width * height)];
+
+    (0..3).all(|axis| atom_cell_range(grid, atom, solvent_radius, axis).contains(&i64::from(cell[axis])))
+        && box_distance(grid, &glm::vec3(cell[0] as f32, cell[1] as f32, cell[2] as f32), atom) < atom.w + 2.0 * solvent_radius
+}
+
+///
+/// Returns the coordinates along an axis of the cells of the grid closer than `radius + 2 * solvent_radius` to the atom
+/// along the axis.
+///
+fn atom_cell_range(grid: &VoxelGridData, atom: &glm::Vec4, solvent_radius: f32, axis: usize) -> std::ops::RangeInclusive<i64> {
+    let min = ((atom[axis] - atom.w - 2.0 * solvent_radius - grid.bb_min[axis]) / grid.voxel_length).floor() as i64;
+    let max = ((atom[axis] + atom.w + 2.0 * solvent_radius - grid.bb_min[axis]) / grid.voxel_length).floor() as i64;
+    min.max(0)..=max.min(grid.bb_size[axis] as i64 - 1)
+}
+
+///
+/// Returns the distance of the centre of an atom from the box of a cell.
+///
+fn box_distance(grid: &VoxelGridData, cell: &glm::Vec3, atom: &glm::Vec4) -> f32 {
+    let min = grid.bb_min + cell * grid.voxel_length;
+    let max = min.add_scalar(grid.voxel_length);
+    glm::length(&glm::max2(&(min - atom.xyz()), &(atom.xyz() - max)).map(|x| x.max(0.0)))
+}
+
+///
+/// Returns the error of patch lists larger than the budget in bytes.
+///
+fn too_many_patches(budget: usize) -> String {
+    format!(
+        "the patches of the surface take more than {:.1} MB, try a smaller solvent radius",
+        budget as f64 / f64::from(1 << 20)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loaders::Reader;
+
+    fn find_patches(atoms: &[glm::Vec4]) -> (VoxelGridData, PatchLists) {
+        let grid = VoxelGridData::layout(1.4, atoms);
+        let patches = PatchLists::new(&grid, atoms, 1.4).unwrap();
+        (grid, patches)
+    }
+
+    fn cell_at(grid: &VoxelGridData, position: glm::Vec3) -> usize {
+        grid.cell_index(&(position - grid.origin)).unwrap()
+    }
+
+    #[test]
+    fn isolated_atom_has_no_patches() {
+        let atoms = [glm::vec4(0.0, 0.0, 0.0, 1.5)];
+        let (grid, patches) = find_patches(&atoms);
+
+        assert!(patches.atoms.is_empty());
+        assert_eq!(patches.pairs(cell_at(&grid, glm::vec3(0.0, 0.0, 0.0))).count(), 0);
+    }
+
+    #[test]
+    fn pairs_of_close_atoms() {
+        let atoms = [
+            glm::vec4(0.0, 0.0, 0.0, 1.5),
+            glm::vec4(4.0, 0.0, 0.0, 1.5),
+            glm::vec4(20.0, 0.0, 0.0, 1.5),
+        ];
+        let (grid, patches) = find_patches(&atoms);
+
+        let pairs: Vec<&[u32]> = patches.pairs(cell_at(&grid, glm::vec3(2.0, 0.0, 0.0))).collect();
+        assert_eq!(pairs, vec![&[0, 1][..]]);
+        assert_eq!(patches.triples(cell_at(&grid, glm::vec3(2.0, 0.0, 0.0))).count(), 0);
+        assert_eq!(patches.pairs(cell_at(&grid, glm::vec3(20.0, 0.0, 0.0))).count(), 0);
+    }
+
+    #[test]
+    fn triples_of_close_atoms() {
+        let atoms = [
+            glm::vec4(0.0, 0.0, 0.0, 1.5),
+            glm::vec4(4.0, 0.0, 0.0, 1.5),
+            glm::vec4(2.0, 3.4, 0.0, 1.5),
+            glm::vec4(8.0, 3.4, 0.0, 1.5),
+        ];
+        let (grid, patches) = find_patches(&atoms);
+
+        let triples: Vec<&[u32]> = patches.triples(cell_at(&grid, glm::vec3(2.0, 1.2, 0.0))).collect();
+        assert_eq!(triples, vec![&[0, 1, 2][..]]);
+
+        // The last atom is close to the second one only
+        let pairs: Vec<&[u32]> = patches.pairs(cell_at(&grid, glm::vec3(6.0, 1.7, 0.0))).collect();
+        assert!(pairs.contains(&&[1, 3][..]));
+        assert!(!pairs.contains(&&[0, 3][..]));
+    }
+
+    ///
+    /// Returns atoms placed pseudo-randomly in a box, so that some of them form patches.
+    ///
+    fn scattered_atoms(count: usize, size: f32) -> Vec<glm::Vec4> {
+        let mut state = 12345u32;
+        let mut random = move || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 8) as f32 / (1 << 24) as f32
+        };
+
+        (0..count)
+            .map(|_| glm::vec4(size * random(), size * random(), size * random(), 1.2 + 0.6 * random()))
+            .collect()
+    }
+
+    fn assert_same_patches(grid: &VoxelGridData, patches: &PatchLists, expected: &PatchLists) {
+        for cell in 0..grid.cell_count() {
+            assert!(patches.pairs(cell).eq(expected.pairs(cell)), "Pairs of cell {} differ", cell);
+            assert!(patches.triples(cell).eq(expected.triples(cell)), "Triples of cell {} differ", cell);
+        }
+    }
+
+    #[test]
+    fn moved_atoms_have_same_patches_as_found_ones() {
+        let mut atoms = scattered_atoms(400, 30.0);
+        let grid = VoxelGridData::layout(1.4, &atoms);
+        let lists = PatchLists::new(&grid, &atoms, 1.4).unwrap();
+        let mut patches = MovablePatches::new(&grid, &atoms, 1.4, &lists).unwrap();
+        assert_same_patches(&grid, &patches.lists, &lists);
+
+        // Atoms cross cells, leave their neighbours and shrink
+        for step in 0..5 {
+            for (index, atom) in atoms.iter_mut().enumerate().filter(|(index, _)| index % 11 == step) {
+                atom.x = (atom.x + 3.5).min(30.0);
+                atom.y -= 1.0;
+                atom.w = if index % 2 == 0 { 1.0 } else { atom.w };
+            }
+            assert!(patches.move_atoms(&grid, &atoms, 1.4));
+            assert!(patches.dirty_slots.start < patches.dirty_slots.end);
+            assert_same_patches(&grid, &patches.lists, &PatchLists::new(&grid, &atoms, 1.4).unwrap());
+        }
+
+        // Patches of all atoms are found at once
+        let moved: Vec<glm::Vec4> = atoms.iter().map(|atom| atom + glm::vec4(0.5, 0.0, 0.0, 0.0)).collect();
+        assert!(!patches.move_atoms(&grid, &moved, 1.4));
+        assert!(!patches.move_atoms(&grid, &atoms, 1.2));
+    }
+
+    #[test]
+    fn patches_of_real_structure_fit_into_buffers() {
+        const SOLVENT_RADIUS: f32 = 0.71590906;
+
+        // Radii are assigned like by the application, hydrogen atoms without a radius in the file get the element radius
+        let content = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/pdb/2aq5.pqr")).unwrap();
+        let mut molecule = crate::loaders::pqr::PqrReader.read(&content);
+        crate::radii::assign_radii(&mut molecule.atoms, None);
+        let atoms: Vec<glm::Vec4> = molecule.atoms.iter().map(|atom| atom.to_vec4()).collect();
+        assert_eq!(atoms.len(), 7415);
+
+        // Patches for the initial solvent radius
+        let grid = VoxelGridData::layout(SOLVENT_RADIUS, &atoms);
+        let patches = PatchLists::new(&grid, &atoms, SOLVENT_RADIUS).unwrap();
+        let size = patches.atoms.len() * std::mem::size_of::<u32>();
+        assert!(size <= MAX_PATCH_BUFFER_SIZE, "Patches take {} bytes", size);
+        assert!(patches.keys.len() * std::mem::size_of::<PatchPointer>() <= MAX_PATCH_BUFFER_SIZE);
+        let movable = MovablePatches::new(&grid, &atoms, SOLVENT_RADIUS, &patches).unwrap();
+        assert!(movable.lists.atoms.len() * std::mem::size_of::<u32>() <= MAX_PATCH_BUFFER_SIZE);
+
+        // Finding patches stops once they take more than allowed
+        assert!(PatchLists::with_budget(&grid, &atoms, SOLVENT_RADIUS, size / 2).is_err());
+    }
+}
@@ -2,3 +2,8 @@ glslangvalidator -V ./raymarch/raymarch.comp -o ./raymarch/raymarch.comp.spv
 glslangvalidator -V ./render/render.vert -o ./render/render.vert.spv
 glslangvalidator -V ./render/render.frag -o ./render/render.frag.spv
 glslangvalidator -V ./ssao/ssao.comp -o ./ssao/ssao.comp.spv
+glslangvalidator -V ./sdf/sdf.comp -o ./sdf/sdf.comp.spv
+glslangvalidator -V ./grid/grid_count.comp -o ./grid/grid_count.comp.spv
+glslangvalidator -V ./grid/grid_prefix_sum.comp -o ./grid/grid_prefix_sum.comp.spv
+glslangvalidator -V ./grid/grid_scatter.comp -o ./grid/grid_scatter.comp.spv
+glslangvalidator -V ./grid/grid_sort.comp -o ./grid/grid_sort.comp.spv
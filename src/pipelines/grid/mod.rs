@@ -0,0 +1,110 @@
+//!
+//! Pipelines building the voxel grid from atoms on the GPU. Atoms are counted per cell, the counts are summed into
+//! starts of the cells, atoms are scattered to their cells and finally sorted within the cells.
+//!
+
+use crate::utils::load_glsl;
+use wgpu;
+
+/// Number of invocations of one work group of the passes over atoms or cells.
+pub const GRID_WORK_GROUP_SIZE: u32 = 256;
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct GridGlobals {
+    pub origin: [f32; 3],
+    /// Reciprocal of the voxel length.
+    pub voxel_scale: f32,
+    pub bb_min: [f32; 3],
+    pub atom_count: u32,
+    pub bb_size: [f32; 3],
+    pub cell_count: u32,
+}
+
+unsafe impl bytemuck::Zeroable for GridGlobals {}
+unsafe impl bytemuck::Pod for GridGlobals {}
+
+pub struct GridPipeline {
+    /// Computes cells of atoms and counts atoms of every cell.
+    pub count_pipeline: wgpu::ComputePipeline,
+    /// Computes starts of cells from their counts in one work group.
+    pub prefix_sum_pipeline: wgpu::ComputePipeline,
+    /// Writes indices of atoms to the ranges of their cells.
+    pub scatter_pipeline: wgpu::ComputePipeline,
+    /// Sorts indices of atoms of every cell and writes the atoms in the flat format of the voxel grid.
+    pub sort_pipeline: wgpu::ComputePipeline,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl GridPipeline {
+    pub fn new(device: &wgpu::Device) -> Self {
+        // Bind Groups
+        let storage_buffer = |binding, readonly| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStage::COMPUTE,
+            ty: wgpu::BindingType::StorageBuffer { dynamic: false, readonly },
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Grid bind group layout"),
+            bindings: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                },
+                // Atoms, their colors and surfaces
+                storage_buffer(1, true),
+                storage_buffer(2, true),
+                storage_buffer(3, true),
+                // Cells of atoms and ranks of atoms within their cells
+                storage_buffer(4, false),
+                // Voxel pointers
+                storage_buffer(5, false),
+                // Indices of atoms sorted by cells
+                storage_buffer(6, false),
+                // Voxels, their colors and surfaces
+                storage_buffer(7, false),
+                storage_buffer(8, false),
+                storage_buffer(9, false),
+                // Slots of atoms in the order of the input
+                storage_buffer(10, false),
+            ],
+        });
+
+        // Pipelines
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[&bind_group_layout],
+        });
+
+        let create_pipeline = |bytes: &[u8]| {
+            let cs_module = device.create_shader_module(&load_glsl(bytes));
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                layout: &pipeline_layout,
+                compute_stage: wgpu::ProgrammableStageDescriptor {
+                    module: &cs_module,
+                    entry_point: "main",
+                },
+            })
+        };
+
+        Self {
+            count_pipeline: create_pipeline(include_bytes!("grid_count.comp.spv")),
+            prefix_sum_pipeline: create_pipeline(include_bytes!("grid_prefix_sum.comp.spv")),
+            scatter_pipeline: create_pipeline(include_bytes!("grid_scatter.comp.spv")),
+            sort_pipeline: create_pipeline(include_bytes!("grid_sort.comp.spv")),
+            bind_group_layout,
+        }
+    }
+}
+
+///
+/// Returns the number of work groups along x and y axes covering `count` invocations. Work groups wrap to the next
+/// row, because the number of work groups along one axis is limited.
+///
+pub fn work_groups(count: u32) -> (u32, u32) {
+    const MAX_WORK_GROUPS: u32 = 65535;
+
+    let groups = (count + GRID_WORK_GROUP_SIZE - 1) / GRID_WORK_GROUP_SIZE;
+    let x = groups.min(MAX_WORK_GROUPS).max(1);
+    (x, (groups + x - 1) / x)
+}
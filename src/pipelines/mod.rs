@@ -2,6 +2,9 @@
 //! Module of which each submodule contains one or more WebGPU pipeline(s) for specific GPU task.
 //!
 
+pub mod grid;
 pub mod raymarch;
 pub mod render;
+#[cfg(test)]
+pub mod sdf;
 pub mod ssao;
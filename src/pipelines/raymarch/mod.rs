@@ -25,9 +25,14 @@ pub struct RaymarchGlobals {
 
     pub time: f32,
     pub solvent_radius: f32,
-    pub max_neighbours: i32,
     pub save: i32,
     pub max_steps: i32,
+    /// Non-zero if atoms of different surfaces must not form patches together.
+    pub separate_surfaces: i32,
+    /// Number of slots of the hash table of a sparse voxel grid. Zero if the voxel grid is dense.
+    pub hash_table_size: i32,
+    /// Number of slots of the hash table of patches of the voxel grid.
+    pub patch_table_size: i32,
 }
 
 unsafe impl bytemuck::Zeroable for RaymarchGlobals {}
@@ -54,9 +59,11 @@ impl Default for RaymarchGlobals {
 
             time: 0.0,
             solvent_radius: 0.0,
-            max_neighbours: 0,
             save: 0,
             max_steps: 0,
+            separate_surfaces: 0,
+            hash_table_size: 0,
+            patch_table_size: 0,
         }
     }
 }
@@ -137,6 +144,62 @@ impl RaymarchPipeline {
                         readonly: false,
                     },
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::StorageBuffer {
+                        dynamic: false,
+                        readonly: true,
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 8,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::StorageBuffer {
+                        dynamic: false,
+                        readonly: true,
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 9,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::StorageBuffer {
+                        dynamic: false,
+                        readonly: true,
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 10,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::StorageBuffer {
+                        dynamic: false,
+                        readonly: true,
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 11,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::StorageBuffer {
+                        dynamic: false,
+                        readonly: true,
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 12,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::StorageBuffer {
+                        dynamic: false,
+                        readonly: true,
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 13,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::StorageBuffer {
+                        dynamic: false,
+                        readonly: true,
+                    },
+                },
             ],
         });
 
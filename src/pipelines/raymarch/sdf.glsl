@@ -0,0 +1,340 @@
+// Signed distance function of the molecular surface shared by the shaders sampling It. Bindings of the voxel grid
+// and of Its patches must be the same in all of them.
+
+struct VoxelPointer {
+    int start;
+    int len;
+};
+
+layout(set = 0, binding = 0, std140) uniform Globals {
+    // Camera
+    mat4 projection;
+    vec4 camera_origin;
+
+    // Bounding Box
+    vec3 bb_min;
+    vec3 bb_max;
+    vec3 bb_diff;
+    vec3 bb_size;
+    vec2 window_size;
+    float voxel_length;    
+
+    // Additional Globals
+    float time;
+    float solvent_radius;
+    int save;
+    int max_steps;
+    int separate_surfaces;
+    int hash_table_size;
+    int patch_table_size;
+}
+globals;
+
+layout(set = 0, binding = 2, std430) readonly buffer AtomPositions { vec4 atom_positions[]; };
+layout(set = 0, binding = 3, std430) readonly buffer VoxelPointers { int voxel_pointers[]; };
+
+layout(set = 0, binding = 7, std430) readonly buffer AtomColors { vec4 atom_colors[]; };
+layout(set = 0, binding = 8, std430) readonly buffer AtomSurfaces { uint atom_surfaces[]; };
+layout(set = 0, binding = 9, std430) readonly buffer HashKeys { uint hash_keys[]; };
+layout(set = 0, binding = 10, std430) readonly buffer AtomSlots { uint atom_slots[]; };
+layout(set = 0, binding = 11, std430) readonly buffer PatchKeys { uint patch_keys[]; };
+layout(set = 0, binding = 12, std430) readonly buffer PatchPointers { uint patch_pointers[]; };
+layout(set = 0, binding = 13, std430) readonly buffer PatchAtoms { uint patch_atoms[]; };
+
+// Key of an empty slot of the hash table of a sparse grid
+const uint EMPTY_CELL = 0xFFFFFFFFu;
+
+// Color of the atom closest to the last position passed to 'sdf'
+vec3 surface_color = vec3(1.0);
+
+// Signed distance function of a sphere:
+// f(p) > 0 interior
+// f(p) = 0 boundary
+// f(p) < 0 exterior
+vec4 f(const vec4 atom, const vec3 p) {
+    const vec3 c = atom.xyz;
+    const float radius = atom.w;
+    const vec3 n = p - c;
+
+    return vec4(n, radius - length(n));
+}
+
+// Same as 'f' but extended with a solvent radius
+vec4 g(const vec4 atom, const vec3 p) {
+    const vec4 atom_f = f(atom, p);
+    return vec4(atom_f.xyz, atom_f.w + globals.solvent_radius);
+}
+
+// Max function of 2 SDF spheres
+vec4 max2_f(const vec4 sphere1, const vec4 sphere2, const vec3 p) {
+    const vec4 f1 = f(sphere1, p);
+    const vec4 f2 = f(sphere2, p);
+
+    if (f1.w > f2.w) {
+        return f1;
+    } else {
+        return f2;
+    }
+}
+
+// Max function of 3 SDF spheres
+vec4 max3_f(const vec4 sphere1, const vec4 sphere2, const vec4 sphere3, const vec3 p) {
+    const vec4 f1 = f(sphere1, p);
+    const vec4 f2 = f(sphere2, p);
+    const vec4 f3 = f(sphere3, p);
+
+    if (f1.w > f2.w) {
+        if (f1.w > f3.w) {
+            return f1;
+        } else {
+            return f3;
+        }
+    } else {
+        if (f2.w > f3.w) {
+            return f2;
+        } else {
+            return f3;
+        }
+    }
+}
+
+// Gradient of a signed distance function 'g' (normal vector)
+vec3 nabla_g(const vec4 atom, const vec3 p) { return normalize(atom.xyz - p); }
+
+// Newton method to compute toroidal signed distance function of two atoms
+vec3 toroidal_newton(const vec4 atom1, const vec4 atom2, const vec3 p) {
+    vec3 x_n = p;
+    for (int i = 0; i < 7; i++) {
+        const float g1 = g(atom1, x_n).w;
+        const float g2 = g(atom2, x_n).w;
+        const vec3 v = vec3(g1, g2, 0.0);
+
+        const vec3 ng1 = nabla_g(atom1, x_n);
+        const vec3 ng2 = nabla_g(atom2, x_n);
+        const mat3 M = inverse(transpose(mat3(ng1, ng2, cross(ng1, ng2))));
+
+        x_n = x_n - (M * v);
+    }
+
+    return x_n;
+}
+
+// Toroidal signed distance function of two atoms
+vec4 toroidal_sdf(const vec4 atom1, const vec4 atom2, const vec3 p) {
+    const float R = globals.solvent_radius;
+
+    const float g1 = g(atom1, p).w;
+    const float g2 = g(atom2, p).w;
+
+    const vec3 ng1 = nabla_g(atom1, p);
+    const vec3 ng2 = nabla_g(atom2, p);
+
+    const vec3 p1 = p - g1 * ng1;
+    const vec3 p2 = p - g2 * ng2;
+
+    const bool predicate = g(atom1, p2).w > 0.0 && g(atom2, p1).w > 0.0;
+
+    if (predicate) {
+        const vec3 x_12 = toroidal_newton(atom1, atom2, p);
+        return vec4(normalize(x_12 - p), distance(p, x_12) - R);
+    } else {
+        return max(f(atom1, p), f(atom2, p));
+    }
+}
+
+// Newton method to compute spherical triangle signed distance function of three atoms
+vec3 spherical_newton(const vec4 atom1, const vec4 atom2, const vec4 atom3, const vec3 p) {
+    vec3 x_n = p;
+    for (int i = 0; i < 7; i++) {
+        const float g1 = g(atom1, x_n).w;
+        const float g2 = g(atom2, x_n).w;
+        const float g3 = g(atom3, x_n).w;
+        const vec3 v = vec3(g1, g2, g3);
+
+        const vec3 ng1 = nabla_g(atom1, x_n);
+        const vec3 ng2 = nabla_g(atom2, x_n);
+        const vec3 ng3 = nabla_g(atom3, x_n);
+        const mat3 M = inverse(transpose(mat3(ng1, ng2, ng3)));
+
+        x_n = x_n - (M * v);
+    }
+
+    return x_n;
+}
+
+// Spherical triangle signed distance function of three atoms
+vec4 spherical_sdf(const vec4 atom1, const vec4 atom2, const vec4 atom3, const vec3 p) {
+    const float R = globals.solvent_radius;
+
+    const float g1 = g(atom1, p).w;
+    const float g2 = g(atom2, p).w;
+    const float g3 = g(atom3, p).w;
+
+    const vec3 ng1 = nabla_g(atom1, p);
+    const vec3 ng2 = nabla_g(atom2, p);
+    const vec3 ng3 = nabla_g(atom3, p);
+
+    const vec3 p1 = p - g1 * ng1;
+    const vec3 p2 = p - g2 * ng2;
+
+    const vec3 x_12 = toroidal_newton(atom1, atom2, p);
+    const vec3 x_13 = toroidal_newton(atom1, atom3, p);
+    const vec3 x_23 = toroidal_newton(atom2, atom3, p);
+    const bool predicate_12 = g(atom1, p).w >= -R && g(atom2, p).w >= -R && g(atom3, x_12).w > 0;
+    const bool predicate_13 = g(atom1, p).w >= -R && g(atom3, p).w >= -R && g(atom2, x_13).w > 0;
+    const bool predicate_23 = g(atom2, p).w >= -R && g(atom3, p).w >= -R && g(atom1, x_23).w > 0;
+    const bool predicate_123 = predicate_12 && predicate_13 && predicate_23;
+
+    if (predicate_123) {
+        const vec3 x_123 = spherical_newton(atom1, atom2, atom3, p);
+        return vec4(normalize(x_123 - p), distance(p, x_123) - R);
+    } else {
+        return max(max(f(atom1, p), f(atom2, p)), f(atom3, p));
+    }
+}
+
+// Returns the first slot of a cell in a hash table with a power of two slots. Must match 'hash_cell' of the voxel grid.
+uint hash_cell(const uint cell, const int table_size) { return (cell * 2654435761u) & uint(table_size - 1); }
+
+// Returns the atoms of a cell. Sparse grids store only the cells containing atoms in a hash table with linear probing.
+VoxelPointer voxel_pointer(const int index) {
+    if (globals.hash_table_size == 0) {
+        return VoxelPointer(voxel_pointers[index * 2], voxel_pointers[index * 2 + 1]);
+    }
+
+    uint slot = hash_cell(uint(index), globals.hash_table_size);
+    for (int i = 0; i < globals.hash_table_size; i++) {
+        const uint key = hash_keys[slot];
+        if (key == uint(index)) {
+            return VoxelPointer(voxel_pointers[slot * 2], voxel_pointers[slot * 2 + 1]);
+        }
+        if (key == EMPTY_CELL) {
+            break;
+        }
+
+        slot = (slot + 1) & uint(globals.hash_table_size - 1);
+    }
+
+    return VoxelPointer(0, 0);
+}
+
+// Start of the pairs and triples of atoms forming patches in a cell, followed by their numbers
+uvec3 patch_pointer(const int index) {
+    uint slot = hash_cell(uint(index), globals.patch_table_size);
+    for (int i = 0; i < globals.patch_table_size; i++) {
+        const uint key = patch_keys[slot];
+        if (key == uint(index)) {
+            return uvec3(patch_pointers[slot * 3], patch_pointers[slot * 3 + 1], patch_pointers[slot * 3 + 2]);
+        }
+        if (key == EMPTY_CELL) {
+            break;
+        }
+
+        slot = (slot + 1) & uint(globals.patch_table_size - 1);
+    }
+
+    return uvec3(0);
+}
+
+// Atoms form patches at a position only if they are close enough. Patches reach up to 'radius + 2 * solvent_radius'
+// from the centre of the atom, which is never more than the voxel length.
+bool forms_patch(const vec4 atom, const vec3 position) { return distance(atom.xyz, position) < atom.w + 2.0 * globals.solvent_radius; }
+
+// Calculates the signed distance at a given position
+vec4 sdf(vec3 position) {
+    float d = -2.0;
+    vec3 normal = vec3(0.0);
+    float closest_d = -1.0e10;
+
+    // 1. Find corresponding cell in the grid
+    const vec3 center_grid_position_vec3 = (position - globals.bb_min) / globals.voxel_length;
+    const ivec3 center_grid_position_ivec3 = ivec3(floor(center_grid_position_vec3));
+
+    // 2. Iterate over 3^3 neighbourhood
+    const int width = int(globals.bb_size.x);
+    const int height = int(globals.bb_size.y);
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            for (int z = -1; z <= 1; z++) {
+                const ivec3 grid_position = center_grid_position_ivec3 + ivec3(x, y, z);
+
+                if (any(greaterThanEqual(grid_position, ivec3(globals.bb_size))) || any(lessThan(grid_position, ivec3(0)))) {
+                    continue;
+                }
+
+                // Find the index of the voxel
+                const int index = (width * height * grid_position.z) + (width * grid_position.y) + grid_position.x;
+                const VoxelPointer pointer = voxel_pointer(index);
+
+                // Go over the atoms in the voxel grid cell
+                for (int atom_index = 0; atom_index < pointer.len; atom_index++) {
+                    const vec4 atom = atom_positions[pointer.start + atom_index];
+                    const vec4 f = f(atom, position);
+                    if (f.w > d) {
+                        d = f.w;
+                        normal = f.xyz;
+                    }
+                    if (f.w > closest_d) {
+                        closest_d = f.w;
+                        surface_color = atom_colors[pointer.start + atom_index].rgb;
+                    }
+                }
+            }
+        }
+    }
+
+    if (any(greaterThanEqual(center_grid_position_ivec3, ivec3(globals.bb_size))) || any(lessThan(center_grid_position_ivec3, ivec3(0)))) {
+        return vec4(normal, d);
+    }
+
+    // 3. Loop through the precomputed pairs and triples of the cell that can form a surface
+    const int index = (width * height * center_grid_position_ivec3.z) + (width * center_grid_position_ivec3.y) + center_grid_position_ivec3.x;
+    const uvec3 patches = patch_pointer(index);
+    for (uint i = 0; i < patches.y; i++) {
+        const uint slot1 = atom_slots[patch_atoms[patches.x + 2 * i]];
+        const uint slot2 = atom_slots[patch_atoms[patches.x + 2 * i + 1]];
+
+        // Separated surfaces only form patches of atoms of the same surface
+        if (globals.separate_surfaces != 0 && atom_surfaces[slot1] != atom_surfaces[slot2]) {
+            continue;
+        }
+
+        const vec4 atom1 = atom_positions[slot1];
+        const vec4 atom2 = atom_positions[slot2];
+        if (!forms_patch(atom1, position) || !forms_patch(atom2, position)) {
+            continue;
+        }
+
+        const vec4 toroidal_patch = toroidal_sdf(atom1, atom2, position);
+        if (toroidal_patch.w > d) {
+            d = toroidal_patch.w;
+            normal = toroidal_patch.xyz;
+        }
+    }
+
+    const uint triples_start = patches.x + 2 * patches.y;
+    for (uint i = 0; i < patches.z; i++) {
+        const uint slot1 = atom_slots[patch_atoms[triples_start + 3 * i]];
+        const uint slot2 = atom_slots[patch_atoms[triples_start + 3 * i + 1]];
+        const uint slot3 = atom_slots[patch_atoms[triples_start + 3 * i + 2]];
+
+        if (globals.separate_surfaces != 0 && (atom_surfaces[slot1] != atom_surfaces[slot2] || atom_surfaces[slot1] != atom_surfaces[slot3])) {
+            continue;
+        }
+
+        const vec4 atom1 = atom_positions[slot1];
+        const vec4 atom2 = atom_positions[slot2];
+        const vec4 atom3 = atom_positions[slot3];
+        if (!forms_patch(atom1, position) || !forms_patch(atom2, position) || !forms_patch(atom3, position)) {
+            continue;
+        }
+
+        const vec4 spherical_patch = spherical_sdf(atom1, atom2, atom3, position);
+        if (spherical_patch.w > d) {
+            d = spherical_patch.w;
+            normal = spherical_patch.xyz;
+        }
+    }
+
+    return vec4(normal, d);
+}
@@ -0,0 +1,296 @@
+//!
+//! Pipeline sampling the signed distance function of the voxel grid at given positions. The shader shares the signed
+//! distance function with the ray marching, so It can be compared with the CPU reference in `sdf`.
+//!
+
+use crate::grid::*;
+use crate::patches::*;
+use crate::pipelines::raymarch::RaymarchGlobals;
+use crate::sdf::SdfSample;
+use crate::utils::load_glsl;
+use nalgebra_glm as glm;
+use wgpu;
+
+/// Number of invocations of one work group of the sampling pass.
+pub const SDF_WORK_GROUP_SIZE: u32 = 64;
+
+pub struct SdfPipeline {
+    pub pipeline: wgpu::ComputePipeline,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl SdfPipeline {
+    pub fn new(device: &wgpu::Device) -> Self {
+        // Shaders
+        let cs_bytes = load_glsl(include_bytes!("sdf.comp.spv"));
+        let cs_module = device.create_shader_module(&cs_bytes);
+
+        // Bind Groups
+        let storage_buffer = |binding, readonly| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStage::COMPUTE,
+            ty: wgpu::BindingType::StorageBuffer { dynamic: false, readonly },
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Sdf bind group layout"),
+            bindings: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                },
+                // Voxel grid bound like for the ray marching
+                storage_buffer(2, true),
+                storage_buffer(3, true),
+                storage_buffer(7, true),
+                storage_buffer(8, true),
+                storage_buffer(9, true),
+                storage_buffer(10, true),
+                storage_buffer(11, true),
+                storage_buffer(12, true),
+                storage_buffer(13, true),
+                // Sampled positions and samples
+                storage_buffer(14, true),
+                storage_buffer(15, false),
+            ],
+        });
+
+        // Pipeline
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[&bind_group_layout],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            layout: &pipeline_layout,
+            compute_stage: wgpu::ProgrammableStageDescriptor {
+                module: &cs_module,
+                entry_point: "main",
+            },
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+        }
+    }
+
+    ///
+    /// Samples the signed distance function of the voxel grid at positions in render space. The globals must describe
+    /// the grid and the solvent radius like for the ray marching.
+    ///
+    pub async fn sample(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        globals: &RaymarchGlobals,
+        voxel_grid: &VoxelGrid,
+        positions: &[glm::Vec3],
+    ) -> Vec<SdfSample> {
+        if positions.is_empty() {
+            return Vec::new();
+        }
+
+        let sample_count = positions.len() as u32;
+        let globals_buffer = device.create_buffer_with_data(bytemuck::cast_slice(&[*globals]), wgpu::BufferUsage::UNIFORM);
+        let positions: Vec<f32> = positions
+            .iter()
+            .flat_map(|position| vec![position.x, position.y, position.z, 1.0])
+            .collect();
+        let positions_size = positions.len() * std::mem::size_of::<f32>();
+        let positions_buffer = device.create_buffer_with_data(bytemuck::cast_slice(&positions), wgpu::BufferUsage::STORAGE_READ);
+
+        // Every sample takes the normal with the distance and the color
+        let samples_size = 2 * positions_size;
+        let samples_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sdf samples buffer"),
+            size: samples_size as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_SRC,
+        });
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sdf staging buffer"),
+            size: samples_size as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+        });
+
+        let buffer = |binding, buffer, size: usize| wgpu::Binding {
+            binding,
+            resource: wgpu::BindingResource::Buffer {
+                buffer,
+                range: 0..size as wgpu::BufferAddress,
+            },
+        };
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Sdf bind group"),
+            layout: &self.bind_group_layout,
+            bindings: &[
+                buffer(0, &globals_buffer, std::mem::size_of::<RaymarchGlobals>()),
+                buffer(2, &voxel_grid.voxels, voxel_grid.voxels_len * std::mem::size_of::<f32>()),
+                buffer(
+                    3,
+                    &voxel_grid.voxel_pointers,
+                    voxel_grid.voxel_pointers_len * std::mem::size_of::<VoxelPointer>(),
+                ),
+                buffer(7, &voxel_grid.colors, voxel_grid.colors_len * std::mem::size_of::<f32>()),
+                buffer(8, &voxel_grid.surfaces, voxel_grid.surfaces_len * std::mem::size_of::<u32>()),
+                buffer(
+                    9,
+                    &voxel_grid.hash_keys,
+                    voxel_grid.hash_keys_len.max(1) * std::mem::size_of::<u32>(),
+                ),
+                buffer(
+                    10,
+                    &voxel_grid.atom_slots,
+                    voxel_grid.atom_slots_len.max(1) * std::mem::size_of::<u32>(),
+                ),
+                buffer(
+                    11,
+                    &voxel_grid.patches.keys,
+                    voxel_grid.patches.keys_len * std::mem::size_of::<u32>(),
+                ),
+                buffer(
+                    12,
+                    &voxel_grid.patches.pointers,
+                    voxel_grid.patches.keys_len * std::mem::size_of::<PatchPointer>(),
+                ),
+                buffer(
+                    13,
+                    &voxel_grid.patches.atoms,
+                    voxel_grid.patches.atoms_len * std::mem::size_of::<u32>(),
+                ),
+                buffer(14, &positions_buffer, positions_size),
+                buffer(15, &samples_buffer, samples_size),
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Sdf encoder"),
+        });
+        {
+            let mut cpass = encoder.begin_compute_pass();
+            cpass.set_pipeline(&self.pipeline);
+            cpass.set_bind_group(0, &bind_group, &[]);
+            cpass.dispatch((sample_count + SDF_WORK_GROUP_SIZE - 1) / SDF_WORK_GROUP_SIZE, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&samples_buffer, 0, &staging_buffer, 0, samples_size as wgpu::BufferAddress);
+        queue.submit(&[encoder.finish()]);
+
+        let mapping = staging_buffer.map_read(0, samples_size as wgpu::BufferAddress);
+        device.poll(wgpu::Maintain::Wait);
+        let mapping = mapping.await.expect("Could not read the samples of the signed distance function");
+
+        let samples: &[f32] = bytemuck::cast_slice(mapping.as_slice());
+        samples
+            .chunks(8)
+            .map(|sample| SdfSample {
+                normal: glm::vec3(sample[0], sample[1], sample[2]),
+                distance: sample[3],
+                color: glm::vec3(sample[4], sample[5], sample[6]),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdf::{Lattice, SurfaceSdf};
+
+    const SOLVENT_RADIUS: f32 = 1.4;
+
+    ///
+    /// Returns a device of any adapter, including software ones like SwiftShader or llvmpipe. Panics if there is no
+    /// adapter, so that the ignored test does not pass without running.
+    ///
+    fn device() -> (wgpu::Device, wgpu::Queue) {
+        futures::executor::block_on(async {
+            let adapter = wgpu::Adapter::request(
+                &wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::Default,
+                    compatible_surface: None,
+                },
+                wgpu::BackendBit::PRIMARY | wgpu::BackendBit::SECONDARY,
+            )
+            .await
+            .expect("No adapter to compare the signed distance functions on");
+
+            adapter
+                .request_device(&wgpu::DeviceDescriptor {
+                    extensions: wgpu::Extensions {
+                        anisotropic_filtering: false,
+                    },
+                    limits: wgpu::Limits::default(),
+                })
+                .await
+        })
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan/GL adapter"]
+    fn gpu_matches_cpu_reference() {
+        let (device, queue) = device();
+        let pipeline = SdfPipeline::new(&device);
+        let lattice = Lattice::new();
+        let atoms = &lattice.atoms;
+
+        // Dense and sparse grids are looked up differently
+        for &dense_budget in [MAX_DENSE_GRID_SIZE, 0].iter() {
+            let grid = VoxelGridData::with_dense_budget(
+                SOLVENT_RADIUS,
+                atoms.clone(),
+                lattice.colors.clone(),
+                lattice.surfaces.clone(),
+                dense_budget,
+            );
+            let patches = PatchLists::new(&grid, atoms, SOLVENT_RADIUS).unwrap();
+            let voxel_grid = VoxelGrid::upload(&device, &grid, &patches);
+            let positions = lattice.sample_positions(&grid);
+
+            for &separate_surfaces in [false, true].iter() {
+                let globals = RaymarchGlobals {
+                    bb_min: grid.bb_min.into(),
+                    bb_max: grid.bb_max.into(),
+                    bb_diff: grid.bb_diff.into(),
+                    bb_size: grid.bb_size.into(),
+                    voxel_length: grid.voxel_length,
+                    solvent_radius: SOLVENT_RADIUS,
+                    separate_surfaces: separate_surfaces as i32,
+                    hash_table_size: voxel_grid.hash_keys_len as i32,
+                    patch_table_size: voxel_grid.patches.keys_len as i32,
+                    ..Default::default()
+                };
+                let samples = futures::executor::block_on(pipeline.sample(&device, &queue, &globals, &voxel_grid, &positions));
+                let sdf = SurfaceSdf {
+                    grid: &grid,
+                    patches: &patches,
+                    solvent_radius: SOLVENT_RADIUS,
+                    separate_surfaces,
+                };
+
+                let mut tested = 0;
+                for (position, actual) in positions.iter().zip(samples.iter()) {
+                    let expected = sdf.sdf(position);
+                    // Only positions near the surface matter for ray marching
+                    if !(-1.0..=0.05).contains(&expected.distance) {
+                        continue;
+                    }
+
+                    tested += 1;
+                    assert!(
+                        (actual.distance - expected.distance).abs() < 1.0e-3,
+                        "{} differs from {} at {:?}",
+                        actual.distance,
+                        expected.distance,
+                        position
+                    );
+                    assert!(
+                        glm::distance(&actual.color, &expected.color) < 1.0e-6,
+                        "{} differs from {} at {:?}",
+                        actual.color,
+                        expected.color,
+                        position
+                    );
+                }
+                assert!(tested > 200);
+            }
+        }
+    }
+}
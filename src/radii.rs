@@ -0,0 +1,361 @@
+//!
+//! Module containing van der Waals radii of chemical elements.
+//!
+//! Radii are taken from Bondi (1964) where available, extended by Mantina et al. (2009) for the rest of the main group
+//! and by Alvarez (2013) for transition metals not covered by Bondi.
+//! Users can override them by their own `RadiiTable`.
+//!
+
+use crate::molecule::Atom;
+
+/// Radius used for elements that are not present in the table.
+pub const DEFAULT_RADIUS: f32 = 1.8;
+
+///
+/// Returns van der Waals radius of an element given by Its symbol. The symbol is case insensitive.
+/// Unknown elements get `DEFAULT_RADIUS`.
+///
+pub fn element_radius(element: &str) -> f32 {
+    match element.to_ascii_uppercase().as_str() {
+        // Bondi
+        "H" => 1.20,
+        "HE" => 1.40,
+        "LI" => 1.82,
+        "C" => 1.70,
+        "N" => 1.55,
+        "O" => 1.52,
+        "F" => 1.47,
+        "NE" => 1.54,
+        "NA" => 2.27,
+        "MG" => 1.73,
+        "SI" => 2.10,
+        "P" => 1.80,
+        "S" => 1.80,
+        "CL" => 1.75,
+        "AR" => 1.88,
+        "K" => 2.75,
+        "NI" => 1.63,
+        "CU" => 1.40,
+        "ZN" => 1.39,
+        "GA" => 1.87,
+        "AS" => 1.85,
+        "SE" => 1.90,
+        "BR" => 1.85,
+        "KR" => 2.02,
+        "PD" => 1.63,
+        "AG" => 1.72,
+        "CD" => 1.58,
+        "IN" => 1.93,
+        "SN" => 2.17,
+        "TE" => 2.06,
+        "I" => 1.98,
+        "XE" => 2.16,
+        "PT" => 1.72,
+        "AU" => 1.66,
+        "HG" => 1.55,
+        "TL" => 1.96,
+        "PB" => 2.02,
+        "U" => 1.86,
+        // Mantina et al.
+        "BE" => 1.53,
+        "B" => 1.92,
+        "AL" => 1.84,
+        "CA" => 2.31,
+        "GE" => 2.11,
+        "RB" => 3.03,
+        "SR" => 2.49,
+        "SB" => 2.06,
+        "CS" => 3.43,
+        "BA" => 2.68,
+        "BI" => 2.07,
+        "PO" => 1.97,
+        "AT" => 2.02,
+        "RN" => 2.20,
+        // Alvarez
+        "SC" => 2.58,
+        "TI" => 2.46,
+        "V" => 2.42,
+        "CR" => 2.45,
+        "MN" => 2.45,
+        "FE" => 2.44,
+        "CO" => 2.40,
+        "MO" => 2.45,
+        "W" => 2.42,
+        _ => DEFAULT_RADIUS,
+    }
+}
+
+///
+/// Guesses the element symbol from a PDB atom name, e.g. `CA` -> `C`, `1HB2` -> `H`, `FE` -> `FE`.
+///
+/// Ions are residues of their own with the same name, e.g. `ZN ZN`, so the name is the full element symbol.
+/// Other atom names of organic elements start with the element letter, so the single letter wins for them.
+/// Otherwise two letter symbols with a known radius (typically metal ions) are preferred.
+///
+pub fn element_from_atom_name(name: &str, residue_name: &str) -> String {
+    if !name.is_empty() && name.eq_ignore_ascii_case(residue_name) && name.chars().all(|c| c.is_ascii_alphabetic()) {
+        return name.to_ascii_uppercase();
+    }
+
+    let letters: String = name
+        .trim()
+        .trim_start_matches(|c: char| c.is_ascii_digit())
+        .chars()
+        .take_while(|c| c.is_ascii_alphabetic())
+        .collect::<String>()
+        .to_ascii_uppercase();
+
+    let first = match letters.get(0..1) {
+        Some(first) => first,
+        None => return String::new(),
+    };
+
+    // No standard residue has atoms named like this, but ligands have chlorine atoms, e.g. `CL1`, and selenomethionine
+    // has a selenium atom
+    if ["CL", "SE"].contains(&letters.as_str()) {
+        return letters;
+    }
+
+    if ["C", "N", "O", "S", "H", "P"].contains(&first) {
+        return first.to_string();
+    }
+
+    match letters.get(0..2) {
+        Some(two) if element_radius(two) != DEFAULT_RADIUS => two.to_string(),
+        _ => first.to_string(),
+    }
+}
+
+///
+/// Guesses the element symbol from the four columns of the atom name of a PDB record, which are not trimmed.
+///
+/// Element symbols are right-justified in the first two columns, so the name of a C-alpha atom starts in the second
+/// column, ` CA `, while the name of a calcium ion starts in the first, `CA  `. Hydrogen atoms with four character names,
+/// e.g. `HG21`, fill the first column as well. Names that do not follow these rules are guessed by
+/// `element_from_atom_name`.
+///
+pub fn element_from_pdb_name(name: &str, residue_name: &str) -> String {
+    let trimmed = name.trim();
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(' '), Some(second)) if second.is_ascii_alphabetic() => second.to_ascii_uppercase().to_string(),
+        (Some(first), Some(second)) if first.is_ascii_alphabetic() && second.is_ascii_alphabetic() => {
+            let symbol = name[0..2].to_ascii_uppercase();
+            if first.eq_ignore_ascii_case(&'H') && trimmed.len() == 4 {
+                "H".to_string()
+            } else if element_radius(&symbol) != DEFAULT_RADIUS {
+                symbol
+            } else {
+                element_from_atom_name(trimmed, residue_name)
+            }
+        }
+        _ => element_from_atom_name(trimmed, residue_name),
+    }
+}
+
+///
+/// One line of a radii table. `None` fields are wildcards.
+///
+#[derive(Clone, Debug)]
+struct RadiusRule {
+    residue_name: Option<String>,
+    atom_name: Option<String>,
+    element: Option<String>,
+    radius: f32,
+}
+
+impl RadiusRule {
+    fn matches(&self, atom: &Atom) -> bool {
+        fn field_matches(rule: &Option<String>, value: &str) -> bool {
+            match rule {
+                Some(rule) => rule.eq_ignore_ascii_case(value),
+                None => true,
+            }
+        }
+
+        field_matches(&self.residue_name, &atom.residue_name)
+            && field_matches(&self.atom_name, &atom.name)
+            && field_matches(&self.element, &atom.element)
+    }
+
+    /// Number of non-wildcard fields. More specific rules take precedence.
+    fn specificity(&self) -> usize {
+        [&self.residue_name, &self.atom_name, &self.element]
+            .iter()
+            .filter(|field| field.is_some())
+            .count()
+    }
+}
+
+///
+/// User-supplied table of radii, e.g. united-atom or coarse-grained bead radii.
+///
+/// The table is a text file with one rule per line in the format `residue atom element radius`.
+/// Any of the first three columns can be `*` to match everything. Text after `#` is a comment.
+/// When several rules match an atom, the most specific one wins and among equally specific rules the last one wins.
+///
+/// ```text
+/// # residue  atom  element  radius
+/// *          *     C        1.90
+/// ALA        CB    *        2.00
+/// ```
+///
+#[derive(Clone, Debug)]
+pub struct RadiiTable {
+    /// Name of the table shown to the user. Usually the file name.
+    pub name: String,
+    rules: Vec<RadiusRule>,
+}
+
+impl RadiiTable {
+    ///
+    /// Loads the table from a file.
+    ///
+    pub fn from_file(path: &std::path::Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let name = path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+
+        Self::parse(name, &content).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    ///
+    /// Parses the table from Its textual representation.
+    ///
+    pub fn parse(name: String, content: &str) -> Result<Self, String> {
+        let mut rules = Vec::new();
+        for (line_number, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            if columns.len() != 4 {
+                return Err(format!("line {}: expected 4 columns, found {}", line_number + 1, columns.len()));
+            }
+
+            let pattern = |column: &str| if column == "*" { None } else { Some(column.to_string()) };
+            let radius = columns[3]
+                .parse::<f32>()
+                .map_err(|_| format!("line {}: invalid radius `{}`", line_number + 1, columns[3]))?;
+            if !radius.is_finite() || radius <= 0.0 {
+                return Err(format!("line {}: radius must be positive and finite", line_number + 1));
+            }
+
+            rules.push(RadiusRule {
+                residue_name: pattern(columns[0]),
+                atom_name: pattern(columns[1]),
+                element: pattern(columns[2]),
+                radius,
+            });
+        }
+
+        Ok(Self { name, rules })
+    }
+
+    ///
+    /// Returns the radius of the most specific rule matching the atom.
+    ///
+    pub fn radius(&self, atom: &Atom) -> Option<f32> {
+        let mut best: Option<&RadiusRule> = None;
+        for rule in self.rules.iter().filter(|rule| rule.matches(atom)) {
+            match best {
+                Some(best_rule) if best_rule.specificity() > rule.specificity() => {}
+                _ => best = Some(rule),
+            }
+        }
+
+        best.map(|rule| rule.radius)
+    }
+}
+
+///
+/// Assigns radii to atoms. Radii given by the structure file, e.g. PQR, are used directly. Other atoms are looked up in
+/// the `table` if there is one, otherwise the element radius is used.
+/// Returns indices of atoms without a file radius that did not match any rule of the table. These get their element
+/// radius.
+///
+pub fn assign_radii(atoms: &mut [Atom], table: Option<&RadiiTable>) -> Vec<usize> {
+    let mut unmatched = Vec::new();
+    for (index, atom) in atoms.iter_mut().enumerate() {
+        if let Some(file_radius) = atom.file_radius {
+            atom.radius = file_radius;
+            continue;
+        }
+
+        let table_radius = table.and_then(|table| table.radius(atom));
+        if table.is_some() && table_radius.is_none() {
+            unmatched.push(index);
+        }
+
+        atom.radius = table_radius.unwrap_or_else(|| element_radius(&atom.element));
+    }
+
+    unmatched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_radii_are_rejected() {
+        assert!(RadiiTable::parse(String::new(), "ALA CA C 1.9").is_ok());
+        for radius in ["0", "-1.5", "nan", "inf", "-inf", "x"].iter() {
+            let content = format!("ALA CA C {}", radius);
+            assert!(RadiiTable::parse(String::new(), &content).is_err(), "radius {} is accepted", radius);
+        }
+    }
+
+    #[test]
+    fn ambiguous_names_resolve_to_elements() {
+        // Atoms of standard residues and of ligands
+        assert_eq!(element_from_atom_name("CA", "ALA"), "C");
+        assert_eq!(element_from_atom_name("CD", "GLU"), "C");
+        assert_eq!(element_from_atom_name("NE", "ARG"), "N");
+        assert_eq!(element_from_atom_name("HG", "SER"), "H");
+        assert_eq!(element_from_atom_name("NA", "HEM"), "N");
+        assert_eq!(element_from_atom_name("FE", "HEM"), "FE");
+        assert_eq!(element_from_atom_name("CL", "LIG"), "CL");
+        assert_eq!(element_from_atom_name("CL12", "LIG"), "CL");
+        assert_eq!(element_from_atom_name("SE", "MSE"), "SE");
+        assert_eq!(element_from_atom_name("1HB2", "ALA"), "H");
+
+        // Ions in residues of their own
+        for ion in ["CL", "NA", "CA", "CU", "CD", "CO", "HG", "NI", "SE"].iter() {
+            assert_eq!(element_from_atom_name(ion, ion), *ion);
+        }
+
+        // Justified PDB names
+        assert_eq!(element_from_pdb_name(" CA ", "ALA"), "C");
+        assert_eq!(element_from_pdb_name("CA  ", "LIG"), "CA");
+        assert_eq!(element_from_pdb_name(" NA ", "HEM"), "N");
+        assert_eq!(element_from_pdb_name("NA  ", "LIG"), "NA");
+        assert_eq!(element_from_pdb_name("CL1 ", "LIG"), "CL");
+        assert_eq!(element_from_pdb_name("HG  ", "LIG"), "HG");
+        assert_eq!(element_from_pdb_name(" HG ", "SER"), "H");
+        assert_eq!(element_from_pdb_name("HG21", "THR"), "H");
+        assert_eq!(element_from_pdb_name("1HB2", "ALA"), "H");
+        assert_eq!(element_from_pdb_name("CO  ", "LIG"), "CO");
+    }
+
+    #[test]
+    fn file_radii_take_precedence_over_table() {
+        let table = RadiiTable::parse(String::new(), "* * C 2.5").unwrap();
+        let atom = |element: &str, file_radius| Atom {
+            element: element.to_string(),
+            file_radius,
+            ..Atom::default()
+        };
+        let mut atoms = vec![atom("C", Some(1.2)), atom("C", None), atom("N", None), atom("N", Some(1.3))];
+
+        let unmatched = assign_radii(&mut atoms, Some(&table));
+        let radii: Vec<f32> = atoms.iter().map(|atom| atom.radius).collect();
+        assert_eq!(radii, vec![1.2, 2.5, element_radius("N"), 1.3]);
+        assert_eq!(unmatched, vec![2]);
+
+        assign_radii(&mut atoms, None);
+        assert_eq!(atoms[0].radius, 1.2);
+        assert_eq!(atoms[1].radius, element_radius("C"));
+    }
+}
@@ -0,0 +1,342 @@
+//!
+//! Module containing the molecules shown together in one scene and collecting their atoms that are put into the voxel grid.
+//!
+
+use crate::assembly::*;
+use crate::crystal::*;
+use crate::molecule::*;
+use crate::selection::*;
+use nalgebra_glm as glm;
+use std::path::PathBuf;
+
+/// Color of atoms of the loaded structure.
+pub const ATOM_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+/// Color of atoms of symmetry mates, distinguishing them from the asymmetric unit.
+pub const SYMMETRY_MATE_COLOR: [f32; 4] = [0.55, 0.7, 1.0, 1.0];
+/// Named colors molecules are tinted with. New molecules get them in turn, so the first molecule is not tinted.
+pub const MOLECULE_COLORS: [(&str, [f32; 4]); 5] = [
+    ("White", [1.0, 1.0, 1.0, 1.0]),
+    ("Orange", [1.0, 0.75, 0.45, 1.0]),
+    ("Green", [0.6, 0.95, 0.6, 1.0]),
+    ("Violet", [0.8, 0.65, 1.0, 1.0]),
+    ("Pink", [1.0, 0.65, 0.85, 1.0]),
+];
+
+///
+/// Atoms of the scene in format `(x, y, z, radius)` with their colors, ready to be put into the voxel grid.
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SceneAtoms {
+    pub atoms: Vec<glm::Vec4>,
+    pub colors: Vec<glm::Vec4>,
+    /// Index of the surface every atom belongs to.
+    pub surfaces: Vec<u32>,
+    /// Number of atoms of the molecule matching the selection.
+    pub selected_atoms: usize,
+    /// Largest number of symmetry mates generated for one frame.
+    pub symmetry_mates: usize,
+}
+
+impl SceneAtoms {
+    ///
+    /// Collects selected atoms of the given frames. If an assembly is given, It is built from the selected atoms.
+    /// If a crystal is given, symmetry mates within the given distance from the selected atoms are added as well.
+    /// The selection is evaluated in the current frame of the molecule.
+    ///
+    pub fn collect(
+        molecule: &Molecule,
+        frames: &[usize],
+        selection: Option<&Selection>,
+        assembly: Option<&Assembly>,
+        symmetry: Option<(&Crystal, f32)>,
+    ) -> Self {
+        let mut scene = Self::default();
+
+        let selected = match selection {
+            Some(selection) => selection.evaluate(&molecule.atoms),
+            None => vec![true; molecule.atoms.len()],
+        };
+        scene.selected_atoms = selected.iter().filter(|selected| **selected).count();
+        if scene.selected_atoms == 0 {
+            return scene;
+        }
+
+        for &frame in frames {
+            let frame_atoms: Vec<Atom> = molecule
+                .frame_atoms(frame)
+                .zip(selected.iter())
+                .filter(|(_, selected)| **selected)
+                .map(|(atom, _)| atom)
+                .collect();
+            let frame_atoms = match assembly {
+                Some(assembly) => assembly.build(&frame_atoms),
+                None => frame_atoms,
+            };
+
+            scene.atoms.extend(frame_atoms.iter().map(|atom| atom.to_vec4()));
+            scene.colors.extend(frame_atoms.iter().map(|_| glm::Vec4::from(ATOM_COLOR)));
+
+            if let Some((crystal, radius)) = symmetry {
+                let positions: Vec<glm::Vec3> = frame_atoms.iter().map(|atom| atom.position).collect();
+                let mates = crystal.symmetry_mates(&positions, radius);
+                for mate in &mates {
+                    scene.atoms.extend(frame_atoms.iter().map(|atom| {
+                        let position = mate * glm::vec4(atom.position.x, atom.position.y, atom.position.z, 1.0);
+                        glm::vec4(position.x, position.y, position.z, atom.radius)
+                    }));
+                    scene
+                        .colors
+                        .extend(frame_atoms.iter().map(|_| glm::Vec4::from(SYMMETRY_MATE_COLOR)));
+                }
+                scene.symmetry_mates = scene.symmetry_mates.max(mates.len());
+            }
+        }
+
+        scene.surfaces = vec![0; scene.atoms.len()];
+
+        scene
+    }
+
+    ///
+    /// Puts atoms of the visible molecules into one scene. Atoms are moved by the transforms of their molecules,
+    /// tinted by their colors and every molecule forms Its own surface.
+    ///
+    pub fn combine(molecules: &[SceneMolecule]) -> Self {
+        let mut scene = Self::default();
+
+        for (index, molecule) in molecules.iter().enumerate().filter(|(_, molecule)| molecule.visible) {
+            scene.extend(molecule.placed_atoms(index as u32));
+        }
+
+        scene
+    }
+
+    ///
+    /// Appends atoms of another scene to this one.
+    ///
+    pub fn extend(&mut self, other: SceneAtoms) {
+        self.atoms.extend(other.atoms);
+        self.colors.extend(other.colors);
+        self.surfaces.extend(other.surfaces);
+        self.selected_atoms += other.selected_atoms;
+        self.symmetry_mates = self.symmetry_mates.max(other.symmetry_mates);
+    }
+
+    ///
+    /// Returns three atoms shown until a structure file is loaded.
+    ///
+    pub fn test_scene() -> Self {
+        let atoms = vec![
+            glm::vec4(1.5, 0.0, 0.0, 1.0),
+            glm::vec4(-1.5, 0.0, 0.0, 1.0),
+            glm::vec4(0.0, 2.5, 0.0, 1.0),
+        ];
+
+        Self {
+            colors: vec![glm::Vec4::from(ATOM_COLOR); atoms.len()],
+            surfaces: vec![0; atoms.len()],
+            selected_atoms: 0,
+            symmetry_mates: 0,
+            atoms,
+        }
+    }
+}
+
+///
+/// Molecule shown in a scene together with other molecules. Every molecule is placed by Its own rigid transform.
+///
+pub struct SceneMolecule {
+    /// Structure file the molecule was loaded from.
+    pub path: PathBuf,
+    pub molecule: Molecule,
+    /// Index of the shown frame of `molecule`.
+    pub frame: usize,
+    /// Index of the biological assembly of `molecule` that is shown. `None` shows the asymmetric unit.
+    pub assembly: Option<usize>,
+    /// Sorted models of an ensemble shown together instead of the current frame. Empty to show only the current frame.
+    pub overlay: Vec<usize>,
+    /// Number of atoms that were not matched by the radii table.
+    pub unmatched_atoms: usize,
+
+    /// Whether the molecule is put into the voxel grid.
+    pub visible: bool,
+    /// Color the surface of the molecule is tinted with.
+    pub color: glm::Vec4,
+    /// Translation of the molecule in Å.
+    pub translation: glm::Vec3,
+    /// Rotation of the molecule around Its centre in degrees. Rotations around the x, y and z axes are applied in this order.
+    pub rotation: glm::Vec3,
+    /// Centre of the bounding box of the molecule when It was loaded.
+    centre: glm::Vec3,
+
+    /// Atoms of the molecule in the coordinates of the structure file.
+    pub atoms: SceneAtoms,
+}
+
+impl SceneMolecule {
+    ///
+    /// Creates a visible molecule without any transform showing Its first frame.
+    ///
+    pub fn new(path: PathBuf, molecule: Molecule, color: glm::Vec4, atoms: SceneAtoms) -> Self {
+        let mut bb_max = glm::vec3(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
+        let mut bb_min = glm::vec3(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+        for atom in molecule.atoms.iter() {
+            bb_max = glm::max2(&bb_max, &atom.position);
+            bb_min = glm::min2(&bb_min, &atom.position);
+        }
+        let centre = if molecule.atoms.is_empty() {
+            glm::vec3(0.0, 0.0, 0.0)
+        } else {
+            (bb_max + bb_min) / 2.0
+        };
+
+        Self {
+            path,
+            molecule,
+            frame: 0,
+            assembly: None,
+            overlay: Vec::new(),
+            unmatched_atoms: 0,
+
+            visible: true,
+            color,
+            translation: glm::vec3(0.0, 0.0, 0.0),
+            rotation: glm::vec3(0.0, 0.0, 0.0),
+            centre,
+
+            atoms,
+        }
+    }
+
+    ///
+    /// Returns the name of the structure file the molecule was loaded from.
+    ///
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .unwrap_or_else(|| self.path.as_os_str())
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    ///
+    /// Returns the rigid transform placing the molecule in the scene.
+    ///
+    pub fn transform(&self) -> glm::Mat4 {
+        let rotation = glm::rotate_x(
+            &glm::rotate_y(
+                &glm::rotate_z(&glm::identity(), self.rotation.z.to_radians()),
+                self.rotation.y.to_radians(),
+            ),
+            self.rotation.x.to_radians(),
+        );
+
+        glm::translation(&(self.centre + self.translation)) * rotation * glm::translation(&-self.centre)
+    }
+
+    ///
+    /// Returns the collected atoms moved by the transform of the molecule and tinted by Its color. They form the surface
+    /// with the given index.
+    ///
+    pub fn placed_atoms(&self, surface: u32) -> SceneAtoms {
+        let transform = self.transform();
+
+        SceneAtoms {
+            atoms: self
+                .atoms
+                .atoms
+                .iter()
+                .map(|atom| {
+                    let position = transform * glm::vec4(atom.x, atom.y, atom.z, 1.0);
+                    glm::vec4(position.x, position.y, position.z, atom.w)
+                })
+                .collect(),
+            colors: self.atoms.colors.iter().map(|color| color.component_mul(&self.color)).collect(),
+            surfaces: vec![surface; self.atoms.atoms.len()],
+            selected_atoms: self.atoms.selected_atoms,
+            symmetry_mates: self.atoms.symmetry_mates,
+        }
+    }
+
+    ///
+    /// Converts a position in the coordinates of the scene to the coordinates of the structure file.
+    ///
+    pub fn to_structure(&self, position: &glm::Vec3) -> glm::Vec3 {
+        let transform = glm::inverse(&self.transform());
+        (transform * glm::vec4(position.x, position.y, position.z, 1.0)).xyz()
+    }
+
+    ///
+    /// Collects atoms of the shown frame, or of the overlaid models, again. Symmetry mates within `symmetry_radius` are
+    /// added to crystal structures if It is given and no assembly is shown.
+    ///
+    pub fn collect_atoms(&mut self, selection: Option<&Selection>, symmetry_radius: Option<f32>) {
+        let frames = if self.overlay.is_empty() {
+            vec![self.frame]
+        } else {
+            self.overlay.clone()
+        };
+        let molecule = &self.molecule;
+        let assembly = self.assembly.and_then(|assembly| molecule.assemblies.get(assembly));
+        let symmetry = molecule
+            .crystal
+            .as_ref()
+            .filter(|_| assembly.is_none())
+            .and_then(|crystal| symmetry_radius.map(|radius| (crystal, radius)));
+
+        self.atoms = SceneAtoms::collect(molecule, &frames, selection, assembly, symmetry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::VoxelGridData;
+
+    #[test]
+    fn positions_round_trip_between_structure_and_render_space() {
+        let positions = [
+            glm::vec3(12.0, -3.5, 40.25),
+            glm::vec3(15.5, -1.0, 38.0),
+            glm::vec3(10.0, 2.0, 44.5),
+            glm::vec3(18.75, -6.0, 41.0),
+        ];
+        let molecule = Molecule::new(
+            positions
+                .iter()
+                .map(|&position| Atom {
+                    position,
+                    radius: 1.5,
+                    ..Atom::default()
+                })
+                .collect(),
+        );
+        let atoms = SceneAtoms::collect(&molecule, &[0], None, None, None);
+        let mut scene_molecule = SceneMolecule::new(PathBuf::from("test.pdb"), molecule, glm::Vec4::from(ATOM_COLOR), atoms);
+        scene_molecule.translation = glm::vec3(-25.0, 7.5, 3.0);
+        scene_molecule.rotation = glm::vec3(30.0, -45.0, 120.0);
+
+        let scene = SceneAtoms::combine(std::slice::from_ref(&scene_molecule));
+        let grid = VoxelGridData::new(1.4, scene.atoms.clone(), scene.colors, scene.surfaces);
+
+        for (index, position) in positions.iter().enumerate() {
+            let render = grid.to_render(&(scene_molecule.transform() * glm::vec4(position.x, position.y, position.z, 1.0)).xyz());
+            let slot = grid.atom_slots[index] as usize;
+            let voxel = glm::vec3(grid.voxels[4 * slot], grid.voxels[4 * slot + 1], grid.voxels[4 * slot + 2]);
+            assert!(
+                glm::distance(&render, &voxel) < 1.0e-4,
+                "{} differs from the voxel {}",
+                render,
+                voxel
+            );
+
+            let structure = scene_molecule.to_structure(&(render + grid.origin));
+            assert!(
+                glm::distance(&structure, position) < 1.0e-4,
+                "{} differs from {}",
+                structure,
+                position
+            );
+        }
+    }
+}
@@ -0,0 +1,435 @@
+//!
+//! Module containing a CPU reference implementation of the signed distance function of the molecular surface that
+//! is ray marched on the GPU. Functions mirror their counterparts in `sdf.glsl`, so tests can check the shader
+//! against analytic results without a GPU. Tests of `pipelines::sdf` compare them with the shader on a GPU.
+//!
+
+use crate::grid::*;
+use crate::patches::*;
+use nalgebra_glm as glm;
+
+/// Number of iterations of the Newton methods. Must match `sdf.glsl`.
+const NEWTON_ITERATIONS: usize = 7;
+
+///
+/// Signed distance function of a sphere. Returns the vector from the centre to the position and the distance, which is
+/// positive inside of the sphere.
+///
+pub fn f(atom: &glm::Vec4, p: &glm::Vec3) -> glm::Vec4 {
+    let n = p - atom.xyz();
+    glm::vec4(n.x, n.y, n.z, atom.w - glm::length(&n))
+}
+
+///
+/// Same as `f` but extended with a solvent radius.
+///
+pub fn g(atom: &glm::Vec4, p: &glm::Vec3, solvent_radius: f32) -> glm::Vec4 {
+    let atom_f = f(atom, p);
+    glm::vec4(atom_f.x, atom_f.y, atom_f.z, atom_f.w + solvent_radius)
+}
+
+///
+/// Gradient of `g`.
+///
+fn nabla_g(atom: &glm::Vec4, p: &glm::Vec3) -> glm::Vec3 {
+    glm::normalize(&(atom.xyz() - p))
+}
+
+///
+/// Finds the centre of the probe touching both atoms closest to the position.
+///
+pub fn toroidal_newton(atom1: &glm::Vec4, atom2: &glm::Vec4, p: &glm::Vec3, solvent_radius: f32) -> glm::Vec3 {
+    let mut x_n = *p;
+    for _ in 0..NEWTON_ITERATIONS {
+        let v = glm::vec3(g(atom1, &x_n, solvent_radius).w, g(atom2, &x_n, solvent_radius).w, 0.0);
+
+        let ng1 = nabla_g(atom1, &x_n);
+        let ng2 = nabla_g(atom2, &x_n);
+        let ng3 = glm::cross(&ng1, &ng2);
+        let m = glm::inverse(&glm::mat3(ng1.x, ng1.y, ng1.z, ng2.x, ng2.y, ng2.z, ng3.x, ng3.y, ng3.z));
+
+        x_n -= m * v;
+    }
+
+    x_n
+}
+
+///
+/// Toroidal signed distance function of two atoms. Falls back to the spheres of the atoms where the position does
+/// not lie between them.
+///
+pub fn toroidal_sdf(atom1: &glm::Vec4, atom2: &glm::Vec4, p: &glm::Vec3, solvent_radius: f32) -> glm::Vec4 {
+    let g1 = g(atom1, p, solvent_radius).w;
+    let g2 = g(atom2, p, solvent_radius).w;
+
+    let p1 = p - nabla_g(atom1, p) * g1;
+    let p2 = p - nabla_g(atom2, p) * g2;
+
+    if g(atom1, &p2, solvent_radius).w > 0.0 && g(atom2, &p1, solvent_radius).w > 0.0 {
+        let x_12 = toroidal_newton(atom1, atom2, p, solvent_radius);
+        let normal = glm::normalize(&(x_12 - p));
+        glm::vec4(normal.x, normal.y, normal.z, glm::distance(p, &x_12) - solvent_radius)
+    } else {
+        // GLSL takes the maximum component-wise
+        glm::max2(&f(atom1, p), &f(atom2, p))
+    }
+}
+
+///
+/// Finds the centre of the probe touching all three atoms closest to the position.
+///
+pub fn spherical_newton(atom1: &glm::Vec4, atom2: &glm::Vec4, atom3: &glm::Vec4, p: &glm::Vec3, solvent_radius: f32) -> glm::Vec3 {
+    let mut x_n = *p;
+    for _ in 0..NEWTON_ITERATIONS {
+        let v = glm::vec3(
+            g(atom1, &x_n, solvent_radius).w,
+            g(atom2, &x_n, solvent_radius).w,
+            g(atom3, &x_n, solvent_radius).w,
+        );
+
+        let ng1 = nabla_g(atom1, &x_n);
+        let ng2 = nabla_g(atom2, &x_n);
+        let ng3 = nabla_g(atom3, &x_n);
+        let m = glm::inverse(&glm::mat3(ng1.x, ng1.y, ng1.z, ng2.x, ng2.y, ng2.z, ng3.x, ng3.y, ng3.z));
+
+        x_n -= m * v;
+    }
+
+    x_n
+}
+
+///
+/// Spherical triangle signed distance function of three atoms. Falls back to the spheres of the atoms where the
+/// position does not lie below a probe touching all of them.
+///
+pub fn spherical_sdf(atom1: &glm::Vec4, atom2: &glm::Vec4, atom3: &glm::Vec4, p: &glm::Vec3, solvent_radius: f32) -> glm::Vec4 {
+    let r = solvent_radius;
+    let g1 = g(atom1, p, r).w;
+    let g2 = g(atom2, p, r).w;
+    let g3 = g(atom3, p, r).w;
+
+    let x_12 = toroidal_newton(atom1, atom2, p, r);
+    let x_13 = toroidal_newton(atom1, atom3, p, r);
+    let x_23 = toroidal_newton(atom2, atom3, p, r);
+    let predicate_12 = g1 >= -r && g2 >= -r && g(atom3, &x_12, r).w > 0.0;
+    let predicate_13 = g1 >= -r && g3 >= -r && g(atom2, &x_13, r).w > 0.0;
+    let predicate_23 = g2 >= -r && g3 >= -r && g(atom1, &x_23, r).w > 0.0;
+
+    if predicate_12 && predicate_13 && predicate_23 {
+        let x_123 = spherical_newton(atom1, atom2, atom3, p, r);
+        let normal = glm::normalize(&(x_123 - p));
+        glm::vec4(normal.x, normal.y, normal.z, glm::distance(p, &x_123) - r)
+    } else {
+        glm::max2(&glm::max2(&f(atom1, p), &f(atom2, p)), &f(atom3, p))
+    }
+}
+
+///
+/// Atoms form patches at a position only if they are close enough.
+///
+fn forms_patch(atom: &glm::Vec4, p: &glm::Vec3, solvent_radius: f32) -> bool {
+    glm::distance(&atom.xyz(), p) < atom.w + 2.0 * solvent_radius
+}
+
+///
+/// Value of the signed distance function at one position.
+///
+#[derive(Clone, Copy, Debug)]
+pub struct SdfSample {
+    /// Direction out of the surface. Not normalized.
+    pub normal: glm::Vec3,
+    /// Signed distance, positive inside of the surface.
+    pub distance: f32,
+    /// Color of the atom closest to the position.
+    pub color: glm::Vec3,
+}
+
+///
+/// Signed distance function of the molecular surface of the atoms of a voxel grid built on the CPU.
+///
+pub struct SurfaceSdf<'a> {
+    pub grid: &'a VoxelGridData,
+    pub patches: &'a PatchLists,
+    pub solvent_radius: f32,
+    /// Whether only atoms of the same surface form patches.
+    pub separate_surfaces: bool,
+}
+
+impl<'a> SurfaceSdf<'a> {
+    fn atom(&self, slot: usize) -> glm::Vec4 {
+        glm::make_vec4(&self.grid.voxels[4 * slot..4 * slot + 4])
+    }
+
+    fn same_surface(&self, slots: &[usize]) -> bool {
+        !self.separate_surfaces || slots.iter().all(|&slot| self.grid.surfaces[slot] == self.grid.surfaces[slots[0]])
+    }
+
+    ///
+    /// Computes the signed distance at a position in render space like `sdf` of `sdf.glsl`.
+    ///
+    pub fn sdf(&self, position: &glm::Vec3) -> SdfSample {
+        let r = self.solvent_radius;
+        let mut sample = SdfSample {
+            normal: glm::vec3(0.0, 0.0, 0.0),
+            distance: -2.0,
+            color: glm::vec3(1.0, 1.0, 1.0),
+        };
+        let mut closest_d = -1.0e10;
+
+        // 1. Find corresponding cell in the grid
+        let grid_position = (position - self.grid.bb_min) / self.grid.voxel_length;
+        let center = glm::vec3(
+            grid_position.x.floor() as i64,
+            grid_position.y.floor() as i64,
+            grid_position.z.floor() as i64,
+        );
+        let size = glm::vec3(self.grid.bb_size.x as i64, self.grid.bb_size.y as i64, self.grid.bb_size.z as i64);
+        let cell_index = |cell: glm::TVec3<i64>| {
+            if (0..3).all(|axis| 0 <= cell[axis] && cell[axis] < size[axis]) {
+                Some((size.x * size.y * cell.z + size.x * cell.y + cell.x) as usize)
+            } else {
+                None
+            }
+        };
+
+        // 2. Iterate over 3^3 neighbourhood
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let cell = match cell_index(center + glm::vec3(x, y, z)) {
+                        Some(cell) => cell,
+                        None => continue,
+                    };
+
+                    let pointer = self.grid.voxel_pointer(cell);
+                    for slot in pointer.start as usize..(pointer.start + pointer.length) as usize {
+                        let atom_f = f(&self.atom(slot), position);
+                        if atom_f.w > sample.distance {
+                            sample.distance = atom_f.w;
+                            sample.normal = atom_f.xyz();
+                        }
+                        if atom_f.w > closest_d {
+                            closest_d = atom_f.w;
+                            sample.color = glm::make_vec3(&self.grid.colors[4 * slot..4 * slot + 3]);
+                        }
+                    }
+                }
+            }
+        }
+
+        let cell = match cell_index(center) {
+            Some(cell) => cell,
+            None => return sample,
+        };
+
+        // 3. Loop through the precomputed pairs and triples of the cell that can form a surface
+        let mut update = |patch: glm::Vec4| {
+            if patch.w > sample.distance {
+                sample.distance = patch.w;
+                sample.normal = patch.xyz();
+            }
+        };
+        for pair in self.patches.pairs(cell) {
+            let slots = [
+                self.grid.atom_slots[pair[0] as usize] as usize,
+                self.grid.atom_slots[pair[1] as usize] as usize,
+            ];
+            let (atom1, atom2) = (self.atom(slots[0]), self.atom(slots[1]));
+            if self.same_surface(&slots) && forms_patch(&atom1, position, r) && forms_patch(&atom2, position, r) {
+                update(toroidal_sdf(&atom1, &atom2, position, r));
+            }
+        }
+
+        for triple in self.patches.triples(cell) {
+            let slots = [
+                self.grid.atom_slots[triple[0] as usize] as usize,
+                self.grid.atom_slots[triple[1] as usize] as usize,
+                self.grid.atom_slots[triple[2] as usize] as usize,
+            ];
+            let (atom1, atom2, atom3) = (self.atom(slots[0]), self.atom(slots[1]), self.atom(slots[2]));
+            if self.same_surface(&slots)
+                && forms_patch(&atom1, position, r)
+                && forms_patch(&atom2, position, r)
+                && forms_patch(&atom3, position, r)
+            {
+                update(spherical_sdf(&atom1, &atom2, &atom3, position, r));
+            }
+        }
+
+        sample
+    }
+}
+
+///
+/// Jittered lattice of atoms of two surfaces, so that many pairs and triples form patches. Tests of the CPU reference
+/// and of the shader share It.
+///
+#[cfg(test)]
+pub struct Lattice {
+    pub atoms: Vec<glm::Vec4>,
+    pub colors: Vec<glm::Vec4>,
+    pub surfaces: Vec<u32>,
+}
+
+#[cfg(test)]
+impl Lattice {
+    pub fn new() -> Self {
+        let mut atoms = Vec::new();
+        for i in 0..64 {
+            let (x, y, z) = ((i % 4) as f32, (i / 4 % 4) as f32, (i / 16) as f32);
+            let jitter = (i as f32 * 12.9898).sin();
+            atoms.push(glm::vec4(
+                3.3 * x + jitter,
+                3.3 * y - jitter,
+                3.3 * z + 0.5 * jitter,
+                1.4 + 0.3 * jitter.abs(),
+            ));
+        }
+        let colors = (0..atoms.len()).map(|i| glm::vec4(i as f32 / 64.0, 0.5, 0.25, 1.0)).collect();
+        let surfaces = (0..atoms.len() as u32).map(|i| i / 32).collect();
+
+        Self { atoms, colors, surfaces }
+    }
+
+    ///
+    /// Returns positions in render space of the grid built of the lattice. The positions cover the lattice and Its
+    /// surroundings.
+    ///
+    pub fn sample_positions(&self, grid: &VoxelGridData) -> Vec<glm::Vec3> {
+        (0..1000)
+            .map(|i| glm::vec3((i % 10) as f32, (i / 10 % 10) as f32, (i / 100) as f32) * 1.3 - grid.origin - glm::vec3(1.1, 0.9, 1.3))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOLVENT_RADIUS: f32 = 1.4;
+
+    fn grid(atoms: &[glm::Vec4], surfaces: Vec<u32>) -> (VoxelGridData, PatchLists) {
+        let colors = (0..atoms.len()).map(|i| glm::vec4(i as f32, 0.5, 0.25, 1.0)).collect();
+        let grid = VoxelGridData::new(SOLVENT_RADIUS, atoms.to_vec(), colors, surfaces);
+        let patches = PatchLists::new(&grid, atoms, SOLVENT_RADIUS).unwrap();
+        (grid, patches)
+    }
+
+    fn sdf_at(grid: &VoxelGridData, patches: &PatchLists, separate_surfaces: bool, position: glm::Vec3) -> SdfSample {
+        let sdf = SurfaceSdf {
+            grid,
+            patches,
+            solvent_radius: SOLVENT_RADIUS,
+            separate_surfaces,
+        };
+        sdf.sdf(&(position - grid.origin))
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1.0e-4, "{} differs from {}", actual, expected);
+    }
+
+    #[test]
+    fn isolated_sphere() {
+        let atoms = [glm::vec4(1.0, 2.0, 3.0, 1.5)];
+        let (grid, patches) = grid(&atoms, vec![0]);
+
+        let outside = sdf_at(&grid, &patches, false, glm::vec3(3.5, 2.0, 3.0));
+        assert_close(outside.distance, -1.0);
+        assert_eq!(glm::normalize(&outside.normal), glm::vec3(1.0, 0.0, 0.0));
+        assert_eq!(outside.color, glm::vec3(0.0, 0.5, 0.25));
+
+        let inside = sdf_at(&grid, &patches, false, glm::vec3(1.0, 1.5, 3.0));
+        assert_close(inside.distance, 1.0);
+    }
+
+    #[test]
+    fn two_atom_torus() {
+        // Probes touching both atoms move on a circle of radius 2.1 around the x axis. Positions on the axis itself are
+        // singular for the Newton method.
+        let atoms = [glm::vec4(-2.0, 0.0, 0.0, 1.5), glm::vec4(2.0, 0.0, 0.0, 1.5)];
+        let (grid, patches) = grid(&atoms, vec![0, 1]);
+        let probe_circle = ((1.5f32 + SOLVENT_RADIUS).powi(2) - 4.0).sqrt();
+
+        for &y in [0.3, 0.7, 1.0].iter() {
+            let sample = sdf_at(&grid, &patches, false, glm::vec3(0.0, y, 0.0));
+            assert_close(sample.distance, probe_circle - y - SOLVENT_RADIUS);
+        }
+
+        // Atoms of different surfaces form no torus when surfaces are separated
+        let separated = sdf_at(&grid, &patches, true, glm::vec3(0.0, 0.7, 0.0));
+        assert_close(separated.distance, 1.5 - (4.0f32 + 0.49).sqrt());
+    }
+
+    #[test]
+    fn three_atom_reentrant_patch() {
+        // Probes touching all three atoms lie on the z axis. Positions in the plane of the atoms are singular for the
+        // Newton method.
+        let circumradius = 2.3f32;
+        let atoms: Vec<glm::Vec4> = (0..3)
+            .map(|i| {
+                let angle = i as f32 * 2.0 * std::f32::consts::PI / 3.0;
+                glm::vec4(circumradius * angle.cos(), circumradius * angle.sin(), 0.0, 1.5)
+            })
+            .collect();
+        let (grid, patches) = grid(&atoms, vec![0; 3]);
+        let probe_height = ((1.5f32 + SOLVENT_RADIUS).powi(2) - circumradius.powi(2)).sqrt();
+
+        for &z in [0.2, 0.5, 0.8].iter() {
+            let sample = sdf_at(&grid, &patches, false, glm::vec3(0.0, 0.0, z));
+            assert_close(sample.distance, probe_height - z - SOLVENT_RADIUS);
+        }
+    }
+
+    #[test]
+    fn patch_lists_match_all_patches() {
+        let lattice = Lattice::new();
+        let atoms = &lattice.atoms;
+        let grid = VoxelGridData::new(SOLVENT_RADIUS, atoms.clone(), lattice.colors.clone(), lattice.surfaces.clone());
+        let patches = PatchLists::new(&grid, atoms, SOLVENT_RADIUS).unwrap();
+
+        let sdf = SurfaceSdf {
+            grid: &grid,
+            patches: &patches,
+            solvent_radius: SOLVENT_RADIUS,
+            separate_surfaces: false,
+        };
+
+        // Surface found by testing every pair and triple of atoms close enough to the position. Positions are in render
+        // space like in the grid, because rounding may flip the predicates of patches.
+        let render_atoms: Vec<glm::Vec4> = atoms.iter().map(|atom| grid.to_render_atom(atom)).collect();
+        let reference = |p: &glm::Vec3| {
+            let near: Vec<&glm::Vec4> = render_atoms.iter().filter(|atom| forms_patch(atom, p, SOLVENT_RADIUS)).collect();
+            let mut d = render_atoms.iter().fold(-2.0f32, |d, atom| d.max(f(atom, p).w));
+            for i in 0..near.len() {
+                for j in i + 1..near.len() {
+                    d = d.max(toroidal_sdf(near[i], near[j], p, SOLVENT_RADIUS).w);
+                    for k in j + 1..near.len() {
+                        d = d.max(spherical_sdf(near[i], near[j], near[k], p, SOLVENT_RADIUS).w);
+                    }
+                }
+            }
+            d
+        };
+
+        let mut tested = 0;
+        for p in lattice.sample_positions(&grid) {
+            let expected = reference(&p);
+            // Only positions near the surface matter for ray marching
+            if !(-1.0..=0.05).contains(&expected) {
+                continue;
+            }
+
+            tested += 1;
+            let actual = sdf.sdf(&p).distance;
+            assert!(
+                (actual - expected).abs() < 1.0e-3,
+                "{} differs from {} at {:?}",
+                actual,
+                expected,
+                p
+            );
+        }
+        assert!(tested > 200);
+    }
+}
@@ -0,0 +1,402 @@
+//!
+//! Module containing the atom selection language used to compute the surface of only a part of a structure.
+//!
+//! Examples of selections:
+//! - `chain A`
+//! - `protein and not water`
+//! - `resid 10-20 35 and name CA CB`
+//! - `within 5 of resname LIG`
+//!
+
+use crate::molecule::Atom;
+use nalgebra_glm as glm;
+use std::collections::HashMap;
+
+/// Residue names of water molecules used by common file formats and force fields.
+const WATER_RESIDUES: [&str; 9] = ["HOH", "WAT", "H2O", "DOD", "SOL", "TIP", "TIP3", "TIP4", "SPC"];
+
+/// Residue names of standard amino acids.
+const PROTEIN_RESIDUES: [&str; 25] = [
+    "ALA", "ARG", "ASN", "ASP", "CYS", "GLN", "GLU", "GLY", "HIS", "ILE", "LEU", "LYS", "MET", "PHE", "PRO", "SER", "THR", "TRP", "TYR",
+    "VAL", "SEC", "PYL", "HID", "HIE", "HIP",
+];
+
+///
+/// Parsed selection expression.
+///
+#[derive(Clone, Debug)]
+enum Expression {
+    All,
+    None,
+    Water,
+    Protein,
+    Hetatm,
+    Chain(Vec<String>),
+    ResidueName(Vec<String>),
+    ResidueId(Vec<(i32, i32)>),
+    Element(Vec<String>),
+    Name(Vec<String>),
+    Within(f32, Box<Expression>),
+    Not(Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+}
+
+///
+/// Atom selection. Selections are written in a small language:
+///
+/// - `all`, `none`, `water`, `protein`, `hetatm`
+/// - `chain <ids>`, `resname <names>`, `element <symbols>`, `name <names>` matching any of the listed values
+/// - `resid <ranges>` where a range is a single number or `first-last` or `first:last`
+/// - `within <distance> of <selection>` selecting atoms closer than `distance` to any atom of `selection`
+/// - `not <selection>`, `<selection> and <selection>`, `<selection> or <selection>` and parentheses
+///
+/// `not` binds tighter than `and`, which binds tighter than `or`. Keywords are case insensitive, values are not.
+///
+#[derive(Clone, Debug)]
+pub struct Selection {
+    /// Text the selection was parsed from.
+    pub text: String,
+    expression: Expression,
+}
+
+impl Selection {
+    ///
+    /// Parses a selection. Returns a description of the problem if the text is not a valid selection.
+    ///
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let tokens = tokenize(text);
+        let mut parser = Parser { tokens, position: 0 };
+
+        let expression = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("unexpected `{}`", token));
+        }
+
+        Ok(Self {
+            text: text.to_string(),
+            expression,
+        })
+    }
+
+    ///
+    /// Returns for every atom whether It is selected.
+    ///
+    pub fn evaluate(&self, atoms: &[Atom]) -> Vec<bool> {
+        evaluate(&self.expression, atoms)
+    }
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+    text.replace('(', " ( ")
+        .replace(')', " ) ")
+        .split_whitespace()
+        .map(|token| token.to_string())
+        .collect()
+}
+
+fn is_keyword(token: &str) -> bool {
+    ["and", "or", "not", "(", ")", "of"].contains(&token.to_ascii_lowercase().as_str())
+}
+
+///
+/// Recursive descent parser of selections.
+///
+struct Parser {
+    tokens: Vec<String>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(|token| token.as_str())
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(token) => token.eq_ignore_ascii_case(keyword),
+            None => false,
+        }
+    }
+
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, keyword: &str) -> Result<(), String> {
+        match self.next() {
+            Some(ref token) if token.eq_ignore_ascii_case(keyword) => Ok(()),
+            Some(token) => Err(format!("expected `{}`, found `{}`", keyword, token)),
+            None => Err(format!("expected `{}`", keyword)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expression, String> {
+        let mut expression = self.and()?;
+        while self.peek_keyword("or") {
+            self.next();
+            expression = Expression::Or(Box::new(expression), Box::new(self.and()?));
+        }
+
+        Ok(expression)
+    }
+
+    fn and(&mut self) -> Result<Expression, String> {
+        let mut expression = self.not()?;
+        while self.peek_keyword("and") {
+            self.next();
+            expression = Expression::And(Box::new(expression), Box::new(self.not()?));
+        }
+
+        Ok(expression)
+    }
+
+    fn not(&mut self) -> Result<Expression, String> {
+        if self.peek_keyword("not") {
+            self.next();
+            return Ok(Expression::Not(Box::new(self.not()?)));
+        }
+
+        self.primary()
+    }
+
+    /// Reads values up to the next keyword. At least one value is required.
+    fn values(&mut self, keyword: &str) -> Result<Vec<String>, String> {
+        let mut values = Vec::new();
+        while let Some(token) = self.peek() {
+            if is_keyword(token) {
+                break;
+            }
+            values.push(token.to_string());
+            self.position += 1;
+        }
+
+        if values.is_empty() {
+            return Err(format!("`{}` requires at least one value", keyword));
+        }
+
+        Ok(values)
+    }
+
+    fn primary(&mut self) -> Result<Expression, String> {
+        let token = self.next().ok_or_else(|| "unexpected end of selection".to_string())?;
+
+        match token.to_ascii_lowercase().as_str() {
+            "(" => {
+                let expression = self.or()?;
+                self.expect(")")?;
+                Ok(expression)
+            }
+            "all" => Ok(Expression::All),
+            "none" => Ok(Expression::None),
+            "water" => Ok(Expression::Water),
+            "protein" => Ok(Expression::Protein),
+            "hetatm" => Ok(Expression::Hetatm),
+            "chain" => Ok(Expression::Chain(self.values("chain")?)),
+            "resname" => Ok(Expression::ResidueName(self.values("resname")?)),
+            "element" => Ok(Expression::Element(self.values("element")?)),
+            "name" => Ok(Expression::Name(self.values("name")?)),
+            "resid" => {
+                let ranges = self
+                    .values("resid")?
+                    .iter()
+                    .map(|range| parse_range(range))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Expression::ResidueId(ranges))
+            }
+            "within" => {
+                let distance = self.next().ok_or_else(|| "`within` requires a distance".to_string())?;
+                let distance = distance.parse::<f32>().map_err(|_| format!("invalid distance `{}`", distance))?;
+                self.expect("of")?;
+                Ok(Expression::Within(distance, Box::new(self.not()?)))
+            }
+            _ => Err(format!("unknown keyword `{}`", token)),
+        }
+    }
+}
+
+///
+/// Parses a residue range `first-last`, `first:last` or a single residue. Negative residue numbers are allowed.
+///
+fn parse_range(range: &str) -> Result<(i32, i32), String> {
+    let invalid = || format!("invalid residue range `{}`", range);
+
+    // Skip the sign of the first number when looking for the separator
+    let separator = range
+        .char_indices()
+        .skip(1)
+        .find(|&(_, c)| c == '-' || c == ':')
+        .map(|(index, _)| index);
+
+    match separator {
+        Some(index) => {
+            let first = range[..index].parse::<i32>().map_err(|_| invalid())?;
+            let last = range[index + 1..].parse::<i32>().map_err(|_| invalid())?;
+            Ok((first.min(last), first.max(last)))
+        }
+        None => {
+            let residue = range.parse::<i32>().map_err(|_| invalid())?;
+            Ok((residue, residue))
+        }
+    }
+}
+
+fn evaluate(expression: &Expression, atoms: &[Atom]) -> Vec<bool> {
+    let matches = |predicate: &dyn Fn(&Atom) -> bool| atoms.iter().map(predicate).collect();
+    let contains = |values: &[String], value: &str| values.iter().any(|v| v == value);
+
+    match expression {
+        Expression::All => vec![true; atoms.len()],
+        Expression::None => vec![false; atoms.len()],
+        Expression::Water => matches(&|atom| WATER_RESIDUES.contains(&atom.residue_name.to_ascii_uppercase().as_str())),
+        Expression::Protein => matches(&|atom| PROTEIN_RESIDUES.contains(&atom.residue_name.to_ascii_uppercase().as_str())),
+        Expression::Hetatm => matches(&|atom| atom.hetatm),
+        Expression::Chain(chains) => matches(&|atom| contains(chains, &atom.chain)),
+        Expression::ResidueName(names) => matches(&|atom| contains(names, &atom.residue_name)),
+        Expression::Element(elements) => matches(&|atom| elements.iter().any(|element| element.eq_ignore_ascii_case(&atom.element))),
+        Expression::Name(names) => matches(&|atom| contains(names, &atom.name)),
+        Expression::ResidueId(ranges) => matches(&|atom| {
+            ranges
+                .iter()
+                .any(|&(first, last)| atom.residue_id >= first && atom.residue_id <= last)
+        }),
+        Expression::Within(distance, expression) => within(*distance, &evaluate(expression, atoms), atoms),
+        Expression::Not(expression) => evaluate(expression, atoms).iter().map(|selected| !selected).collect(),
+        Expression::And(left, right) => evaluate(left, atoms)
+            .iter()
+            .zip(evaluate(right, atoms).iter())
+            .map(|(left, right)| *left && *right)
+            .collect(),
+        Expression::Or(left, right) => evaluate(left, atoms)
+            .iter()
+            .zip(evaluate(right, atoms).iter())
+            .map(|(left, right)| *left || *right)
+            .collect(),
+    }
+}
+
+///
+/// Selects atoms closer than `distance` to any of the `selected` atoms.
+/// The selected atoms are hashed into cells of size `distance`, so only the 3x3x3 neighbourhood of every atom is searched.
+///
+fn within(distance: f32, selected: &[bool], atoms: &[Atom]) -> Vec<bool> {
+    let cell_size = distance.max(0.1);
+    let cell = |position: &glm::Vec3| {
+        (
+            (position.x / cell_size).floor() as i32,
+            (position.y / cell_size).floor() as i32,
+            (position.z / cell_size).floor() as i32,
+        )
+    };
+
+    let mut cells: HashMap<(i32, i32, i32), Vec<glm::Vec3>> = HashMap::new();
+    for (atom, _) in atoms.iter().zip(selected.iter()).filter(|(_, selected)| **selected) {
+        cells.entry(cell(&atom.position)).or_default().push(atom.position);
+    }
+
+    atoms
+        .iter()
+        .map(|atom| {
+            let (x, y, z) = cell(&atom.position);
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        if let Some(positions) = cells.get(&(x + dx, y + dy, z + dz)) {
+                            if positions.iter().any(|position| glm::distance(position, &atom.position) <= distance) {
+                                return true;
+                            }
+                        }
+                    }
+                }
+            }
+
+            false
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atoms() -> Vec<Atom> {
+        let atom = |chain: &str, residue_name: &str, residue_id, name: &str, x| Atom {
+            position: glm::vec3(x, 0.0, 0.0),
+            element: name[..1].to_string(),
+            name: name.to_string(),
+            residue_name: residue_name.to_string(),
+            residue_id,
+            chain: chain.to_string(),
+            hetatm: residue_name == "LIG" || residue_name == "HOH",
+            ..Atom::default()
+        };
+
+        vec![
+            atom("A", "ALA", -3, "N", 0.0),
+            atom("A", "ALA", -3, "CA", 1.5),
+            atom("A", "GLY", 1, "CA", 3.0),
+            atom("B", "SER", 10, "OG", 4.5),
+            atom("B", "LIG", 20, "C1", 6.0),
+            atom("B", "HOH", 30, "O", 20.0),
+        ]
+    }
+
+    fn selected(text: &str) -> Vec<usize> {
+        let selection = Selection::parse(text).unwrap_or_else(|e| panic!("`{}` is not parsed: {}", text, e));
+        selection
+            .evaluate(&atoms())
+            .iter()
+            .enumerate()
+            .filter(|(_, selected)| **selected)
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    #[test]
+    fn operators_follow_precedence() {
+        assert_eq!(selected("chain A or chain B and name OG"), vec![0, 1, 2, 3]);
+        assert_eq!(selected("(chain A or chain B) and name OG"), vec![3]);
+        assert_eq!(selected("not chain A and not water"), vec![3, 4]);
+        assert_eq!(selected("not (chain A and protein)"), vec![3, 4, 5]);
+        assert_eq!(selected("not chain A and protein"), vec![3]);
+        assert_eq!(selected("not not hetatm"), vec![4, 5]);
+        assert_eq!(selected("protein AND NOT ((name CA))"), vec![0, 3]);
+        assert_eq!(selected("all and not none"), vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn residue_ranges_allow_negative_ids() {
+        assert_eq!(selected("resid -3"), vec![0, 1]);
+        assert_eq!(selected("resid -5-1"), vec![0, 1, 2]);
+        assert_eq!(selected("resid -5--1 20"), vec![0, 1, 4]);
+        assert_eq!(selected("resid 10:-3"), vec![0, 1, 2, 3]);
+        assert_eq!(selected("resid 11-19"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn within_selects_neighbours() {
+        assert_eq!(selected("within 1.6 of (resname LIG)"), vec![3, 4]);
+        assert_eq!(selected("within 3 of resname LIG or chain A"), vec![0, 1, 2, 3, 4]);
+        assert_eq!(selected("within 3 of (resname LIG or water)"), vec![2, 3, 4, 5]);
+        assert_eq!(selected("not within 3.1 of name N"), vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn malformed_selections_are_described() {
+        let error = |text: &str| Selection::parse(text).err().unwrap_or_else(|| panic!("`{}` is parsed", text));
+
+        assert_eq!(error(""), "unexpected end of selection");
+        assert_eq!(error("chain A and"), "unexpected end of selection");
+        assert_eq!(error("chain"), "`chain` requires at least one value");
+        assert_eq!(error("name and chain A"), "`name` requires at least one value");
+        assert_eq!(error("(chain A"), "expected `)`");
+        assert_eq!(error("chain A)"), "unexpected `)`");
+        assert_eq!(error("residue 10"), "unknown keyword `residue`");
+        assert_eq!(error("resid 1-x"), "invalid residue range `1-x`");
+        assert_eq!(error("within"), "`within` requires a distance");
+        assert_eq!(error("within five of water"), "invalid distance `five`");
+        assert_eq!(error("within 5 water"), "expected `of`, found `water`");
+    }
+}
@@ -4,57 +4,481 @@
 
 
 use crate::application::*;
+use crate::molecule::AltLocPolicy;
+use crate::scene::MOLECULE_COLORS;
+use nalgebra_glm as glm;
 
 use iced_wgpu::Renderer;
-use iced_winit::{slider, Column, Container, Element, Length, Slider, Space, Text};
+use iced_winit::{
+    button, scrollable, slider, text_input, Button, Checkbox, Column, Container, Element, Length, ProgressBar, Radio, Row, Scrollable,
+    Slider, Space, Text, TextInput,
+};
 
 /// Events that can be fired by the UI
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum Message {
     /// Called when solvent radius is adjusted
     SolventRadiusChanged(f32),
-    /// Called when number of maximum neighbours is adjusted
-    MaxNeighboursChanged(f32),
     /// Called when maximum number of steps per frame is adjusted
     MaxStepsChanged(f32),
+    /// Called when user-supplied radii are discarded in favour of file or element radii
+    DefaultRadiiPressed,
+    /// Called when trajectory playback is started or paused
+    PlayPressed,
+    /// Called when trajectory is stepped by the given number of frames
+    StepPressed(i32),
+    /// Called when trajectory frame is scrubbed
+    FrameChanged(f32),
+    /// Called when a model of an ensemble is added to or removed from the overlay
+    OverlayToggled(usize, bool),
+    /// Called when the policy for alternate conformers is changed
+    AltLocPolicyChanged(AltLocPolicy),
+    /// Called when a biological assembly or the asymmetric unit is chosen
+    AssemblyChanged(Option<usize>),
+    /// Called when symmetry mates of a crystal structure are shown or hidden
+    SymmetryMatesToggled(bool),
+    /// Called when the distance of shown symmetry mates from the asymmetric unit is adjusted
+    SymmetryRadiusChanged(f32),
+    /// Called when the shown error is dismissed
+    ErrorDismissed,
+    /// Called when the shown warnings are dismissed
+    WarningsDismissed,
+    /// Called when text of the atom selection is edited
+    SelectionChanged(String),
+    /// Called when the atom selection is confirmed
+    SelectionSubmitted,
+    /// Called when a molecule is chosen as the one the other options apply to
+    ActiveMoleculeChanged(usize),
+    /// Called when a molecule is shown or hidden
+    MoleculeVisibilityToggled(usize, bool),
+    /// Called when a molecule is removed from the scene
+    MoleculeRemoved(usize),
+    /// Called when the active molecule is switched to the next color
+    MoleculeColorPressed,
+    /// Called when the translation of the active molecule along an axis is adjusted
+    MoleculeTranslationChanged(usize, f32),
+    /// Called when the rotation of the active molecule around an axis is adjusted
+    MoleculeRotationChanged(usize, f32),
+    /// Called when surfaces of molecules are separated or blended together
+    SeparateSurfacesToggled(bool),
+    /// Called when dropped files start to be added to the scene or to replace the active molecule
+    AddDroppedFilesToggled(bool),
 }
 /// State of the user interface 
 pub struct UserInterface {
     solvent_radius_slider: slider::State,
-    max_neighbours_slider: slider::State,
     max_steps_slider: slider::State,
+    default_radii_button: button::State,
+    dismiss_error_button: button::State,
+    dismiss_warnings_button: button::State,
+    step_backward_button: button::State,
+    play_button: button::State,
+    step_forward_button: button::State,
+    frame_slider: slider::State,
+    symmetry_radius_slider: slider::State,
+    overlay_scrollable: scrollable::State,
+    selection_input: text_input::State,
+    selection_text: String,
+    selection_error: Option<String>,
+    remove_molecule_buttons: Vec<button::State>,
+    molecule_color_button: button::State,
+    translation_sliders: [slider::State; 3],
+    rotation_sliders: [slider::State; 3],
 }
 
 impl UserInterface {
     /// Initializes default state of the UI
-    pub fn new() -> Self {
+    pub fn new(application: &Application) -> Self {
         Self {
             solvent_radius_slider: iced_wgpu::slider::State::new(),
-            max_neighbours_slider: iced_wgpu::slider::State::new(),
             max_steps_slider: iced_wgpu::slider::State::new(),
+            default_radii_button: iced_wgpu::button::State::new(),
+            dismiss_error_button: iced_wgpu::button::State::new(),
+            dismiss_warnings_button: iced_wgpu::button::State::new(),
+            step_backward_button: iced_wgpu::button::State::new(),
+            play_button: iced_wgpu::button::State::new(),
+            step_forward_button: iced_wgpu::button::State::new(),
+            frame_slider: iced_wgpu::slider::State::new(),
+            symmetry_radius_slider: iced_wgpu::slider::State::new(),
+            overlay_scrollable: iced_wgpu::scrollable::State::new(),
+            selection_input: iced_wgpu::text_input::State::new(),
+            selection_text: application.selection().map_or(String::new(), |selection| selection.text.clone()),
+            selection_error: None,
+            remove_molecule_buttons: Vec::new(),
+            molecule_color_button: iced_wgpu::button::State::new(),
+            translation_sliders: [
+                iced_wgpu::slider::State::new(),
+                iced_wgpu::slider::State::new(),
+                iced_wgpu::slider::State::new(),
+            ],
+            rotation_sliders: [
+                iced_wgpu::slider::State::new(),
+                iced_wgpu::slider::State::new(),
+                iced_wgpu::slider::State::new(),
+            ],
         }
     }
 
     /// Processes a fired event
-    pub fn update(&self, message: Message, application: &mut Application) {
+    pub fn update(&mut self, message: Message, application: &mut Application) {
         match message {
             Message::SolventRadiusChanged(solvent_radius) => {
                 application.set_solvent_radius(solvent_radius);
             }
-            Message::MaxNeighboursChanged(max_neighbours) => {
-                application.set_max_neighbours(max_neighbours.round() as i32);
-            }
             Message::MaxStepsChanged(max_steps) => {
                 application.set_max_steps(max_steps.round() as i32);
             }
+            Message::DefaultRadiiPressed => {
+                application.set_radii_table(None);
+            }
+            Message::PlayPressed => {
+                application.set_playing(!application.playing());
+            }
+            Message::StepPressed(step) => {
+                let frame_count = application.frame_count() as i32;
+                let frame = (application.frame() as i32 + step).rem_euclid(frame_count.max(1));
+
+                application.set_playing(false);
+                application.set_frame(frame as usize);
+            }
+            Message::FrameChanged(frame) => {
+                application.set_frame(frame.round() as usize);
+            }
+            Message::OverlayToggled(model, overlaid) => {
+                application.set_overlaid(model, overlaid);
+            }
+            Message::AltLocPolicyChanged(alt_loc_policy) => {
+                application.set_alt_loc_policy(alt_loc_policy);
+            }
+            Message::AssemblyChanged(assembly) => {
+                application.set_assembly(assembly);
+            }
+            Message::SymmetryMatesToggled(show_symmetry_mates) => {
+                application.set_show_symmetry_mates(show_symmetry_mates);
+            }
+            Message::SymmetryRadiusChanged(symmetry_radius) => {
+                application.set_symmetry_radius(symmetry_radius);
+            }
+            Message::ErrorDismissed => {
+                application.set_error(None);
+            }
+            Message::WarningsDismissed => {
+                application.dismiss_warnings();
+            }
+            Message::SelectionChanged(text) => {
+                self.selection_text = text;
+            }
+            Message::SelectionSubmitted => {
+                self.selection_error = application.set_selection(&self.selection_text).err();
+            }
+            Message::ActiveMoleculeChanged(index) => {
+                application.set_active_molecule(index);
+            }
+            Message::MoleculeVisibilityToggled(index, visible) => {
+                application.set_molecule_visible(index, visible);
+            }
+            Message::MoleculeRemoved(index) => {
+                application.remove_molecule(index);
+            }
+            Message::MoleculeColorPressed => {
+                let index = application.active_molecule();
+                if let Some(molecule) = application.molecules().get(index) {
+                    let next = MOLECULE_COLORS
+                        .iter()
+                        .position(|(_, color)| glm::Vec4::from(*color) == molecule.color)
+                        .map_or(0, |current| (current + 1) % MOLECULE_COLORS.len());
+                    application.set_molecule_color(index, glm::Vec4::from(MOLECULE_COLORS[next].1));
+                }
+            }
+            Message::MoleculeTranslationChanged(axis, value) => {
+                let index = application.active_molecule();
+                if let Some(molecule) = application.molecules().get(index) {
+                    let (mut translation, rotation) = (molecule.translation, molecule.rotation);
+                    translation[axis] = value;
+                    application.set_molecule_transform(index, translation, rotation);
+                }
+            }
+            Message::MoleculeRotationChanged(axis, value) => {
+                let index = application.active_molecule();
+                if let Some(molecule) = application.molecules().get(index) {
+                    let (translation, mut rotation) = (molecule.translation, molecule.rotation);
+                    rotation[axis] = value;
+                    application.set_molecule_transform(index, translation, rotation);
+                }
+            }
+            Message::SeparateSurfacesToggled(separate_surfaces) => {
+                application.set_separate_surfaces(separate_surfaces);
+            }
+            Message::AddDroppedFilesToggled(add_dropped_files) => {
+                application.set_add_dropped_files(add_dropped_files);
+            }
         };
     }
 
     /// Returns the UI based on a state
     pub fn view<'a>(&'a mut self, application: &Application) -> Element<'a, Message, Renderer> {
+        let mut status = Column::new();
+        if let Some((path, progress)) = application.loading() {
+            let name = path.file_name().unwrap_or_else(|| path.as_os_str()).to_string_lossy();
+            status = status
+                .push(Space::new(Length::Fill, Length::Units(12)))
+                .push(Text::new(format!("Loading {}", name)).size(16))
+                .push(ProgressBar::new(0.0..=1.0, progress).height(Length::Units(8)));
+        }
+        if let Some(error) = application.error() {
+            status = status
+                .push(Space::new(Length::Fill, Length::Units(12)))
+                .push(Text::new(error).size(16).color([0.8, 0.1, 0.1]))
+                .push(Button::new(&mut self.dismiss_error_button, Text::new("Dismiss").size(16)).on_press(Message::ErrorDismissed));
+        }
+        if !application.warnings().is_empty() {
+            status = status.push(Space::new(Length::Fill, Length::Units(12)));
+            for warning in application.warnings() {
+                status = status.push(Text::new(warning.as_str()).size(16).color([0.75, 0.45, 0.0]));
+            }
+            status = status
+                .push(Button::new(&mut self.dismiss_warnings_button, Text::new("Dismiss").size(16)).on_press(Message::WarningsDismissed));
+        }
+
+        let mut molecules = Column::new();
+        if !application.molecules().is_empty() {
+            molecules = molecules
+                .push(Space::new(Length::Fill, Length::Units(12)))
+                .push(Text::new("Molecules:").size(18));
+
+            self.remove_molecule_buttons
+                .resize_with(application.molecules().len(), button::State::new);
+            for ((index, molecule), remove_button) in application
+                .molecules()
+                .iter()
+                .enumerate()
+                .zip(self.remove_molecule_buttons.iter_mut())
+            {
+                molecules = molecules
+                    .push(Radio::new(
+                        index,
+                        molecule.name(),
+                        Some(application.active_molecule()),
+                        Message::ActiveMoleculeChanged,
+                    ))
+                    .push(
+                        Row::new()
+                            .spacing(4)
+                            .push(Checkbox::new(molecule.visible, "Visible", move |visible| {
+                                Message::MoleculeVisibilityToggled(index, visible)
+                            }))
+                            .push(Button::new(remove_button, Text::new("Remove").size(16)).on_press(Message::MoleculeRemoved(index))),
+                    );
+            }
+
+            if let Some(molecule) = application.molecules().get(application.active_molecule()) {
+                let color_name = MOLECULE_COLORS
+                    .iter()
+                    .find(|(_, color)| glm::Vec4::from(*color) == molecule.color)
+                    .map_or("Custom", |(name, _)| *name);
+                molecules = molecules
+                    .push(Space::new(Length::Fill, Length::Units(12)))
+                    .push(
+                        Button::new(
+                            &mut self.molecule_color_button,
+                            Text::new(format!("Color: {}", color_name)).size(16),
+                        )
+                        .on_press(Message::MoleculeColorPressed),
+                    )
+                    .push(
+                        Text::new(format!(
+                            "Translation: {:.0}, {:.0}, {:.0}",
+                            molecule.translation.x, molecule.translation.y, molecule.translation.z
+                        ))
+                        .size(16),
+                    );
+                for (axis, slider) in self.translation_sliders.iter_mut().enumerate() {
+                    molecules = molecules.push(Slider::new(slider, -50.0..=50.0, molecule.translation[axis], move |value| {
+                        Message::MoleculeTranslationChanged(axis, value)
+                    }));
+                }
+                molecules = molecules.push(
+                    Text::new(format!(
+                        "Rotation: {:.0}, {:.0}, {:.0}",
+                        molecule.rotation.x, molecule.rotation.y, molecule.rotation.z
+                    ))
+                    .size(16),
+                );
+                for (axis, slider) in self.rotation_sliders.iter_mut().enumerate() {
+                    molecules = molecules.push(Slider::new(slider, -180.0..=180.0, molecule.rotation[axis], move |value| {
+                        Message::MoleculeRotationChanged(axis, value)
+                    }));
+                }
+                // Centre in the coordinates of the structure file, which are kept instead of recentering the atoms
+                if let Some(centre) = application.view_centre() {
+                    molecules =
+                        molecules.push(Text::new(format!("View centre: {:.1}, {:.1}, {:.1}", centre.x, centre.y, centre.z)).size(16));
+                }
+            }
+
+            molecules = molecules.push(Checkbox::new(
+                application.separate_surfaces(),
+                "Separate surfaces",
+                Message::SeparateSurfacesToggled,
+            ));
+        }
+        molecules = molecules.push(Checkbox::new(
+            application.add_dropped_files(),
+            "Add dropped files",
+            Message::AddDroppedFilesToggled,
+        ));
+
+        let mut radii = Column::new().push(Space::new(Length::Fill, Length::Units(12)));
+        if let Some(radii_table) = application.radii_table() {
+            radii = radii
+                .push(Text::new(format!("Radii: {}", radii_table.name)).size(18))
+                .push(Text::new(format!("Unmatched atoms: {}", application.unmatched_atoms())).size(18))
+                .push(
+                    Button::new(&mut self.default_radii_button, Text::new("Use default radii").size(18))
+                        .on_press(Message::DefaultRadiiPressed),
+                );
+        } else {
+            let source = if application.has_file_radii() { "file" } else { "element" };
+            radii = radii.push(Text::new(format!("Radii: {}", source)).size(18));
+        }
+
+        let mut alt_locs = Column::new();
+        if !application.alt_locs().is_empty() || application.alt_loc_policy() != AltLocPolicy::HighestOccupancy {
+            let mut policies = vec![AltLocPolicy::HighestOccupancy, AltLocPolicy::All];
+            policies.extend(application.alt_locs().iter().map(|&letter| AltLocPolicy::Letter(letter)));
+
+            alt_locs = alt_locs
+                .push(Space::new(Length::Fill, Length::Units(12)))
+                .push(Text::new("Alternate locations:").size(18));
+            for policy in policies {
+                alt_locs = alt_locs.push(Radio::new(
+                    policy,
+                    policy.to_string(),
+                    Some(application.alt_loc_policy()),
+                    Message::AltLocPolicyChanged,
+                ));
+            }
+            alt_locs = alt_locs.push(Text::new(format!("Discarded atoms: {}", application.discarded_atoms())).size(16));
+        }
+
+        let mut assemblies = Column::new();
+        if !application.assemblies().is_empty() {
+            assemblies = assemblies
+                .push(Space::new(Length::Fill, Length::Units(12)))
+                .push(Text::new("Assembly:").size(18))
+                .push(Radio::new(
+                    None,
+                    "Asymmetric unit",
+                    Some(application.assembly()),
+                    Message::AssemblyChanged,
+                ));
+            for (index, assembly) in application.assemblies().iter().enumerate() {
+                assemblies = assemblies.push(Radio::new(
+                    Some(index),
+                    format!("Assembly {}", assembly.name),
+                    Some(application.assembly()),
+                    Message::AssemblyChanged,
+                ));
+            }
+        }
+
+        let mut crystal = Column::new();
+        if let Some(unit_cell) = application.crystal() {
+            crystal = crystal
+                .push(Space::new(Length::Fill, Length::Units(12)))
+                .push(Text::new(format!("Space group: {}", unit_cell.space_group)).size(18));
+            if unit_cell.operations.is_empty() {
+                crystal = crystal.push(Text::new("Space group is not supported").size(16));
+            } else {
+                crystal = crystal
+                    .push(Checkbox::new(
+                        application.show_symmetry_mates(),
+                        "Show symmetry mates",
+                        Message::SymmetryMatesToggled,
+                    ))
+                    .push(Text::new(format!("Symmetry radius: {:.1}", application.symmetry_radius())).size(18))
+                    .push(Slider::new(
+                        &mut self.symmetry_radius_slider,
+                        0.0..=20.0,
+                        application.symmetry_radius(),
+                        Message::SymmetryRadiusChanged,
+                    ));
+                if application.show_symmetry_mates() {
+                    crystal = crystal.push(Text::new(format!("Symmetry mates: {}", application.symmetry_mates())).size(16));
+                }
+            }
+        }
+
+        let mut selection = Column::new()
+            .push(Space::new(Length::Fill, Length::Units(12)))
+            .push(Text::new("Selection:").size(18))
+            .push(
+                TextInput::new(&mut self.selection_input, "all", &self.selection_text, Message::SelectionChanged)
+                    .on_submit(Message::SelectionSubmitted)
+                    .padding(4)
+                    .size(18),
+            );
+        if let Some(error) = self.selection_error.as_ref() {
+            selection = selection.push(Text::new(error.as_str()).size(16));
+        } else if application.atom_count() > 0 {
+            selection = selection.push(
+                Text::new(format!(
+                    "Selected atoms: {} / {}",
+                    application.selected_atoms(),
+                    application.atom_count()
+                ))
+                .size(16),
+            );
+        }
+
+        let mut playback = Column::new();
+        if application.frame_count() > 1 {
+            let play_label = if application.playing() { "Pause" } else { "Play" };
+            let frame_label = if application.is_ensemble() { "Model" } else { "Frame" };
+
+            playback = playback
+                .push(Space::new(Length::Fill, Length::Units(12)))
+                .push(
+                    Text::new(format!(
+                        "{}: {} / {}",
+                        frame_label,
+                        application.frame() + 1,
+                        application.frame_count()
+                    ))
+                    .size(18),
+                )
+                .push(Slider::new(
+                    &mut self.frame_slider,
+                    0.0..=(application.frame_count() - 1) as f32,
+                    application.frame() as f32,
+                    move |n| Message::FrameChanged(n),
+                ))
+                .push(
+                    Row::new()
+                        .spacing(4)
+                        .push(Button::new(&mut self.step_backward_button, Text::new("<").size(18)).on_press(Message::StepPressed(-1)))
+                        .push(Button::new(&mut self.play_button, Text::new(play_label).size(18)).on_press(Message::PlayPressed))
+                        .push(Button::new(&mut self.step_forward_button, Text::new(">").size(18)).on_press(Message::StepPressed(1))),
+                );
+
+            if application.is_ensemble() {
+                let mut overlay = Scrollable::new(&mut self.overlay_scrollable).height(Length::Units(150));
+                for model in 0..application.frame_count() {
+                    overlay = overlay.push(Checkbox::new(
+                        application.is_overlaid(model),
+                        format!("Overlay model {}", model + 1),
+                        move |overlaid| Message::OverlayToggled(model, overlaid),
+                    ));
+                }
+
+                playback = playback.push(Space::new(Length::Fill, Length::Units(12))).push(overlay);
+            }
+        }
+
         Container::new(
             Column::new()
                 .push(Text::new("Options").size(24))
+                .push(status)
                 .push(Space::new(Length::Fill, Length::Units(12)))
                 .push(Text::new(format!("Solvent radius: {:.2}", application.solvent_radius())).size(18))
                 .push(Slider::new(
@@ -63,13 +487,6 @@ impl UserInterface {
                     application.solvent_radius(),
                     move |n| Message::SolventRadiusChanged(n),
                 ))
-                .push(Text::new("Max neighbours: ".to_string() + &application.max_neighbours().to_string()).size(18))
-                .push(Slider::new(
-                    &mut self.max_neighbours_slider,
-                    1.0..=45.0,
-                    application.max_neighbours() as f32,
-                    move |n| Message::MaxNeighboursChanged(n),
-                ))
                 .push(Text::new(format!("Max steps per frame: {}", application.max_steps() as u32)).size(18))
                 .push(Slider::new(
                     &mut self.max_steps_slider,
@@ -77,6 +494,13 @@ impl UserInterface {
                     application.max_steps() as f32,
                     move |n| Message::MaxStepsChanged(n),
                 ))
+                .push(molecules)
+                .push(radii)
+                .push(alt_locs)
+                .push(assemblies)
+                .push(crystal)
+                .push(selection)
+                .push(playback)
                 .padding(12),
         )
         .width(Length::Units(200))
@@ -15,14 +15,57 @@ pub trait RoundToMultiple {
 impl RoundToMultiple for f32 {
     /// Takes a floating number and rounds It to a `multiple`. The rounding is away from the zero.
     fn round_to_multiple(&self, multiple: i32) -> f32 {
-        let number = *self;
+        // Rounded in `f64`, so that huge numbers do not overflow
+        let multiple = f64::from(multiple);
+        let rounded = (f64::from(self.abs()).ceil() / multiple).ceil() * multiple;
 
-        if number < 0.0 {
-            let number = (number.floor() as i32) * -1;
-            -(((number + multiple - 1) / multiple) * multiple) as f32
+        if *self < 0.0 {
+            -rounded as f32
         } else {
-            let number = number.ceil() as i32;
-            (((number + multiple - 1) / multiple) * multiple) as f32
+            rounded as f32
         }
     }
 }
+
+///
+/// Extends a range of changed elements by the given index.
+///
+pub fn mark(range: &mut std::ops::Range<usize>, index: usize) {
+    if range.start == range.end {
+        *range = index..index + 1;
+    } else {
+        range.start = range.start.min(index);
+        range.end = range.end.max(index + 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_to_multiple_keeps_multiples() {
+        assert_eq!(0.0.round_to_multiple(4), 0.0);
+        assert_eq!(8.0.round_to_multiple(4), 8.0);
+        assert_eq!((-8.0).round_to_multiple(4), -8.0);
+        assert_eq!((-4.0).round_to_multiple(8), -8.0);
+        assert_eq!(16.0.round_to_multiple(8), 16.0);
+    }
+
+    #[test]
+    fn round_to_multiple_rounds_away_from_zero() {
+        assert_eq!(0.5.round_to_multiple(4), 4.0);
+        assert_eq!((-0.5).round_to_multiple(4), -4.0);
+        assert_eq!(4.2.round_to_multiple(4), 8.0);
+        assert_eq!((-4.2).round_to_multiple(4), -8.0);
+        assert_eq!(7.9.round_to_multiple(8), 8.0);
+        assert_eq!(3.0.round_to_multiple(1), 3.0);
+    }
+
+    #[test]
+    fn round_to_multiple_does_not_overflow() {
+        assert_eq!(1e12.round_to_multiple(4), 1e12);
+        assert_eq!(std::f32::MAX.round_to_multiple(4), std::f32::MAX);
+        assert_eq!((-std::f32::MAX).round_to_multiple(4), -std::f32::MAX);
+    }
+}